@@ -0,0 +1,149 @@
+use std::ffi::c_void;
+use std::slice;
+
+use yat_rack::export_plugin;
+use yat_rack::plugin::abi::{
+    ControlDescriptor, ModuleDescriptor, PluginDescriptor, PortDescriptor, UNIT_GATE, UNIT_NONE,
+};
+use yat_rack::types::SampleType;
+
+/// Samples its signal input whenever the gate input rises, and holds it until the next rise
+struct SampleHold {
+    held: SampleType,
+    gate_high: bool,
+}
+
+unsafe extern "C" fn sample_hold_create(_sample_rate: SampleType) -> *mut c_void {
+    Box::into_raw(Box::new(SampleHold {
+        held: 0.0,
+        gate_high: false,
+    })) as *mut c_void
+}
+
+unsafe extern "C" fn sample_hold_destroy(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut SampleHold));
+}
+
+/// Holding a value doesn't depend on time
+unsafe extern "C" fn sample_hold_set_sample_rate(_instance: *mut c_void, _rate: SampleType) {}
+
+unsafe extern "C" fn sample_hold_process(
+    instance: *mut c_void,
+    inputs: *const SampleType,
+    outputs: *mut SampleType,
+    frames: usize,
+) {
+    let sample_hold = &mut *(instance as *mut SampleHold);
+    let (signal, gate) = slice::from_raw_parts(inputs, 2 * frames).split_at(frames);
+    let result = slice::from_raw_parts_mut(outputs, frames);
+
+    for frame in 0..frames {
+        let gate_high = gate[frame] > 0.5;
+        if gate_high && !sample_hold.gate_high {
+            sample_hold.held = signal[frame];
+        }
+        sample_hold.gate_high = gate_high;
+        result[frame] = sample_hold.held;
+    }
+}
+
+/// A gate which is switched on and off with the 't' key
+struct Toggle {
+    gate: SampleType,
+}
+
+unsafe extern "C" fn toggle_create() -> *mut c_void {
+    Box::into_raw(Box::new(Toggle { gate: 0.0 })) as *mut c_void
+}
+
+unsafe extern "C" fn toggle_destroy(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut Toggle));
+}
+
+unsafe extern "C" fn toggle_get_value(instance: *mut c_void, _port: usize) -> SampleType {
+    (*(instance as *mut Toggle)).gate
+}
+
+/// The gate is either off or on, so values are rounded to 0 or 1
+unsafe extern "C" fn toggle_set_value(instance: *mut c_void, _port: usize, value: SampleType) {
+    (*(instance as *mut Toggle)).gate = if value >= 0.5 { 1.0 } else { 0.0 };
+}
+
+unsafe extern "C" fn toggle_recv_key(instance: *mut c_void, key: u32) {
+    let toggle = &mut *(instance as *mut Toggle);
+    if key == 't' as u32 {
+        toggle.gate = 1.0 - toggle.gate;
+    }
+}
+
+static SAMPLE_HOLD_IN_PORTS: [PortDescriptor; 2] = [
+    PortDescriptor {
+        name: c"signal".as_ptr(),
+        lower_range: SampleType::MIN,
+        upper_range: SampleType::MAX,
+        default: 0.0,
+        unit: UNIT_NONE,
+        description: c"The signal to sample".as_ptr(),
+    },
+    PortDescriptor {
+        name: c"gate".as_ptr(),
+        lower_range: 0.0,
+        upper_range: 1.0,
+        default: 0.0,
+        unit: UNIT_GATE,
+        description: c"Samples the signal when it rises above 0.5".as_ptr(),
+    },
+];
+
+static SAMPLE_HOLD_OUT_PORTS: [PortDescriptor; 1] = [PortDescriptor {
+    name: c"result".as_ptr(),
+    lower_range: SampleType::MIN,
+    upper_range: SampleType::MAX,
+    default: 0.0,
+    unit: UNIT_NONE,
+    description: c"The last sampled signal".as_ptr(),
+}];
+
+static TOGGLE_OUT_PORTS: [PortDescriptor; 1] = [PortDescriptor {
+    name: c"gate".as_ptr(),
+    lower_range: 0.0,
+    upper_range: 1.0,
+    default: 0.0,
+    unit: UNIT_GATE,
+    description: std::ptr::null(),
+}];
+
+static MODULES: [ModuleDescriptor; 1] = [ModuleDescriptor {
+    type_name: c"sample_hold".as_ptr(),
+    description: c"Samples signal when gate rises, and holds it".as_ptr(),
+    in_ports: SAMPLE_HOLD_IN_PORTS.as_ptr(),
+    in_port_count: SAMPLE_HOLD_IN_PORTS.len(),
+    out_ports: SAMPLE_HOLD_OUT_PORTS.as_ptr(),
+    out_port_count: SAMPLE_HOLD_OUT_PORTS.len(),
+    create: sample_hold_create,
+    destroy: sample_hold_destroy,
+    set_sample_rate: sample_hold_set_sample_rate,
+    process: sample_hold_process,
+}];
+
+static CONTROLS: [ControlDescriptor; 1] = [ControlDescriptor {
+    type_name: c"toggle".as_ptr(),
+    description: c"Gate, switched on and off with 't'".as_ptr(),
+    out_ports: TOGGLE_OUT_PORTS.as_ptr(),
+    out_port_count: TOGGLE_OUT_PORTS.len(),
+    create: toggle_create,
+    destroy: toggle_destroy,
+    get_value: toggle_get_value,
+    set_value: toggle_set_value,
+    recv_key: toggle_recv_key,
+}];
+
+static PLUGIN: PluginDescriptor = PluginDescriptor {
+    name: c"example".as_ptr(),
+    modules: MODULES.as_ptr(),
+    module_count: MODULES.len(),
+    controls: CONTROLS.as_ptr(),
+    control_count: CONTROLS.len(),
+};
+
+export_plugin!(PLUGIN);
@@ -0,0 +1,239 @@
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use yat_rack::command::{Command, CommandOutput, PluginAction};
+use yat_rack::engine::Engine;
+use yat_rack::event::Event;
+use yat_rack::modules::output::Output;
+use yat_rack::port_descriptor::{PortDirection, PortUnit};
+use yat_rack::rack::Rack;
+use yat_rack::ring_buffer::Consumer;
+
+/// The example plugin, as built by cargo alongside this test
+fn plugin_path() -> PathBuf {
+    let name = format!("{}yat_example_plugin{}", DLL_PREFIX, DLL_SUFFIX);
+    let exe = std::env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+
+    [deps, deps.parent().unwrap()]
+        .iter()
+        .map(|dir| dir.join(&name))
+        .find(|path| path.exists())
+        .expect("the example plugin hasn't been built")
+}
+
+/// A Rack with the plugin loaded, and an output to read the result from
+fn rack_with_plugin() -> (Rack, Consumer) {
+    rack_with_plugin_at(plugin_path())
+}
+
+fn rack_with_plugin_at(path: PathBuf) -> (Rack, Consumer) {
+    let mut rack = Rack::new();
+    rack.set_block_size(4);
+    rack.load_plugin(path).unwrap();
+
+    let (output, consumer) = Output::new("out".into(), 1);
+    rack.add_module(Arc::new(Mutex::new(output))).unwrap();
+
+    (rack, consumer)
+}
+
+fn process_block(rack: &mut Rack, consumer: &Consumer) -> Vec<f64> {
+    rack.process_module_chain();
+    (0..4).map(|_| consumer.pop().unwrap()).collect()
+}
+
+#[test]
+fn registers_types() {
+    let (rack, _) = rack_with_plugin();
+
+    let sample_hold = rack.get_registry().get_info("sample_hold").unwrap();
+    assert!(!sample_hold.is_control);
+    assert_eq!(sample_hold.in_ports, ["signal", "gate"]);
+    assert_eq!(sample_hold.out_ports, ["result"]);
+
+    let toggle = rack.get_registry().get_info("toggle").unwrap();
+    assert!(toggle.is_control);
+    assert_eq!(toggle.out_ports, ["gate"]);
+
+    assert!(rack.print_plugins().contains("example"));
+}
+
+#[test]
+fn processes_modules_and_controls() {
+    let (mut rack, consumer) = rack_with_plugin();
+    rack.run_script(
+        "add sample_hold sh
+        add toggle t
+        add control k
+        set k value 0.25
+        connect k value sh signal
+        connect t gate sh gate
+        connect sh result out signal_in",
+    )
+    .unwrap();
+
+    // Nothing is held before the gate rises
+    assert_eq!(process_block(&mut rack, &consumer), [0.0; 4]);
+
+    rack.run_script("set t gate 1\nset k value 0.75").unwrap();
+    assert_eq!(process_block(&mut rack, &consumer), [0.75; 4]);
+
+    // The value is held while the gate stays high
+    rack.run_script("set k value 0.5").unwrap();
+    assert_eq!(process_block(&mut rack, &consumer), [0.75; 4]);
+
+    // Toggling the gate off and on again samples the new value
+    rack.set_focus_control("t").unwrap();
+    rack.send_control_key('t');
+    assert_eq!(process_block(&mut rack, &consumer), [0.75; 4]);
+    rack.send_control_key('t');
+    assert_eq!(process_block(&mut rack, &consumer), [0.5; 4]);
+}
+
+#[test]
+fn describes_ports() {
+    let (mut rack, _) = rack_with_plugin();
+
+    let sample_hold = rack.get_registry().get_info("sample_hold").unwrap();
+    let gate = &sample_hold.ports[1];
+    assert_eq!(gate.label, "gate");
+    assert_eq!(gate.direction, PortDirection::Input);
+    assert_eq!((gate.lower_range, gate.upper_range), (0.0, 1.0));
+    assert_eq!(gate.default, Some(0.0));
+    assert_eq!(gate.unit, PortUnit::Gate);
+    assert_eq!(
+        gate.description,
+        "Samples the signal when it rises above 0.5"
+    );
+
+    let toggle = rack.get_registry().get_info("toggle").unwrap();
+    assert_eq!(toggle.ports[0].direction, PortDirection::Output);
+    assert_eq!(toggle.ports[0].default, None);
+    assert_eq!(toggle.ports[0].description, "");
+
+    rack.run_script("add toggle t").unwrap();
+    assert!(rack.run_script("set t gate 2").is_err());
+    assert!(rack.print_ports(Some("t")).contains("gate (gate, 0 to 1)"));
+}
+
+#[test]
+fn refuses_loading_twice() {
+    let (mut rack, _) = rack_with_plugin();
+
+    let e = rack.load_plugin(plugin_path()).unwrap_err();
+    assert_eq!(e.to_string(), "Plugin example is already loaded");
+}
+
+#[test]
+fn refuses_non_plugins() {
+    let mut rack = Rack::new();
+
+    assert!(rack.load_plugin("no_such_plugin.so").is_err());
+    assert!(rack.get_registry().get_info("sample_hold").is_none());
+}
+
+#[test]
+fn reload_keeps_the_patch() {
+    let (mut rack, consumer) = rack_with_plugin();
+    rack.run_script(
+        "add sample_hold sh
+        add toggle t
+        add control k1
+        add control k2
+        set k1 value 0.25
+        set k2 value 0.5
+        connect t gate sh gate
+        connect sh result out signal_in",
+    )
+    .unwrap();
+    rack.set_summing("sh", "signal", true).unwrap();
+    rack.run_script("connect k1 value sh signal\nconnect k2 value sh signal\nset t gate 1")
+        .unwrap();
+    rack.set_focus_control("t").unwrap();
+    assert_eq!(process_block(&mut rack, &consumer), [0.75; 4]);
+
+    let patch = rack.to_patch();
+    let msg = rack.reload_plugin("example").unwrap();
+    assert_eq!(
+        msg,
+        "Reloaded plugin example, swapped 2 modules and controls"
+    );
+    assert_eq!(rack.to_patch(), patch);
+
+    // The new sample_hold starts with its gate low, so the high gate counts as a rise
+    rack.run_script("set k2 value 1").unwrap();
+    assert_eq!(process_block(&mut rack, &consumer), [1.25; 4]);
+
+    // The new toggle has the focus
+    rack.send_control_key('t');
+    assert_eq!(process_block(&mut rack, &consumer), [1.25; 4]);
+    rack.run_script("set k1 value 0").unwrap();
+    rack.send_control_key('t');
+    assert_eq!(process_block(&mut rack, &consumer), [1.0; 4]);
+}
+
+#[test]
+fn failed_reload_keeps_the_old_library() {
+    let dir = std::env::temp_dir().join(format!("yat-plugin-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(plugin_path().file_name().unwrap());
+    fs::copy(plugin_path(), &path).unwrap();
+
+    let (mut rack, consumer) = rack_with_plugin_at(path.clone());
+    rack.run_script(
+        "add sample_hold sh
+        add control k
+        set k value 0.75
+        connect k value sh gate
+        connect k value sh signal
+        connect sh result out signal_in",
+    )
+    .unwrap();
+
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(rack.reload_plugin("example").is_err());
+    assert!(rack.reload_plugin("missing").is_err());
+
+    assert!(rack.get_registry().get_info("sample_hold").is_some());
+    assert!(rack.print_plugins().contains("example"));
+    assert_eq!(process_block(&mut rack, &consumer), [0.75; 4]);
+}
+
+#[test]
+fn reloads_through_the_engine() {
+    let (mut rack, _) = rack_with_plugin();
+    rack.run_script("add sample_hold sh\nadd toggle t").unwrap();
+    let patch = rack.to_patch();
+
+    let rack = Arc::new(Mutex::new(rack));
+    let (engine, event_tx) = Engine::new(rack.clone());
+    let engine = thread::spawn(move || engine.run());
+
+    let (reply_tx, reply_rx) = mpsc::channel();
+    let reload = Command::Plugin(PluginAction::Reload {
+        name: String::from("example"),
+    });
+    event_tx
+        .send(Event::Request(reload, reply_tx.clone()))
+        .unwrap();
+    assert_eq!(
+        reply_rx.recv().unwrap().result,
+        Ok(CommandOutput::Message(String::from(
+            "Reloaded plugin example, swapped 2 modules and controls"
+        )))
+    );
+
+    let missing = Command::Plugin(PluginAction::Reload {
+        name: String::from("missing"),
+    });
+    event_tx.send(Event::Request(missing, reply_tx)).unwrap();
+    assert!(reply_rx.recv().unwrap().result.is_err());
+
+    event_tx.send(Event::Command(Command::Quit)).unwrap();
+    engine.join().unwrap();
+    assert_eq!(rack.lock().unwrap().to_patch(), patch);
+}
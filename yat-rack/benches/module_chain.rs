@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use yat_rack::modules::adder::Adder;
+use yat_rack::rack::Rack;
+use yat_rack::types::AUDIO_BUF_SIZE;
+
+/// The number of modules chained after the oscillator
+const CHAIN_LENGTH: usize = 50;
+
+/// Build a Rack with an oscillator feeding a chain of adders
+fn build_chain(block_size: usize) -> Rack {
+    let mut rack = Rack::new();
+    rack.add_module_type("osc", "osc").unwrap();
+
+    let mut out_module_id = String::from("osc");
+    let mut out_port_id = "audio_out";
+    for i in 0..CHAIN_LENGTH {
+        let module_id = format!("adder_{}", i);
+        rack.add_module(Arc::new(Mutex::new(Adder::new(module_id.clone()))))
+            .unwrap();
+        rack.connect_modules(&out_module_id, out_port_id, &module_id, "a")
+            .unwrap();
+
+        out_module_id = module_id;
+        out_port_id = "sum";
+    }
+
+    rack.set_block_size(block_size);
+    rack
+}
+
+fn module_chain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("module_chain");
+
+    for block_size in [1, 64, AUDIO_BUF_SIZE] {
+        let mut rack = build_chain(block_size);
+
+        group.throughput(Throughput::Elements(block_size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(block_size),
+            &block_size,
+            |b, _| b.iter(|| rack.process_module_chain()),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, module_chain);
+criterion_main!(benches);
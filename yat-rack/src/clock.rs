@@ -4,19 +4,22 @@ use std::sync::atomic::Ordering::Relaxed;
 
 pub struct Clock {
     time: SampleType,
+    /// The number of frames per second
+    sample_rate: SampleType,
+    /// The time between two frames, i.e., the inverse of the sample rate
     pub time_delta: SampleType,
     running: AtomicBool,
 }
 
 impl Clock {
-    pub fn new() -> Self {
+    pub fn new(sample_rate: SampleType) -> Self {
         let time = 0f64;
-        // FIXME: SAMPLE_RATE should be taken from CPAL config
-        let time_delta = 1.0 / SAMPLE_RATE;
+        let time_delta = 1.0 / sample_rate;
         let running = AtomicBool::new(false);
 
         Clock {
             time,
+            sample_rate,
             time_delta,
             running,
         }
@@ -38,6 +41,18 @@ impl Clock {
         self.time = 0.0;
     }
 
+    pub fn get_sample_rate(&self) -> SampleType {
+        self.sample_rate
+    }
+
+    /// Change the sample rate. The clock is reset, as times from before the change are
+    /// measured in frames of a different length
+    pub fn set_sample_rate(&mut self, sample_rate: SampleType) {
+        self.sample_rate = sample_rate;
+        self.time_delta = 1.0 / sample_rate;
+        self.reset_clock();
+    }
+
     pub fn get_time_ref(&self) -> SampleType {
         self.time
     }
@@ -50,6 +65,13 @@ impl Clock {
         self.time = new_time;
     }
 
+    /// Move the clock forward by a number of frames
+    pub fn advance(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.increment();
+        }
+    }
+
     pub fn increment(&mut self) {
         if self.time >= 100_000f64 {
             self.time -= 100_000f64;
@@ -61,6 +83,6 @@ impl Clock {
 
 impl Default for Clock {
     fn default() -> Self {
-        Self::new()
+        Self::new(SAMPLE_RATE)
     }
 }
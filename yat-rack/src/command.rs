@@ -0,0 +1,558 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use hashbrown::HashMap;
+
+use crate::info::Info;
+use crate::types::{ConflictingModuleIdError, ModuleNotFoundError, PortNotFoundError, SampleType};
+
+/// Commands which drive a Rack, e.g. from the TUI, a patch script or a remote client.
+///
+/// As text, a command is its name followed by its arguments, in the order of the variant's
+/// fields, e.g. `connect osc1 audio_out audio_out signal_in`. Arguments can also be named, as
+/// `key=value`, in any order. See `Command::from_str` for the names.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Add a module or control: `add <type> <id>`
+    Add { module_type: String, id: String },
+
+    /// Remove a module or control: `remove <id>`
+    Remove { id: String },
+
+    /// Connect modules, or a control to a module:
+    /// `connect <out_module> <out_port> <in_module> <in_port> [<gain>]`.
+    /// Without a gain, the cable passes its signal on as it is
+    Connect {
+        out_module: String,
+        out_port: String,
+        in_module: String,
+        in_port: String,
+        gain: Option<SampleType>,
+    },
+
+    /// Disconnect an input port: `disconnect <in_module> <in_port> [<out_module> <out_port>]`.
+    /// If an output is given, only the cable from that output is disconnected
+    Disconnect {
+        in_module: String,
+        in_port: String,
+        out: Option<(String, String)>,
+    },
+
+    /// Let an input port sum several cables, or only keep its latest one:
+    /// `summing <module> <port> on|off`
+    Summing {
+        module: String,
+        port: String,
+        summing: bool,
+    },
+
+    /// Set a control's value: `set <ctrl> <port> <value>`
+    Set {
+        ctrl: String,
+        port: String,
+        value: SampleType,
+    },
+
+    /// Give a control the focus: `focus <ctrl>`
+    Focus { ctrl: String },
+
+    /// Start processing: `run`
+    Run,
+
+    /// Stop processing: `stop`
+    Stop,
+
+    /// Print information about the Rack: `info <data> [<module>]`
+    Info(InfoData),
+
+    /// Save the patch to a file: `save <path>`
+    Save { path: String },
+
+    /// Replace the patch with one loaded from a file: `load <path>`
+    Load { path: String },
+
+    /// Manage plugin libraries: `plugin <action> <plugin>`
+    Plugin(PluginAction),
+
+    /// Stop the engine: `quit`
+    Quit,
+}
+
+/// The information printed by `Command::Info`
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfoData {
+    /// The ports of every module, or of a single module: `info ports [<module>]`
+    Ports(Option<String>),
+
+    /// `info modules`
+    Modules,
+
+    /// `info connections`
+    Connections,
+
+    /// `info module_order`
+    ModuleOrder,
+
+    /// The module and control types which can be added: `info types`
+    Types,
+
+    /// The loaded plugin libraries: `info plugins`
+    Plugins,
+}
+
+/// What `Command::Plugin` does
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginAction {
+    /// Load a plugin library and register its types: `plugin load <path>`
+    Load { path: String },
+
+    /// Load a loaded plugin's library again, e.g. after rebuilding it, and swap its modules
+    /// and controls for new ones: `plugin reload <name>`
+    Reload { name: String },
+}
+
+impl Command {
+    /// The command's name, as it is written in text
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Add { .. } => "add",
+            Command::Remove { .. } => "remove",
+            Command::Connect { .. } => "connect",
+            Command::Disconnect { .. } => "disconnect",
+            Command::Summing { .. } => "summing",
+            Command::Set { .. } => "set",
+            Command::Focus { .. } => "focus",
+            Command::Run => "run",
+            Command::Stop => "stop",
+            Command::Info(_) => "info",
+            Command::Save { .. } => "save",
+            Command::Load { .. } => "load",
+            Command::Plugin(_) => "plugin",
+            Command::Quit => "quit",
+        }
+    }
+
+    /// Whether the command changes the Rack's state, e.g. its modules, connections or
+    /// control values, rather than just printing or saving it
+    pub fn changes_rack(&self) -> bool {
+        !matches!(
+            self,
+            Command::Info(_) | Command::Save { .. } | Command::Quit
+        )
+    }
+
+    /// Whether other programs may send the command, e.g. over OSC or an event server. Nobody
+    /// should be able to stop yat, or touch its files, from outside
+    pub fn is_accepted_remotely(&self) -> bool {
+        !matches!(
+            self,
+            Command::Quit | Command::Save { .. } | Command::Load { .. } | Command::Plugin(_)
+        )
+    }
+
+    /// The names of a command's arguments, in the order they are given without names
+    fn arg_names(cmd: &str) -> Option<&'static [&'static str]> {
+        match cmd {
+            "add" => Some(&["type", "id"]),
+            "remove" => Some(&["id"]),
+            "connect" => Some(&["out_module", "out_port", "in_module", "in_port", "gain"]),
+            "disconnect" => Some(&["in_module", "in_port", "out_module", "out_port"]),
+            "summing" => Some(&["module", "port", "summing"]),
+            "set" => Some(&["ctrl", "port", "value"]),
+            "focus" => Some(&["ctrl"]),
+            "info" => Some(&["data", "module"]),
+            "save" | "load" => Some(&["path"]),
+            "plugin" => Some(&["action", "plugin"]),
+            "run" | "stop" | "quit" => Some(&[]),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = CommandError;
+
+    /// Parse a command from a line of text. The argument names are those of the variant's
+    /// fields, except for `type` (`Add`), `value` (`Set`), `out_module` and `out_port`
+    /// (`Disconnect`), `data` and `module` (`Info`), and `action` and `plugin` (`Plugin`)
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let cmd = words
+            .next()
+            .ok_or_else(|| CommandError::Parse(String::from("No command given")))?;
+        let arg_names = Self::arg_names(cmd)
+            .ok_or_else(|| CommandError::Parse(format!("Unknown command: {}", cmd)))?;
+
+        let mut args = HashMap::new();
+        let mut positional = arg_names.iter();
+        for word in words {
+            let (key, value) = match word.split_once('=') {
+                Some((key, value)) if arg_names.contains(&key) => (key, value),
+                Some((key, _)) => {
+                    return Err(CommandError::Parse(format!(
+                        "{}: unknown argument {}",
+                        cmd, key
+                    )))
+                }
+                // Named arguments are skipped, so that both kinds can be mixed
+                None => match positional.find(|key| !args.contains_key(*key)) {
+                    Some(key) => (*key, word),
+                    None => {
+                        return Err(CommandError::Parse(format!(
+                            "{}: unexpected argument {}",
+                            cmd, word
+                        )))
+                    }
+                },
+            };
+            args.insert(key, value.to_string());
+        }
+
+        let mut arg = |key: &str| {
+            args.remove(key)
+                .ok_or_else(|| CommandError::Parse(format!("{}: {} not specified", cmd, key)))
+        };
+
+        Ok(match cmd {
+            "add" => Command::Add {
+                module_type: arg("type")?,
+                id: arg("id")?,
+            },
+            "remove" => Command::Remove { id: arg("id")? },
+            "connect" => Command::Connect {
+                out_module: arg("out_module")?,
+                out_port: arg("out_port")?,
+                in_module: arg("in_module")?,
+                in_port: arg("in_port")?,
+                gain: match arg("gain") {
+                    Ok(gain) => Some(gain.parse().map_err(|_| {
+                        CommandError::Parse(format!("{}: invalid gain {}", cmd, gain))
+                    })?),
+                    Err(_) => None,
+                },
+            },
+            "disconnect" => Command::Disconnect {
+                in_module: arg("in_module")?,
+                in_port: arg("in_port")?,
+                out: match (arg("out_module"), arg("out_port")) {
+                    (Ok(out_module), Ok(out_port)) => Some((out_module, out_port)),
+                    (Err(_), Err(_)) => None,
+                    (_, Err(e)) | (Err(e), _) => return Err(e),
+                },
+            },
+            "summing" => Command::Summing {
+                module: arg("module")?,
+                port: arg("port")?,
+                summing: match arg("summing")?.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    summing => {
+                        return Err(CommandError::Parse(format!(
+                            "{}: expected on or off, not {}",
+                            cmd, summing
+                        )))
+                    }
+                },
+            },
+            "set" => {
+                let ctrl = arg("ctrl")?;
+                let port = arg("port")?;
+                let value = arg("value")?;
+                let value = value.parse().map_err(|_| {
+                    CommandError::Parse(format!("{}: invalid value {}", cmd, value))
+                })?;
+                Command::Set { ctrl, port, value }
+            }
+            "focus" => Command::Focus { ctrl: arg("ctrl")? },
+            "run" => Command::Run,
+            "stop" => Command::Stop,
+            "info" => Command::Info(match arg("data")?.as_str() {
+                "ports" => InfoData::Ports(arg("module").ok()),
+                "modules" => InfoData::Modules,
+                "connections" => InfoData::Connections,
+                "module_order" => InfoData::ModuleOrder,
+                "types" => InfoData::Types,
+                "plugins" => InfoData::Plugins,
+                data => {
+                    return Err(CommandError::Parse(format!(
+                        "{}: unknown data {}",
+                        cmd, data
+                    )))
+                }
+            }),
+            "save" => Command::Save { path: arg("path")? },
+            "load" => Command::Load { path: arg("path")? },
+            "plugin" => Command::Plugin(match arg("action")?.as_str() {
+                "load" => PluginAction::Load {
+                    path: arg("plugin")?,
+                },
+                "reload" => PluginAction::Reload {
+                    name: arg("plugin")?,
+                },
+                action => {
+                    return Err(CommandError::Parse(format!(
+                        "{}: unknown action {}",
+                        cmd, action
+                    )))
+                }
+            }),
+            "quit" => Command::Quit,
+            _ => unreachable!("arg_names only knows the commands above"),
+        })
+    }
+}
+
+impl fmt::Display for Command {
+    /// Write the command as text, with its arguments in positional order
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+
+        match self {
+            Command::Add { module_type, id } => write!(f, " {} {}", module_type, id),
+            Command::Remove { id } => write!(f, " {}", id),
+            Command::Connect {
+                out_module,
+                out_port,
+                in_module,
+                in_port,
+                gain,
+            } => {
+                write!(f, " {} {} {} {}", out_module, out_port, in_module, in_port)?;
+                match gain {
+                    Some(gain) => write!(f, " {}", gain),
+                    None => Ok(()),
+                }
+            }
+            Command::Disconnect {
+                in_module,
+                in_port,
+                out,
+            } => {
+                write!(f, " {} {}", in_module, in_port)?;
+                match out {
+                    Some((out_module, out_port)) => write!(f, " {} {}", out_module, out_port),
+                    None => Ok(()),
+                }
+            }
+            Command::Summing {
+                module,
+                port,
+                summing,
+            } => write!(
+                f,
+                " {} {} {}",
+                module,
+                port,
+                if *summing { "on" } else { "off" }
+            ),
+            Command::Set { ctrl, port, value } => write!(f, " {} {} {}", ctrl, port, value),
+            Command::Focus { ctrl } => write!(f, " {}", ctrl),
+            Command::Save { path } | Command::Load { path } => write!(f, " {}", path),
+            Command::Info(data) => match data {
+                InfoData::Ports(Some(module)) => write!(f, " ports {}", module),
+                InfoData::Ports(None) => write!(f, " ports"),
+                InfoData::Modules => write!(f, " modules"),
+                InfoData::Connections => write!(f, " connections"),
+                InfoData::ModuleOrder => write!(f, " module_order"),
+                InfoData::Types => write!(f, " types"),
+                InfoData::Plugins => write!(f, " plugins"),
+            },
+            Command::Plugin(action) => match action {
+                PluginAction::Load { path } => write!(f, " load {}", path),
+                PluginAction::Reload { name } => write!(f, " reload {}", name),
+            },
+            Command::Run | Command::Stop | Command::Quit => Ok(()),
+        }
+    }
+}
+
+/// Why a command failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    ModuleNotFound,
+    PortNotFound,
+    ConflictingModuleId,
+
+    /// The command couldn't be parsed from its text
+    Parse(String),
+
+    /// Any other failure, described by its message
+    Failed(String),
+}
+
+impl Error for CommandError {}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::ModuleNotFound => ModuleNotFoundError.fmt(f),
+            CommandError::PortNotFound => PortNotFoundError.fmt(f),
+            CommandError::ConflictingModuleId => ConflictingModuleIdError.fmt(f),
+            CommandError::Parse(msg) | CommandError::Failed(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<ModuleNotFoundError> for CommandError {
+    fn from(_: ModuleNotFoundError) -> Self {
+        CommandError::ModuleNotFound
+    }
+}
+
+impl From<PortNotFoundError> for CommandError {
+    fn from(_: PortNotFoundError) -> Self {
+        CommandError::PortNotFound
+    }
+}
+
+impl From<ConflictingModuleIdError> for CommandError {
+    fn from(_: ConflictingModuleIdError) -> Self {
+        CommandError::ConflictingModuleId
+    }
+}
+
+impl From<Box<dyn Error>> for CommandError {
+    /// Keep the Rack's own errors typed, anything else is kept as its message
+    fn from(e: Box<dyn Error>) -> Self {
+        if e.is::<ModuleNotFoundError>() {
+            CommandError::ModuleNotFound
+        } else if e.is::<PortNotFoundError>() {
+            CommandError::PortNotFound
+        } else if e.is::<ConflictingModuleIdError>() {
+            CommandError::ConflictingModuleId
+        } else {
+            CommandError::Failed(e.to_string())
+        }
+    }
+}
+
+/// What a successful command produced. It is kept as data until it is shown or sent, e.g. by
+/// the TUI or the event server, see `fmt::Display` for the text
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandOutput {
+    /// What the command did, e.g. "Added module: osc1"
+    Message(String),
+
+    /// A control's output port was set: `set`
+    Value {
+        ctrl: String,
+        port: String,
+        value: SampleType,
+    },
+
+    /// Information about the Rack: `info`
+    Info(Info),
+}
+
+impl From<String> for CommandOutput {
+    fn from(message: String) -> Self {
+        CommandOutput::Message(message)
+    }
+}
+
+impl fmt::Display for CommandOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandOutput::Message(message) => write!(f, "{}", message),
+            CommandOutput::Value { ctrl, port, value } => {
+                write!(f, "Updated control {}: {} is {}", ctrl, port, value)
+            }
+            CommandOutput::Info(info) => write!(f, "{}", info),
+        }
+    }
+}
+
+/// The Rack's response to a command, as written to its message queue
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub command: Command,
+
+    /// The command's output, e.g. the info asked for, or why it failed
+    pub result: Result<CommandOutput, CommandError>,
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.result {
+            Ok(output) => write!(f, "{}", output),
+            Err(e) => write!(f, "{}: {}", self.command.name(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Command {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_a_connection_with_a_gain() {
+        let connect = Command::Connect {
+            out_module: "lfo".into(),
+            out_port: "signal_out".into(),
+            in_module: "osc".into(),
+            in_port: "freq".into(),
+            gain: Some(0.5),
+        };
+        assert_eq!(parse("connect lfo signal_out osc freq 0.5"), connect);
+        assert_eq!(parse("connect lfo signal_out osc freq gain=0.5"), connect);
+        assert_eq!(parse("connect gain=0.5 lfo signal_out osc freq"), connect);
+
+        assert_eq!(
+            parse("connect lfo signal_out osc freq"),
+            Command::Connect {
+                out_module: "lfo".into(),
+                out_port: "signal_out".into(),
+                in_module: "osc".into(),
+                in_port: "freq".into(),
+                gain: None,
+            }
+        );
+        assert!("connect lfo signal_out osc freq gain=loud"
+            .parse::<Command>()
+            .is_err());
+    }
+
+    #[test]
+    fn parses_summing() {
+        assert_eq!(
+            parse("summing mixer in on"),
+            Command::Summing {
+                module: "mixer".into(),
+                port: "in".into(),
+                summing: true,
+            }
+        );
+        assert_eq!(
+            parse("summing port=in module=mixer summing=off"),
+            Command::Summing {
+                module: "mixer".into(),
+                port: "in".into(),
+                summing: false,
+            }
+        );
+        assert!("summing mixer in yes".parse::<Command>().is_err());
+        assert!("summing mixer in".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn writes_commands_which_parse_back() {
+        for line in [
+            "connect lfo signal_out osc freq",
+            "connect lfo signal_out osc freq 0.25",
+            "connect k1 value a a -2",
+            "summing a a on",
+            "summing a a off",
+            "disconnect osc freq lfo signal_out",
+            "set knob value 0.5",
+        ] {
+            let command = parse(line);
+            assert_eq!(command.to_string(), line);
+            assert_eq!(parse(&command.to_string()), command);
+        }
+    }
+}
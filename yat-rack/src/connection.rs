@@ -0,0 +1,122 @@
+use std::fmt;
+
+use crate::types::SampleType;
+
+/// A connection from a module's or control's output port to a module's input port
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    /// The position of the connection in the order connections were made
+    index: u64,
+
+    /// The module or control which the signal comes from
+    out_module_id: String,
+
+    /// The output port which the signal comes from
+    out_port_id: String,
+
+    /// The module which receives the signal
+    in_module_id: String,
+
+    /// The input port which receives the signal
+    in_port_id: String,
+
+    /// Factor applied to the signal, e.g. for attenuating one of several summed cables
+    gain: SampleType,
+
+    /// If this connection closes a feedback loop, the modules along the loop, starting at the
+    /// input module and ending at the output module. The input module reads the value of the
+    /// output from the previous sample, i.e., the loop is broken by a one-sample delay.
+    feedback_path: Option<Vec<String>>,
+}
+
+impl Connection {
+    pub(crate) fn new(
+        index: u64,
+        out_module_id: String,
+        out_port_id: String,
+        in_module_id: String,
+        in_port_id: String,
+    ) -> Self {
+        Self {
+            index,
+            out_module_id,
+            out_port_id,
+            in_module_id,
+            in_port_id,
+            gain: 1.0,
+            feedback_path: None,
+        }
+    }
+
+    pub fn get_index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn get_out_module_id(&self) -> &str {
+        &self.out_module_id
+    }
+
+    pub fn get_out_port_id(&self) -> &str {
+        &self.out_port_id
+    }
+
+    pub fn get_in_module_id(&self) -> &str {
+        &self.in_module_id
+    }
+
+    pub fn get_in_port_id(&self) -> &str {
+        &self.in_port_id
+    }
+
+    pub fn get_gain(&self) -> SampleType {
+        self.gain
+    }
+
+    pub(crate) fn set_gain(&mut self, gain: SampleType) {
+        self.gain = gain;
+    }
+
+    /// Returns the modules along the feedback loop which this connection closes, if any
+    pub fn get_feedback_path(&self) -> Option<&Vec<String>> {
+        self.feedback_path.as_ref()
+    }
+
+    pub fn is_feedback(&self) -> bool {
+        self.feedback_path.is_some()
+    }
+
+    pub(crate) fn set_feedback_path(&mut self, feedback_path: Option<Vec<String>>) {
+        self.feedback_path = feedback_path;
+    }
+
+    /// Whether the connection feeds the given input port
+    pub fn feeds(&self, in_module_id: &str, in_port_id: &str) -> bool {
+        self.in_module_id == in_module_id && self.in_port_id == in_port_id
+    }
+
+    /// Whether the connection comes from the given output port
+    pub fn comes_from(&self, out_module_id: &str, out_port_id: &str) -> bool {
+        self.out_module_id == out_module_id && self.out_port_id == out_port_id
+    }
+
+    /// Whether the given module or control is at either end of the connection
+    pub fn involves(&self, module_id: &str) -> bool {
+        self.out_module_id == module_id || self.in_module_id == module_id
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} to {} -> {}",
+            self.index, self.out_module_id, self.out_port_id, self.in_module_id, self.in_port_id
+        )?;
+
+        if self.gain != 1.0 {
+            write!(f, " (gain {})", self.gain)?;
+        }
+
+        Ok(())
+    }
+}
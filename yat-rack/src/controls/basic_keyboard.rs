@@ -1,9 +1,8 @@
-use std::sync::{Weak, RwLock};
-
 use crate::controls::control::Control;
 use crate::midi::message_status::MessageStatus;
 use crate::types::SampleType;
-use crate::out_port::OutPort;
+use crate::out_port::{OutPort, PortRef};
+use crate::port_descriptor::{PortDescriptor, PortUnit};
 
 
 /// An control IoModule
@@ -25,9 +24,20 @@ pub struct BasicKeyboard {
 impl BasicKeyboard {
     /// Create a new BasicKeyboard
     pub fn new(id: String) -> Self {
-        let out_gate = OutPort::new("gate".into());
-        let out_pitch = OutPort::new("pitch".into());
-        let out_velocity = OutPort::new("velocity".into());
+        let mut out_gate = OutPort::new("gate".into());
+        out_gate.set_range(0.0, 1.0);
+        out_gate.set_unit(PortUnit::Gate);
+        out_gate.set_description("On while a note is held");
+
+        let mut out_pitch = OutPort::new("pitch".into());
+        out_pitch.set_range(0.0, 20_000.0);
+        out_pitch.set_unit(PortUnit::Hz);
+        out_pitch.set_description("The frequency of the last note played");
+
+        let mut out_velocity = OutPort::new("velocity".into());
+        out_velocity.set_range(0.0, 1.0);
+        out_velocity.set_unit(PortUnit::Gain);
+        out_velocity.set_description("How hard the last note was played");
 
         Self {
             id,
@@ -40,8 +50,7 @@ impl BasicKeyboard {
 
 impl Control for BasicKeyboard {
     /// Get a reference to the control's output port
-    fn get_port_reference(&self, port: &str)
-        -> Option<Weak<RwLock<Option<SampleType>>>> {
+    fn get_port_reference(&self, port: &str) -> Option<PortRef> {
         match port {
             "gate" => Some(self.out_gate.get_ref()),
             "pitch" => Some(self.out_pitch.get_ref()),
@@ -50,12 +59,24 @@ impl Control for BasicKeyboard {
         }
     }
 
+    fn get_out_ports(&self) -> &[&str] {
+        &["gate", "pitch", "velocity"]
+    }
+
+    fn get_port_descriptors(&self) -> Vec<PortDescriptor> {
+        vec![
+            self.out_gate.get_descriptor(),
+            self.out_pitch.get_descriptor(),
+            self.out_velocity.get_descriptor(),
+        ]
+    }
+
     /// Set the controls output value
     fn set_value(&self, port: &str, new_value: SampleType) {
         match port {
-            "gate" => self.out_gate.set_value(new_value),
-            "pitch" => self.out_pitch.set_value(new_value),
-            "velocity" => self.out_velocity.set_value(new_value),
+            "gate" => self.out_gate.fill_value(new_value),
+            "pitch" => self.out_pitch.fill_value(new_value),
+            "velocity" => self.out_velocity.fill_value(new_value),
             _ => (),
         }
     }
@@ -1,8 +1,7 @@
-use std::sync::{RwLock, Weak};
-
 use crate::controls::control::Control;
 use crate::types::SampleType;
-use crate::out_port::OutPort;
+use crate::out_port::{OutPort, PortRef};
+use crate::port_descriptor::{PortDescriptor, PortUnit};
 
 /// An control IoModule
 pub struct Button {
@@ -16,7 +15,10 @@ pub struct Button {
 impl Button {
     /// Create a new, unordered IoModule
     pub fn new(id: String) -> Self {
-        let out_value = OutPort::new("gate".into());
+        let mut out_value = OutPort::new("gate".into());
+        out_value.set_range(0.0, 1.0);
+        out_value.set_unit(PortUnit::Gate);
+        out_value.set_description("Toggled on and off with the spacebar");
 
         Self {
             id,
@@ -27,21 +29,28 @@ impl Button {
 
 impl Control for Button {
     /// Get a reference to the control's output port
-    fn get_port_reference(&self, port: &str)
-        -> Option<Weak<RwLock<Option<SampleType>>>> {
+    fn get_port_reference(&self, port: &str) -> Option<PortRef> {
         match port {
             "gate" => Some(self.out_gate.get_ref()),
             _ => None,
         }
     }
 
+    fn get_out_ports(&self) -> &[&str] {
+        &["gate"]
+    }
+
+    fn get_port_descriptors(&self) -> Vec<PortDescriptor> {
+        vec![self.out_gate.get_descriptor()]
+    }
+
     /// Set the controls output value
     fn set_value(&self, port: &str, new_value: SampleType) {
         if port == "gate" {
             if new_value > 0.0 {
-                self.out_gate.set_value(1.0);
+                self.out_gate.fill_value(1.0);
             } else {
-                self.out_gate.set_value(0.0);
+                self.out_gate.fill_value(0.0);
             }
         }
     }
@@ -51,16 +60,9 @@ impl Control for Button {
     fn recv_control_key(&self, key: char) {
         if key == ' ' {
             // Toggle between on and off, using space
-            let next_value = match self.out_gate.get_ref().upgrade() {
-                Some(val) => {
-                    let current_val = val.read().expect("RwLock poisoned").unwrap_or(0.0);
-                    if current_val > 0f64 {
-                        0f64
-                    } else {
-                        1f64
-                    }
-                }
-                None => 1f64,
+            let next_value = match self.out_gate.get_value() {
+                Some(current_val) if current_val > 0f64 => 0f64,
+                _ => 1f64,
             };
             self.set_value("gate", next_value);
         }
@@ -1,5 +1,5 @@
-use std::sync::{RwLock, Weak};
-
+use crate::out_port::PortRef;
+use crate::port_descriptor::PortDescriptor;
 use crate::types::SampleType;
 
 /// A trait for implementng controls.
@@ -7,8 +7,23 @@ use crate::types::SampleType;
 /// are controlled concurrently and don't take an input from other modules.
 pub trait Control {
     /// Get a reference to the control's output port
-    fn get_port_reference(&self, port: &str)
-        -> Option<Weak<RwLock<Option<SampleType>>>>;
+    fn get_port_reference(&self, port: &str) -> Option<PortRef>;
+
+    /// Returns the control's output ports
+    fn get_out_ports(&self) -> &[&str];
+
+    /// Describe the control's output ports. By default they are unbounded and without unit
+    fn get_port_descriptors(&self) -> Vec<PortDescriptor> {
+        self.get_out_ports()
+            .iter()
+            .map(|port_id| PortDescriptor::output(port_id))
+            .collect()
+    }
+
+    /// Get the current value of one of the control's output ports, if it was ever set
+    fn get_value(&self, port: &str) -> Option<SampleType> {
+        self.get_port_reference(port)?.upgrade()?.get(0)
+    }
 
     /// Set the controls output value
     fn set_value(&self, port: &str, new_value: SampleType);
@@ -1,7 +1,6 @@
-use std::sync::{RwLock, Weak};
-
 use crate::controls::control::Control;
-use crate::out_port::OutPort;
+use crate::out_port::{OutPort, PortRef};
+use crate::port_descriptor::PortDescriptor;
 use crate::types::SampleType;
 
 /// An control
@@ -16,7 +15,8 @@ pub struct ControlKnob {
 impl ControlKnob {
     /// Create a new, unordered IoModule
     pub fn new(id: String) -> Self {
-        let out_value = OutPort::new("value".into());
+        let mut out_value = OutPort::new("value".into());
+        out_value.set_description("Raised and lowered in steps of 100 with k and j");
 
         Self {
             id,
@@ -27,18 +27,25 @@ impl ControlKnob {
 
 impl Control for ControlKnob {
     /// Get a reference to the control's output port
-    fn get_port_reference(&self, port_id: &str)
-        -> Option<Weak<RwLock<Option<SampleType>>>> {
+    fn get_port_reference(&self, port_id: &str) -> Option<PortRef> {
         match port_id {
             "value" => Some(self.out_value.get_ref()),
             _ => None,
         }
     }
 
+    fn get_out_ports(&self) -> &[&str] {
+        &["value"]
+    }
+
+    fn get_port_descriptors(&self) -> Vec<PortDescriptor> {
+        vec![self.out_value.get_descriptor()]
+    }
+
     /// Set the controls output value
     fn set_value(&self, port_id: &str, new_value: SampleType) {
         if port_id == "value" {
-            self.out_value.set_value(new_value);
+            self.out_value.fill_value(new_value);
         }
     }
 
@@ -49,21 +56,15 @@ impl Control for ControlKnob {
     fn recv_control_key(&self, key: char) {
         match key {
             'k' => {
-                let next_value = match self.out_value.get_ref().upgrade() {
-                    Some(val) => {
-                        let val = val.read().expect("RwLock poisoned").unwrap_or(0.0);
-                        val + 100f64
-                    },
+                let next_value = match self.out_value.get_value() {
+                    Some(val) => val + 100f64,
                     None => 0f64,
                 };
                 self.set_value("value", next_value);
             }
             'j' => {
-                let next_value = match self.out_value.get_ref().upgrade() {
-                    Some(val) => {
-                        let val = val.read().expect("RwLock poisoned").unwrap_or(0.0);
-                        val - 100f64
-                    },
+                let next_value = match self.out_value.get_value() {
+                    Some(val) => val - 100f64,
                     None => 0f64,
                 };
                 self.set_value("value", next_value);
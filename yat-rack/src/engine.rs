@@ -0,0 +1,309 @@
+use std::error::Error;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::command::{Command, CommandError, CommandOutput, PluginAction, Response};
+use crate::event::Event;
+use crate::plugin::Plugin;
+use crate::rack::Rack;
+use crate::ring_buffer::Pacer;
+use crate::types::SampleType;
+
+/// How long to wait before checking the output's buffer for space again
+const WAIT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long the output's buffer may stay full before the reading side counts as stalled, e.g.
+/// while no audio device is open
+const STALL_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// When the engine may process its next block
+struct Schedule {
+    /// When the next block is due, if the engine follows the clock
+    next_block: Instant,
+
+    /// The output's read count when it was last seen to change, and when that was
+    last_read: Option<(usize, Instant)>,
+}
+
+/// The Rack's processing loop.
+///
+/// Between blocks, the engine drains its event queue and applies the events to the Rack, so
+/// changes to the patch only ever happen at block boundaries. While the Rack is running, the
+/// loop is paced by the audio output: before each block, it waits until the output's buffer
+/// has space for it. Without an output, or while nothing reads from it, blocks follow the
+/// clock instead. The Rack is only locked while a block is processed or an event applied, so
+/// others can use it while the engine waits, or while it loads a plugin library for a
+/// `plugin` command. While the Rack is stopped, the loop blocks until the next event arrives.
+pub struct Engine {
+    rack: Arc<Mutex<Rack>>,
+
+    event_queue: Receiver<Event>,
+
+    /// The output which paces the loop, if any
+    pacer: Option<Pacer>,
+}
+
+impl Engine {
+    /// Create an engine for the Rack, along with a sender for its event queue
+    pub fn new(rack: Arc<Mutex<Rack>>) -> (Self, Sender<Event>) {
+        let (event_tx, event_queue) = mpsc::channel();
+
+        let engine = Self {
+            rack,
+            event_queue,
+            pacer: None,
+        };
+
+        (engine, event_tx)
+    }
+
+    /// Pace the loop by an output, rather than by the clock
+    pub fn pace_by(&mut self, pacer: Pacer) {
+        self.pacer = Some(pacer);
+    }
+
+    /// Run the loop until a `Command::Quit` is received, or every sender of the event queue
+    /// has been dropped
+    pub fn run(&self) {
+        let mut schedule = Schedule {
+            next_block: Instant::now(),
+            last_read: None,
+        };
+
+        loop {
+            let (running, block_size, sample_rate) = {
+                let rack = self.rack.lock().expect("Mutex lock is poisoned");
+                (
+                    rack.running.load(Relaxed),
+                    rack.get_block_size(),
+                    rack.get_sample_rate(),
+                )
+            };
+
+            if !running {
+                // Nothing to process, so wait for the next event
+                match self.event_queue.recv() {
+                    Ok(event) => {
+                        if !self.handle_event(event) {
+                            return;
+                        }
+                    }
+                    Err(_) => return,
+                }
+                schedule.next_block = Instant::now();
+                continue;
+            }
+
+            // Apply any pending events before processing the next block
+            loop {
+                match self.event_queue.try_recv() {
+                    Ok(event) => {
+                        if !self.handle_event(event) {
+                            return;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            // Wait for the block to be due, applying any events which arrive in the meantime
+            if let Some(wait) = self.time_until_next_block(&mut schedule, block_size) {
+                match self.event_queue.recv_timeout(wait) {
+                    Ok(event) => {
+                        if !self.handle_event(event) {
+                            return;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                continue;
+            }
+
+            let started = Instant::now();
+            self.rack
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .process_module_chain();
+
+            // Don't try to catch up on more than a block, e.g. after the output stalled
+            let duration = Duration::from_secs_f64(block_size as SampleType / sample_rate);
+            schedule.next_block = schedule.next_block.max(started) + duration;
+        }
+    }
+
+    /// Returns how long to wait until the next block can be processed, or None if it can be
+    /// processed right away
+    fn time_until_next_block(
+        &self,
+        schedule: &mut Schedule,
+        block_size: usize,
+    ) -> Option<Duration> {
+        let now = Instant::now();
+
+        if let Some(pacer) = &self.pacer {
+            if pacer.has_space(block_size) {
+                schedule.last_read = None;
+                return None;
+            }
+
+            // The buffer is full, so wait for the reading side, unless it stopped reading
+            let read_count = pacer.get_watcher().read_count();
+            let (last_count, last_progress) = schedule.last_read.get_or_insert((read_count, now));
+            if *last_count != read_count {
+                *last_count = read_count;
+                *last_progress = now;
+            }
+            if !pacer.get_watcher().is_closed() && now - *last_progress < STALL_TIMEOUT {
+                return Some(WAIT_INTERVAL);
+            }
+        }
+
+        // There is nothing to pace against, so follow the clock
+        match schedule.next_block.checked_duration_since(now) {
+            Some(wait) if !wait.is_zero() => Some(wait),
+            _ => None,
+        }
+    }
+
+    /// Apply an event to the Rack. Returns false if the engine should quit
+    fn handle_event(&self, event: Event) -> bool {
+        let (action, reply) = match event {
+            Event::Command(Command::Quit) => return false,
+            Event::Command(Command::Plugin(action)) => (action, None),
+            Event::Request(Command::Plugin(action), reply) => (action, Some(reply)),
+            event => {
+                self.rack
+                    .lock()
+                    .expect("Mutex lock is poisoned")
+                    .handle_event(event);
+                return true;
+            }
+        };
+
+        // Loading a library can take a while, so the Rack is only locked to add or swap it
+        let plugin = self.load_plugin(&action);
+
+        let mut rack = self.rack.lock().expect("Mutex lock is poisoned");
+        let result = plugin
+            .and_then(|plugin| match &action {
+                PluginAction::Load { .. } => rack.add_plugin(plugin),
+                PluginAction::Reload { .. } => rack.swap_plugin(plugin),
+            })
+            .map(CommandOutput::from)
+            .map_err(CommandError::from);
+        let command = Command::Plugin(action);
+        rack.respond(Response { command, result }, reply);
+
+        true
+    }
+
+    /// Load the library which a plugin command adds or swaps in
+    fn load_plugin(&self, action: &PluginAction) -> Result<Arc<Plugin>, Box<dyn Error>> {
+        match action {
+            PluginAction::Load { path } => Plugin::load(path),
+            PluginAction::Reload { name } => {
+                let old = self
+                    .rack
+                    .lock()
+                    .expect("Mutex lock is poisoned")
+                    .get_plugin(name)
+                    .ok_or_else(|| format!("Plugin {} isn't loaded", name))?;
+                old.reload()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::output::Output;
+    use crate::types::AUDIO_BUF_SIZE;
+    use std::thread;
+
+    fn quit(event_tx: &Sender<Event>, engine: thread::JoinHandle<()>) {
+        event_tx.send(Event::Command(Command::Quit)).unwrap();
+        engine.join().unwrap();
+    }
+
+    #[test]
+    fn waits_for_the_output_without_holding_the_rack() {
+        let rack = Arc::new(Mutex::new(Rack::new()));
+        let (output, consumer) = Output::new("out".into(), 1);
+        let pacer = output.get_pacer();
+        rack.lock()
+            .unwrap()
+            .add_module(Arc::new(Mutex::new(output)))
+            .unwrap();
+
+        let (mut engine, event_tx) = Engine::new(rack.clone());
+        engine.pace_by(pacer);
+        let engine = thread::spawn(move || engine.run());
+
+        // The buffer fills up, and then the engine waits for it to be read
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(consumer.len(), 2 * AUDIO_BUF_SIZE);
+        let started = Instant::now();
+        let time = rack.lock().unwrap().clock.read().unwrap().get_time_ref();
+        assert!(started.elapsed() < Duration::from_millis(20));
+
+        // Reading a block lets the engine process the next one
+        consumer.skip(AUDIO_BUF_SIZE);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(consumer.len(), 2 * AUDIO_BUF_SIZE);
+        assert!(rack.lock().unwrap().clock.read().unwrap().get_time_ref() > time);
+
+        quit(&event_tx, engine);
+    }
+
+    #[test]
+    fn follows_the_clock_without_an_output() {
+        let rack = Arc::new(Mutex::new(Rack::with_sample_rate(1000.0)));
+        rack.lock().unwrap().set_block_size(10);
+
+        let (engine, event_tx) = Engine::new(rack.clone());
+        let started = Instant::now();
+        let engine = thread::spawn(move || engine.run());
+        thread::sleep(Duration::from_millis(200));
+        quit(&event_tx, engine);
+
+        // Each block takes 10 ms, so there is about a block for every 10 ms which passed
+        let seconds = rack.lock().unwrap().clock.read().unwrap().get_time_ref();
+        assert!(seconds > 0.05, "only {} seconds were processed", seconds);
+        assert!(
+            seconds <= started.elapsed().as_secs_f64() + 0.01,
+            "{} seconds were processed",
+            seconds
+        );
+    }
+
+    #[test]
+    fn changes_the_number_of_workers_while_running() {
+        let rack = Arc::new(Mutex::new(Rack::with_sample_rate(1000.0)));
+        {
+            let mut rack = rack.lock().unwrap();
+            rack.set_block_size(4);
+            rack.run_script("add osc o1\nadd osc o2\nadd osc o3\nadd adder a")
+                .unwrap();
+            rack.run_script("connect o1 audio_out a a\nconnect o2 audio_out a b")
+                .unwrap();
+        }
+
+        let (engine, event_tx) = Engine::new(rack.clone());
+        let engine = thread::spawn(move || engine.run());
+
+        // The pool is swapped between blocks, as the engine only processes with the Rack locked
+        for workers in [4, 0, 2, 8, 1, 3] {
+            let time = rack.lock().unwrap().clock.read().unwrap().get_time_ref();
+            rack.lock().unwrap().set_worker_threads(workers);
+            thread::sleep(Duration::from_millis(30));
+            assert!(rack.lock().unwrap().clock.read().unwrap().get_time_ref() > time);
+        }
+
+        quit(&event_tx, engine);
+    }
+}
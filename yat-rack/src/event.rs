@@ -1,29 +1,15 @@
-use hashbrown::HashMap;
+use std::sync::mpsc::Sender;
 
-/// - Control
-///     - MIDI
-/// - Add module ("add")
-///     add <module_type> <module_id>
-/// - Connect modules ("connect")
-///     connect <out_module_id> <out_port_id> <in_module> <in_module_id>
-/// - Disconnect modules
-/// - Connect control to module ("connect")
-/// - Set focus control ("focus")
-///     set <ctrl_id> <port_id> <value>
-/// - Set control value
-///     set <ctrl_id> <port_id> <value>
-/// - Print ("print/info")
-///     set <ctrl_id> <port_id> <value>
-///     - ports
-///     - modules
-///     - connections
-///     - module order
-/// - Process chain
-/// - Start ("run")
-/// - Stop ("stop")
-/// - Unknown command
+use crate::command::{Command, Response};
+
+/// Events which are applied to a Rack by its engine, in between processing blocks.
+///
+/// The response to a command is written to the Rack's message queue as a
+/// `command::Response`. The response to a request is written to the request's own queue
+/// instead, e.g. for a remote client waiting on it.
+#[derive(Debug, Clone)]
 pub enum Event {
     Midi(u8, u8, u8),
-    Command(String, HashMap<String, String>),
+    Command(Command),
+    Request(Command, Sender<Response>),
 }
-
@@ -0,0 +1,460 @@
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::command::{Command, CommandError, Response};
+use crate::event::Event;
+use crate::rack::Rack;
+
+/// How often a subscription checks whether its client has gone away
+const SUBSCRIPTION_POLL: Duration = Duration::from_millis(100);
+
+/// Lets other programs drive a running Rack, e.g. editor integrations or a second terminal,
+/// over a Unix domain socket or a localhost TCP port.
+///
+/// The protocol is line based. Clients send one command per line, as text (see `Command`),
+/// and the server answers each with a single line:
+///
+/// - `ok <output>` if the command succeeded
+/// - `err <kind> <message>` if it failed, where kind is one of `module_not_found`,
+///   `port_not_found`, `conflicting_module_id`, `parse` or `failed`
+///
+/// Newlines and backslashes in the output and message are escaped as `\n` and `\\`.
+///
+/// Sending `subscribe` starts a stream of the changes made to the Rack, by any client or the
+/// TUI, e.g. `change add osc osc1` or `change set knob1 value 0.5`. Change lines can arrive
+/// in between a request and its answer. A connection can only subscribe once. Commands are
+/// applied by the engine, in between blocks. `quit`, `save`, `load` and `plugin` aren't
+/// accepted.
+///
+/// Dropping the server stops it from accepting clients. Clients which are already connected
+/// are served until they disconnect, or the engine stops.
+pub struct EventServer {
+    address: String,
+
+    /// The socket file to clean up when the server is dropped
+    socket_path: Option<PathBuf>,
+
+    /// Tells the listener thread to stop
+    stop: Arc<AtomicBool>,
+
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EventServer {
+    /// Listen on a Unix domain socket at the given path. The path must not exist yet
+    #[cfg(unix)]
+    pub fn bind_unix<P: Into<PathBuf>>(
+        path: P,
+        rack: Arc<Mutex<Rack>>,
+        event_tx: Sender<Event>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = path.into();
+        let listener = UnixListener::bind(&path)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let l_stop = stop.clone();
+
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if l_stop.load(Relaxed) {
+                    break;
+                }
+                if let Ok(writer) = stream.try_clone() {
+                    serve_client(stream, Box::new(writer), rack.clone(), event_tx.clone());
+                }
+            }
+        });
+
+        Ok(Self {
+            address: path.display().to_string(),
+            socket_path: Some(path),
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Listen on a TCP address. Anybody who can connect controls the Rack, so only
+    /// loopback addresses are allowed
+    pub fn bind_tcp(
+        address: &str,
+        rack: Arc<Mutex<Rack>>,
+        event_tx: Sender<Event>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let address: SocketAddr = address.parse()?;
+        if !address.ip().is_loopback() {
+            return Err(format!("{} isn't a loopback address", address.ip()).into());
+        }
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let l_stop = stop.clone();
+
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if l_stop.load(Relaxed) {
+                    break;
+                }
+                if let Ok(writer) = stream.try_clone() {
+                    serve_client(stream, Box::new(writer), rack.clone(), event_tx.clone());
+                }
+            }
+        });
+
+        Ok(Self {
+            address: address.to_string(),
+            socket_path: None,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// The socket path or TCP address which the server listens on
+    pub fn get_address(&self) -> &str {
+        &self.address
+    }
+
+    /// Connect to the server, so that the listener thread wakes up from waiting for a client
+    fn wake(&self) -> std::io::Result<()> {
+        #[cfg(unix)]
+        if let Some(path) = &self.socket_path {
+            return UnixStream::connect(path).map(drop);
+        }
+
+        TcpStream::connect(&self.address).map(drop)
+    }
+}
+
+impl Drop for EventServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+
+        // If nobody can connect anymore, e.g. because the socket file was removed, the
+        // listener thread would never wake up, so it is left to end with the program
+        let woken = self.wake().is_ok();
+        if let Some(thread) = self.thread.take() {
+            if woken {
+                let _ = thread.join();
+            }
+        }
+
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Serve a client on its own thread, until it disconnects or the engine stops
+fn serve_client<R: Read + Send + 'static>(
+    reader: R,
+    writer: Box<dyn Write + Send>,
+    rack: Arc<Mutex<Rack>>,
+    event_tx: Sender<Event>,
+) {
+    thread::spawn(move || {
+        // Shared with the subscription, so that lines are never interleaved
+        let writer = Arc::new(Mutex::new(writer));
+        let closed = Arc::new(AtomicBool::new(false));
+        let mut subscribed = false;
+
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else { break };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let answer = if line == "subscribe" {
+                if subscribed {
+                    format_error(&CommandError::Failed(String::from("Already subscribed")))
+                } else {
+                    subscribed = true;
+                    subscribe(&rack, writer.clone(), closed.clone());
+                    String::from("ok Subscribed")
+                }
+            } else {
+                match line.parse::<Command>() {
+                    Ok(command) if !command.is_accepted_remotely() => {
+                        format_error(&CommandError::Failed(String::from(
+                            "quit, save, load and plugin aren't accepted remotely",
+                        )))
+                    }
+                    Ok(command) => {
+                        let (reply_tx, reply_rx) = mpsc::channel();
+                        if event_tx.send(Event::Request(command, reply_tx)).is_err() {
+                            break;
+                        }
+                        match reply_rx.recv() {
+                            Ok(response) => format_response(&response),
+                            // The engine stopped before applying the command
+                            Err(_) => break,
+                        }
+                    }
+                    Err(e) => format_error(&e),
+                }
+            };
+
+            if write_line(&writer, &answer).is_err() {
+                break;
+            }
+        }
+
+        closed.store(true, Relaxed);
+    });
+}
+
+/// Forward the Rack's changes to the client, until it disconnects
+fn subscribe(
+    rack: &Arc<Mutex<Rack>>,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    closed: Arc<AtomicBool>,
+) {
+    let responses = rack.lock().expect("Mutex lock is poisoned").subscribe();
+
+    thread::spawn(move || {
+        while !closed.load(Relaxed) {
+            match responses.recv_timeout(SUBSCRIPTION_POLL) {
+                Ok(response) => {
+                    if response.result.is_ok() && response.command.changes_rack() {
+                        let line = format!("change {}", response.command);
+                        if write_line(&writer, &line).is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+}
+
+fn write_line(writer: &Mutex<Box<dyn Write + Send>>, line: &str) -> std::io::Result<()> {
+    let mut writer = writer.lock().expect("Mutex lock is poisoned");
+    writeln!(writer, "{}", line)?;
+    writer.flush()
+}
+
+fn format_response(response: &Response) -> String {
+    match &response.result {
+        Ok(output) => format!("ok {}", escape(&output.to_string())),
+        Err(e) => format_error(e),
+    }
+}
+
+fn format_error(e: &CommandError) -> String {
+    let kind = match e {
+        CommandError::ModuleNotFound => "module_not_found",
+        CommandError::PortNotFound => "port_not_found",
+        CommandError::ConflictingModuleId => "conflicting_module_id",
+        CommandError::Parse(_) => "parse",
+        CommandError::Failed(_) => "failed",
+    };
+
+    format!("err {} {}", kind, escape(&e.to_string()))
+}
+
+/// Keep multi-line output on a single line
+fn escape(text: &str) -> String {
+    text.trim_end_matches('\n')
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use std::io::Lines;
+
+    /// How long a client waits for another line before taking it that none is coming
+    const QUIET: Duration = Duration::from_millis(300);
+
+    fn tcp_server() -> (EventServer, mpsc::Receiver<Event>) {
+        let rack = Arc::new(Mutex::new(Rack::new()));
+        let (event_tx, event_rx) = mpsc::channel();
+        let server = EventServer::bind_tcp("127.0.0.1:0", rack, event_tx).unwrap();
+        (server, event_rx)
+    }
+
+    #[test]
+    fn rejects_commands_which_stop_yat_or_touch_its_files() {
+        let (server, event_rx) = tcp_server();
+        let stream = TcpStream::connect(server.get_address()).unwrap();
+        let mut answers = BufReader::new(stream.try_clone().unwrap()).lines();
+
+        for line in [
+            "quit",
+            "save patch.toml",
+            "load patch.toml",
+            "plugin load libx.so",
+        ] {
+            writeln!(&stream, "{}", line).unwrap();
+            let answer = answers.next().unwrap().unwrap();
+            assert!(answer.starts_with("err failed "), "{}: {}", line, answer);
+        }
+
+        // None of them reached the engine
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn stops_listening_once_dropped() {
+        let (server, _event_rx) = tcp_server();
+        let address = server.get_address().to_string();
+        drop(server);
+
+        // The listener thread has ended, and closed the listener along with it
+        assert!(TcpStream::connect(&address).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removes_its_socket_file_once_dropped() {
+        let path = std::env::temp_dir().join(format!("yat-test-{}.sock", std::process::id()));
+        let rack = Arc::new(Mutex::new(Rack::new()));
+        let (event_tx, _) = mpsc::channel();
+        let server = EventServer::bind_unix(&path, rack, event_tx).unwrap();
+        drop(server);
+
+        assert!(!path.exists());
+    }
+
+    /// A connection to a server, reading its answers and change lines
+    struct Client<S: Read + Write> {
+        lines: Lines<BufReader<S>>,
+        writer: S,
+    }
+
+    impl<S: Read + Write> Client<S> {
+        fn new(reader: S, writer: S) -> Self {
+            Self {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            }
+        }
+
+        /// Send a line and return the next line the server sends
+        fn request(&mut self, line: &str) -> String {
+            writeln!(self.writer, "{}", line).unwrap();
+            self.lines.next().unwrap().unwrap()
+        }
+
+        /// The lines the server sends until it goes quiet
+        fn rest(&mut self) -> Vec<String> {
+            self.lines.by_ref().map_while(Result::ok).collect()
+        }
+    }
+
+    /// Serve a Rack whose events are applied by a running engine
+    fn serve<F>(bind: F) -> (EventServer, Sender<Event>)
+    where
+        F: FnOnce(Arc<Mutex<Rack>>, Sender<Event>) -> EventServer,
+    {
+        let rack = Arc::new(Mutex::new(Rack::new()));
+        let (engine, event_tx) = Engine::new(rack.clone());
+        thread::spawn(move || engine.run());
+        (bind(rack, event_tx.clone()), event_tx)
+    }
+
+    fn tcp_client(server: &EventServer) -> Client<TcpStream> {
+        let stream = TcpStream::connect(server.get_address()).unwrap();
+        stream.set_read_timeout(Some(QUIET)).unwrap();
+        Client::new(stream.try_clone().unwrap(), stream)
+    }
+
+    #[cfg(unix)]
+    fn unix_client(server: &EventServer) -> Client<UnixStream> {
+        let stream = UnixStream::connect(server.get_address()).unwrap();
+        stream.set_read_timeout(Some(QUIET)).unwrap();
+        Client::new(stream.try_clone().unwrap(), stream)
+    }
+
+    #[cfg(unix)]
+    fn unix_server(name: &str) -> (EventServer, Sender<Event>) {
+        let path =
+            std::env::temp_dir().join(format!("yat-test-{}-{}.sock", std::process::id(), name));
+        serve(|rack, event_tx| EventServer::bind_unix(path, rack, event_tx).unwrap())
+    }
+
+    fn tcp_server_with_engine() -> (EventServer, Sender<Event>) {
+        serve(|rack, event_tx| EventServer::bind_tcp("127.0.0.1:0", rack, event_tx).unwrap())
+    }
+
+    fn answers_requests<S: Read + Write>(mut client: Client<S>) {
+        assert!(client.request("add adder a").starts_with("ok "));
+        assert_eq!(
+            client.request("info modules"),
+            "ok Modules:\\n    a\\n\\nControls:"
+        );
+
+        let errors = [
+            ("add adder a", "err conflicting_module_id "),
+            ("connect x sum a a", "err module_not_found "),
+            ("connect a nothing a a", "err port_not_found "),
+            ("frobnicate", "err parse "),
+        ];
+        for (line, error) in errors {
+            let answer = client.request(line);
+            assert!(answer.starts_with(error), "{}: {}", line, answer);
+        }
+
+        assert!(client.rest().is_empty());
+    }
+
+    fn streams_changes<S: Read + Write>(mut watcher: Client<S>, mut other: Client<S>) {
+        assert_eq!(watcher.request("subscribe"), "ok Subscribed");
+        assert!(watcher.request("subscribe").starts_with("err failed "));
+
+        assert!(other.request("add adder a").starts_with("ok "));
+        assert!(other.request("info modules").starts_with("ok "));
+        assert!(other.request("add adder a").starts_with("err "));
+        assert!(other.request("add control k").starts_with("ok "));
+
+        // Once each, only for what changed, although the watcher asked to subscribe twice
+        assert_eq!(
+            watcher.rest(),
+            ["change add adder a", "change add control k"]
+        );
+    }
+
+    #[test]
+    fn answers_requests_over_tcp() {
+        let (server, event_tx) = tcp_server_with_engine();
+        answers_requests(tcp_client(&server));
+        event_tx.send(Event::Command(Command::Quit)).unwrap();
+    }
+
+    #[test]
+    fn streams_changes_over_tcp() {
+        let (server, event_tx) = tcp_server_with_engine();
+        streams_changes(tcp_client(&server), tcp_client(&server));
+        event_tx.send(Event::Command(Command::Quit)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn answers_requests_over_a_unix_socket() {
+        let (server, event_tx) = unix_server("answers");
+        answers_requests(unix_client(&server));
+        event_tx.send(Event::Command(Command::Quit)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn streams_changes_over_a_unix_socket() {
+        let (server, event_tx) = unix_server("changes");
+        streams_changes(unix_client(&server), unix_client(&server));
+        event_tx.send(Event::Command(Command::Quit)).unwrap();
+    }
+}
@@ -1,11 +1,33 @@
-use std::sync::{RwLock, Weak};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+use crate::out_port::{PortBuffer, PortRef};
+use crate::port_descriptor::{PortDescriptor, PortDirection, PortUnit};
+use crate::types::SampleType;
+
+/// A cable from an output port's buffer into an input port
+struct Cable {
+    /// The buffer of the connected output port. This is held directly, rather than as a weak
+    /// pointer, so that reading a sample doesn't need to upgrade the pointer first
+    source: Arc<PortBuffer>,
+
+    /// Factor applied to the source's signal, e.g. for attenuating it
+    gain: SampleType,
+}
 
 pub struct InPort {
     /// The port's ID label
     label: String,
 
-    /// A weak pointer to an output port's value
-    value: Weak<RwLock<Option<f64>>>,
+    /// The cables connected to the port. Unless the port is summing, there is at most one
+    cables: Vec<Cable>,
+
+    /// Whether the port accepts several cables, whose signals are summed
+    summing: bool,
+
+    /// The frame of the block which is being processed
+    frame: AtomicUsize,
 
     /// This is a suggested lower bound on the port's value.
     /// A user is free to ignore this.
@@ -17,6 +39,12 @@ pub struct InPort {
 
     /// A default value, in case it's not connected, i.e., it's value is None
     default: f64,
+
+    /// What the port's value means, e.g. a frequency
+    unit: PortUnit,
+
+    /// What the port does, for showing to the user
+    description: String,
 }
 
 impl InPort {
@@ -27,14 +55,22 @@ impl InPort {
         default: f64,
         ) -> Self
     {
-        let value = Weak::new();
+        let cables = Vec::new();
+        let summing = false;
+        let frame = AtomicUsize::new(0);
+        let unit = PortUnit::None;
+        let description = String::new();
 
         Self {
             label,
-            value,
+            cables,
+            summing,
+            frame,
             lower_range,
             upper_range,
             default,
+            unit,
+            description,
         }
     }
 
@@ -46,18 +82,117 @@ impl InPort {
         self.label = new_label;
     }
 
+    /// Get the value of the current frame. This is the sum of the connected signals, or the
+    /// default if none of them has been written yet
     pub fn get_value(&self) -> f64 {
-        match self.value.upgrade() {
-            Some(v) => {
-                let v = v.read().expect("RwLock is poisoned").unwrap_or(self.default);
-                v
-            },
-            None => self.default,
+        let frame = self.frame.load(Relaxed);
+
+        let mut value = None;
+        for cable in &self.cables {
+            if let Some(sample) = cable.source.get(frame) {
+                *value.get_or_insert(0.0) += sample * cable.gain;
+            }
+        }
+
+        value.unwrap_or(self.default)
+    }
+
+    /// Fill the given slice with the values of the first frames of the block
+    pub fn read_block(&self, block: &mut [SampleType]) {
+        let written = match self.cables.as_slice() {
+            [cable] if cable.gain == 1.0 => cable.source.read_block(block),
+            cables => {
+                block.fill(0.0);
+
+                let mut written = false;
+                for cable in cables {
+                    written |= cable.source.add_to_block(block, cable.gain);
+                }
+                written
+            }
+        };
+
+        if !written {
+            block.fill(self.default);
+        }
+    }
+
+    /// Connect the port to an output port's buffer, replacing every other cable. An empty
+    /// reference disconnects the port
+    pub fn set_value(&mut self, value: PortRef) {
+        self.cables.clear();
+        if let Some(source) = value.upgrade() {
+            self.cables.push(Cable { source, gain: 1.0 });
+        }
+    }
+
+    /// Connect a cable from an output port's buffer. A summing port keeps its other cables,
+    /// otherwise they are replaced. Connecting the same output twice replaces its cable, which
+    /// then counts as the most recent one, e.g. for `set_summing`
+    pub fn add_source(&mut self, value: PortRef, gain: SampleType) {
+        let source = match value.upgrade() {
+            Some(source) => source,
+            None => return,
+        };
+
+        if self.summing {
+            self.cables
+                .retain(|cable| !Arc::ptr_eq(&cable.source, &source));
+        } else {
+            self.cables.clear();
+        }
+
+        self.cables.push(Cable { source, gain });
+    }
+
+    /// Disconnect the cable from an output port's buffer.
+    /// Returns false if no such cable was connected
+    pub fn remove_source(&mut self, value: &PortRef) -> bool {
+        let count = self.cables.len();
+        self.cables
+            .retain(|cable| !std::ptr::eq(Arc::as_ptr(&cable.source), value.as_ptr()));
+
+        self.cables.len() != count
+    }
+
+    /// Set the gain of the cable from an output port's buffer.
+    /// Returns false if no such cable is connected
+    pub fn set_source_gain(&mut self, value: &PortRef, gain: SampleType) -> bool {
+        match self
+            .cables
+            .iter_mut()
+            .find(|cable| std::ptr::eq(Arc::as_ptr(&cable.source), value.as_ptr()))
+        {
+            Some(cable) => {
+                cable.gain = gain;
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn set_value(&mut self, value: Weak<RwLock<Option<f64>>>) {
-        self.value = value;
+    pub fn is_summing(&self) -> bool {
+        self.summing
+    }
+
+    /// Allow or disallow several cables. When summing is turned off, only the most recently
+    /// connected cable is kept
+    pub fn set_summing(&mut self, summing: bool) {
+        self.summing = summing;
+
+        if !summing && self.cables.len() > 1 {
+            self.cables.drain(..self.cables.len() - 1);
+        }
+    }
+
+    /// Move to a frame of the block, for per-sample processing
+    pub fn seek(&self, frame: usize) {
+        self.frame.store(frame, Relaxed);
+    }
+
+    /// Get the value which is used while no connected signal has been written
+    pub fn get_default(&self) -> f64 {
+        self.default
     }
 
     pub fn get_lower_range(&self) -> f64 {
@@ -76,7 +211,36 @@ impl InPort {
         self.upper_range = new_upper_range;
     }
 
+    pub fn get_unit(&self) -> PortUnit {
+        self.unit
+    }
+
+    pub fn set_unit(&mut self, new_unit: PortUnit) {
+        self.unit = new_unit;
+    }
+
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn set_description(&mut self, new_description: &str) {
+        self.description = new_description.into();
+    }
+
+    /// Describe the port's label, range, default and unit
+    pub fn get_descriptor(&self) -> PortDescriptor {
+        PortDescriptor {
+            label: self.label.clone(),
+            direction: PortDirection::Input,
+            lower_range: self.lower_range,
+            upper_range: self.upper_range,
+            default: Some(self.default),
+            unit: self.unit,
+            description: self.description.clone(),
+        }
+    }
+
     pub fn is_connected(&self) -> bool {
-        self.value.strong_count() > 0
+        !self.cables.is_empty()
     }
 }
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::connection::Connection;
+use crate::port_descriptor::{PortDescriptor, PortDirection};
+use crate::registry::TypeInfo;
+
+/// Information about a Rack, as asked for by an `info` command. It is kept as data until it is
+/// shown or sent, see `fmt::Display` for the text the TUI shows
+#[derive(Debug, Clone, PartialEq)]
+pub enum Info {
+    /// The ports of every module and control, or of a single one, with their descriptions
+    Ports {
+        items: Vec<ItemPorts>,
+        with_descriptions: bool,
+    },
+
+    /// The IDs of the modules and of the controls
+    Modules {
+        modules: Vec<String>,
+        controls: Vec<String>,
+    },
+
+    /// The connections, in the order they were made
+    Connections(Vec<Connection>),
+
+    /// The IDs of the modules in each position of the chain
+    ModuleOrder(Vec<Vec<String>>),
+
+    /// The registered types, ordered by name
+    Types(Vec<TypeInfo>),
+
+    /// The loaded plugins
+    Plugins(Vec<PluginInfo>),
+}
+
+/// The ports of a module or control
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemPorts {
+    pub id: String,
+
+    pub is_control: bool,
+
+    pub ports: Vec<PortDescriptor>,
+}
+
+/// Describes a loaded plugin
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginInfo {
+    pub name: String,
+
+    /// The library the plugin was loaded from
+    pub path: PathBuf,
+
+    /// The names of the types the plugin registered
+    pub types: Vec<String>,
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Info::Ports {
+                items,
+                with_descriptions,
+            } => {
+                writeln!(f, "Ports: ")?;
+                for item in items {
+                    item.write(f, *with_descriptions)?;
+                }
+                writeln!(f)
+            }
+            Info::Modules { modules, controls } => {
+                writeln!(f, "Modules:")?;
+                for module in modules {
+                    writeln!(f, "    {}", module)?;
+                }
+                writeln!(f)?;
+
+                writeln!(f, "Controls:")?;
+                for control in controls {
+                    writeln!(f, "    {}", control)?;
+                }
+                writeln!(f)
+            }
+            Info::Connections(connections) => {
+                writeln!(f, "Connections:")?;
+                for conn in connections {
+                    writeln!(f, "    {}", conn)?;
+                    if let Some(path) = conn.get_feedback_path() {
+                        writeln!(
+                            f,
+                            "        feedback loop, delayed by one sample: {} -> {}",
+                            path.join(" -> "),
+                            conn.get_in_module_id()
+                        )?;
+                    }
+                }
+                writeln!(f)
+            }
+            Info::ModuleOrder(chain) => {
+                writeln!(f, "Module order:")?;
+                for (position, modules) in chain.iter().enumerate() {
+                    writeln!(f, "    Modules in position {}:", position + 1)?;
+                    for module in modules {
+                        writeln!(f, "        {}", module)?;
+                    }
+                }
+                writeln!(f)
+            }
+            Info::Types(types) => {
+                writeln!(f, "Types:")?;
+                for info in types {
+                    write_type(f, info)?;
+                }
+                Ok(())
+            }
+            Info::Plugins(plugins) => {
+                writeln!(f, "Plugins:")?;
+                for plugin in plugins {
+                    writeln!(
+                        f,
+                        "    {} ({}): {}",
+                        plugin.name,
+                        plugin.path.display(),
+                        plugin.types.join(", ")
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ItemPorts {
+    /// Write the ports, grouped by direction. Directions without ports, e.g. a control's
+    /// inputs, are left out
+    fn write(&self, f: &mut fmt::Formatter, with_descriptions: bool) -> fmt::Result {
+        let kind = if self.is_control { "Control" } else { "Module" };
+        writeln!(f, "{} - {}:", kind, self.id)?;
+
+        let groups = [
+            ("inputs", PortDirection::Input),
+            ("outputs", PortDirection::Output),
+        ];
+        for (name, direction) in groups {
+            if !self.ports.iter().any(|port| port.direction == direction) {
+                continue;
+            }
+
+            writeln!(f, "    {}:", name)?;
+            for port in self.ports.iter().filter(|port| port.direction == direction) {
+                writeln!(f, "        {}", port)?;
+                if with_descriptions && !port.description.is_empty() {
+                    writeln!(f, "            {}", port.description)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Write a type's name, description and ports
+fn write_type(f: &mut fmt::Formatter, info: &TypeInfo) -> fmt::Result {
+    let kind = if info.is_control { "control" } else { "module" };
+    writeln!(f, "    {} ({}): {}", info.name, kind, info.description)?;
+
+    let describe = |direction| {
+        info.ports
+            .iter()
+            .filter(|port| port.direction == direction)
+            .map(|port| port.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    if !info.in_ports.is_empty() {
+        writeln!(f, "        inputs: {}", describe(PortDirection::Input))?;
+    }
+    writeln!(f, "        outputs: {}", describe(PortDirection::Output))
+}
@@ -1,9 +1,23 @@
 pub mod clock;
+pub mod command;
+pub mod connection;
 pub mod controls;
+pub mod engine;
+pub mod event;
+pub mod event_server;
 pub mod in_port;
+pub mod info;
 pub mod midi;
 pub mod modules;
+pub mod osc;
 pub mod out_port;
+pub mod patch;
+pub mod plugin;
+pub mod port_descriptor;
 pub mod rack;
+pub mod recorder;
+pub mod registry;
+pub mod render;
+pub mod ring_buffer;
 pub mod types;
-mod event;
+pub mod worker_pool;
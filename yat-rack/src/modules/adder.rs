@@ -1,9 +1,7 @@
-use std::sync::{RwLock, Weak};
-
-use crate::modules::io_module::IoModule;
-use crate::types::{PortNotFoundError, PortResult, SampleType};
+use crate::modules::io_module::{self, IoModule};
+use crate::types::{AUDIO_BUF_SIZE, PortNotFoundError, PortResult, SampleType};
 use crate::in_port::InPort;
-use crate::out_port::OutPort;
+use crate::out_port::{OutPort, PortRef};
 
 /// A module which adds its input signals and outputs the result
 pub struct Adder {
@@ -22,6 +20,11 @@ pub struct Adder {
     in_b: InPort,
 
     out_sum: OutPort,
+
+    /// Scratch buffers for reading a block of each input
+    a_block: Vec<SampleType>,
+
+    b_block: Vec<SampleType>,
 }
 
 impl Adder {
@@ -29,11 +32,12 @@ impl Adder {
     pub fn new(id: String) -> Self {
         let order = None;
         let input_ports = vec!["a".to_string(), "b".to_string()];
-        let output_ports = vec!["result".to_string()];
+        let output_ports = vec!["sum".to_string()];
 
         let in_a = InPort::new("a".into(), SampleType::MIN, SampleType::MAX, 0.0);
         let in_b = InPort::new("b".into(), SampleType::MIN, SampleType::MAX, 0.0);
-        let out_sum = OutPort::new("sum".into());
+        let mut out_sum = OutPort::new("sum".into());
+        out_sum.set_description("a + b");
 
         Self {
             id,
@@ -43,6 +47,8 @@ impl Adder {
             in_a,
             in_b,
             out_sum,
+            a_block: vec![0.0; AUDIO_BUF_SIZE],
+            b_block: vec![0.0; AUDIO_BUF_SIZE],
         }
     }
 }
@@ -64,6 +70,19 @@ impl IoModule for Adder {
         self.out_sum.set_value(sum);
     }
 
+    /// Combine a block of both inputs
+    fn process_block(&mut self, frames: usize) {
+        io_module::process_binary_block(
+            &self.in_a,
+            &self.in_b,
+            &self.out_sum,
+            &mut self.a_block,
+            &mut self.b_block,
+            frames,
+            |a, b| a + b,
+        );
+    }
+
     /// Return a module's ID
     fn get_id(&self) -> &String {
         &self.id
@@ -79,9 +98,24 @@ impl IoModule for Adder {
 
     /// Return a reference to one of the module's input ports
     fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
         match port_id {
-            "a" | "b" => true,
-            _ => false,
+            "a" => Some(&self.in_a),
+            "b" => Some(&self.in_b),
+            _ => None,
+        }
+    }
+
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
+        match port_id {
+            "a" => Some(&mut self.in_a),
+            "b" => Some(&mut self.in_b),
+            _ => None,
         }
     }
 
@@ -93,7 +127,7 @@ impl IoModule for Adder {
     }
 
     /// Set the value of a module's input port
-    fn set_in_port(&mut self, port_id: &str, out_port_ref: Weak<RwLock<Option<SampleType>>>) -> PortResult<String> {
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
         match port_id {
             "a" => self.in_a.set_value(out_port_ref),
             "b" => self.in_b.set_value(out_port_ref),
@@ -1,10 +1,11 @@
-use std::sync::{Arc, RwLock, Weak};
+use std::sync::{Arc, RwLock};
 
 use crate::clock::Clock;
 use crate::modules::io_module::IoModule;
-use crate::types::{PortNotFoundError, PortResult, SampleType};
+use crate::types::{AUDIO_BUF_SIZE, PortNotFoundError, PortResult, SampleType};
 use crate::in_port::InPort;
-use crate::out_port::OutPort;
+use crate::out_port::{OutPort, PortRef};
+use crate::port_descriptor::PortUnit;
 
 #[derive(PartialEq, Eq)]
 enum AdsrState {
@@ -15,6 +16,15 @@ enum AdsrState {
     Release,
 }
 
+/// The values of the ADSR's inputs for a single frame
+struct AdsrInputs {
+    gate: SampleType,
+    attack: SampleType,
+    decay: SampleType,
+    sustain: SampleType,
+    release: SampleType,
+}
+
 /// An ADSR (Attack Decay Sustain Release) envelope generator
 pub struct Adsr {
     /// A unique string used for identifying the module
@@ -62,6 +72,9 @@ pub struct Adsr {
     /// Signal level at the time the gate is released. This is used to
     /// smoothly transition from any state in the ADSR to zero.
     pre_release_sig: SampleType,
+
+    /// Scratch buffers for reading a block of each input, in the order of `input_ports`
+    in_blocks: Vec<Vec<SampleType>>,
 }
 
 impl Adsr {
@@ -75,12 +88,30 @@ impl Adsr {
 
         let time_delta = clock.read().expect("RwLock is poisoned").time_delta;
 
-        let in_gate = InPort::new("gate".into(), 0.0, 1.0, 0.0);
-        let in_attack = InPort::new("attack".into(), 0.0, 1.0, time_delta);
-        let in_decay = InPort::new("decay".into(), 0.0, 1.0, time_delta);
-        let in_sustain = InPort::new("sustain".into(), 0.0, 1.0, 1.0);
-        let in_release = InPort::new("release".into(), 0.0, 1.0, time_delta);
-        let out_signal_out = OutPort::new("signal_out".into());
+        let mut in_gate = InPort::new("gate".into(), 0.0, 1.0, 0.0);
+        in_gate.set_unit(PortUnit::Gate);
+        in_gate.set_description("Starts the envelope when on, and releases it when off");
+
+        let mut in_attack = InPort::new("attack".into(), 0.0, 1.0, time_delta);
+        in_attack.set_unit(PortUnit::Seconds);
+        in_attack.set_description("The time until the full amplitude is reached");
+
+        let mut in_decay = InPort::new("decay".into(), 0.0, 1.0, time_delta);
+        in_decay.set_unit(PortUnit::Seconds);
+        in_decay.set_description("The time until the amplitude decays to the sustain level");
+
+        let mut in_sustain = InPort::new("sustain".into(), 0.0, 1.0, 1.0);
+        in_sustain.set_unit(PortUnit::Gain);
+        in_sustain.set_description("The amplitude held while the gate is on");
+
+        let mut in_release = InPort::new("release".into(), 0.0, 1.0, time_delta);
+        in_release.set_unit(PortUnit::Seconds);
+        in_release.set_description("The time until the amplitude reaches zero after the gate is off");
+
+        let mut out_signal_out = OutPort::new("signal_out".into());
+        out_signal_out.set_range(0.0, 1.0);
+        out_signal_out.set_unit(PortUnit::Gain);
+        out_signal_out.set_description("The envelope");
 
         let active_time = 0f64;
         let gate_trigger_time = 0f64;
@@ -104,115 +135,170 @@ impl Adsr {
             adsr_state,
             clock,
             pre_release_sig,
+            in_blocks: vec![vec![0.0; AUDIO_BUF_SIZE]; 5],
         }
     }
-}
 
-impl PartialEq for Adsr {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
-    }
-}
-
-impl IoModule for Adsr {
-    /// Read inputs and populate outputs
-    fn process_inputs(&mut self) {
-        let gate_active = self.in_gate.get_value() != 0.0;
+    /// Advance the envelope by one frame and return its next sample
+    fn next_sample(&mut self, clock: &Clock, inputs: &AdsrInputs) -> SampleType {
+        let gate_active = inputs.gate != 0.0;
 
         // no key is active
         if (self.adsr_state == AdsrState::Inactive) && (!gate_active) {
-            self.out_signal_out.set_value(0.0);
-        } else {
-            let clock = self.clock.read().expect("RwLock is poisoned");
+            return 0.0;
+        }
 
-            let sustain_amp = self.in_sustain.get_value();
+        let sustain_amp = inputs.sustain;
 
-            // This makes sense as a default value, in case attack and decay are zero
-            let mut signal_out = sustain_amp;
+        // This makes sense as a default value, in case attack and decay are zero
+        let mut signal_out = sustain_amp;
 
-            match self.adsr_state {
-                AdsrState::Inactive => {
-                    if gate_active {
-                        self.gate_trigger_time = clock.get_current_time().unwrap();
-                        self.active_time = 0f64;
-                        self.adsr_state = AdsrState::Attack;
-                    }
+        match self.adsr_state {
+            AdsrState::Inactive => {
+                if gate_active {
+                    self.gate_trigger_time = clock.get_current_time().unwrap();
+                    self.active_time = 0f64;
+                    self.adsr_state = AdsrState::Attack;
                 }
-                AdsrState::Attack => {
-                    // Transition to max amplitude, and change state to decay after time
-                    // If released, go straight to that
-                    // Effectively set to zero, but avoiding potential zero division
-                    let attack = self.in_attack.get_value();
-
-                    if !gate_active {
-                        self.pre_release_sig = self.active_time / attack;
-                        self.active_time = 0f64;
-                        self.adsr_state = AdsrState::Release;
-                    } else if self.active_time >= attack {
-                        self.active_time = 0f64;
-                        self.adsr_state = AdsrState::Decay;
-                    } else {
-                        // Gradually increase amplitude to max
-                        signal_out = self.active_time / attack;
-                    }
+            }
+            AdsrState::Attack => {
+                // Transition to max amplitude, and change state to decay after time
+                // If released, go straight to that
+                // Effectively set to zero, but avoiding potential zero division
+                let attack = inputs.attack;
+
+                if !gate_active {
+                    self.pre_release_sig = self.active_time / attack;
+                    self.active_time = 0f64;
+                    self.adsr_state = AdsrState::Release;
+                } else if self.active_time >= attack {
+                    self.active_time = 0f64;
+                    self.adsr_state = AdsrState::Decay;
+                } else {
+                    // Gradually increase amplitude to max
+                    signal_out = self.active_time / attack;
                 }
-                AdsrState::Decay => {
-                    // Transition to sustain amplitude
-                    // Effectively set to zero, but avoiding potential zero division
-                    let decay = self.in_decay.get_value();
-
-                    if !gate_active {
-                        self.pre_release_sig =
-                            1f64 - ((self.active_time * (1f64 - sustain_amp)) / decay);
-                        self.active_time = 0f64;
-                        self.adsr_state = AdsrState::Release;
-                    } else if self.active_time >= decay {
-                        self.active_time = 0f64;
-                        self.adsr_state = AdsrState::Sustain;
-                    } else {
-                        let sustain_amp = self.in_sustain.get_value();
-
-                        // Decay to sustain amplitude
-                        signal_out = 1f64 - ((self.active_time * (1f64 - sustain_amp)) / decay);
-                    }
+            }
+            AdsrState::Decay => {
+                // Transition to sustain amplitude
+                // Effectively set to zero, but avoiding potential zero division
+                let decay = inputs.decay;
+
+                if !gate_active {
+                    self.pre_release_sig =
+                        1f64 - ((self.active_time * (1f64 - sustain_amp)) / decay);
+                    self.active_time = 0f64;
+                    self.adsr_state = AdsrState::Release;
+                } else if self.active_time >= decay {
+                    self.active_time = 0f64;
+                    self.adsr_state = AdsrState::Sustain;
+                } else {
+                    // Decay to sustain amplitude
+                    signal_out = 1f64 - ((self.active_time * (1f64 - sustain_amp)) / decay);
                 }
-                AdsrState::Sustain => {
-                    // Output at sustain level while gate is active
-                    if !gate_active {
-                        self.pre_release_sig = sustain_amp;
-                        self.active_time = 0f64;
-                        self.adsr_state = AdsrState::Release;
-                    } else {
-                        signal_out = sustain_amp;
-                    }
+            }
+            AdsrState::Sustain => {
+                // Output at sustain level while gate is active
+                if !gate_active {
+                    self.pre_release_sig = sustain_amp;
+                    self.active_time = 0f64;
+                    self.adsr_state = AdsrState::Release;
+                } else {
+                    signal_out = sustain_amp;
                 }
-                AdsrState::Release => {
-                    if gate_active {
+            }
+            AdsrState::Release => {
+                if gate_active {
+                    self.active_time = 0f64;
+                    self.adsr_state = AdsrState::Attack;
+                } else {
+                    // Effectively set to zero, but avoiding potential zero division
+                    let release = inputs.release;
+
+                    // Decay to zero
+                    if self.active_time >= release {
                         self.active_time = 0f64;
-                        self.adsr_state = AdsrState::Attack;
+                        self.adsr_state = AdsrState::Inactive;
                     } else {
-                        // Effectively set to zero, but avoiding potential zero division
-                        let release = self.in_release.get_value();
-
-                        // Decay to zero
-                        if self.active_time >= release {
-                            self.active_time = 0f64;
-                            self.adsr_state = AdsrState::Inactive;
-                        } else {
-                            signal_out = (1f64 - (self.active_time / release)) * self.pre_release_sig;
-                        }
+                        signal_out = (1f64 - (self.active_time / release)) * self.pre_release_sig;
                     }
                 }
             }
+        }
+
+        // Note: while it's technically incorrect to increment here,
+        // as it occurs between state transitions,
+        // it prevents a bunch of handling of zero division and
+        // only increase the active time by an insignificant value
+        self.active_time += clock.time_delta;
+
+        signal_out
+    }
+}
+
+
+impl PartialEq for Adsr {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
 
-            // Note: while it's technically incorrect to increment here,
-            // as it occurs between state transitions,
-            // it prevents a bunch of handling of zero division and
-            // only increase the active time by an insignificant value
-            self.active_time += clock.time_delta;
+impl IoModule for Adsr {
+    /// Read inputs and populate outputs
+    fn process_inputs(&mut self) {
+        let inputs = AdsrInputs {
+            gate: self.in_gate.get_value(),
+            attack: self.in_attack.get_value(),
+            decay: self.in_decay.get_value(),
+            sustain: self.in_sustain.get_value(),
+            release: self.in_release.get_value(),
+        };
+
+        let clock = Arc::clone(&self.clock);
+        let clock = clock.read().expect("RwLock is poisoned");
+        let signal_out = self.next_sample(&clock, &inputs);
+
+        self.out_signal_out.set_value(signal_out);
+    }
 
-            self.out_signal_out.set_value(signal_out);
+    /// Run the envelope over a block, writing it over the gate block
+    fn process_block(&mut self, frames: usize) {
+        let mut blocks = std::mem::take(&mut self.in_blocks);
+        let ports = [
+            &self.in_gate,
+            &self.in_attack,
+            &self.in_decay,
+            &self.in_sustain,
+            &self.in_release,
+        ];
+        for (port, block) in ports.iter().zip(blocks.iter_mut()) {
+            port.read_block(&mut block[..frames]);
         }
+
+        let clock = Arc::clone(&self.clock);
+        let clock = clock.read().expect("RwLock is poisoned");
+        let (gate_block, blocks_rest) = blocks.split_at_mut(1);
+        for (frame, signal) in gate_block[0][..frames].iter_mut().enumerate() {
+            let inputs = AdsrInputs {
+                gate: *signal,
+                attack: blocks_rest[0][frame],
+                decay: blocks_rest[1][frame],
+                sustain: blocks_rest[2][frame],
+                release: blocks_rest[3][frame],
+            };
+            *signal = self.next_sample(&clock, &inputs);
+        }
+        self.out_signal_out.write_block(&gate_block[0][..frames]);
+
+        self.in_blocks = blocks;
+    }
+
+    /// End any envelope in progress. The clock's time delta already follows the new rate
+    fn set_sample_rate(&mut self, _sample_rate: SampleType) {
+        self.active_time = 0f64;
+        self.gate_trigger_time = 0f64;
+        self.adsr_state = AdsrState::Inactive;
+        self.pre_release_sig = 0f64;
     }
 
     /// Return a module's ID
@@ -230,10 +316,30 @@ impl IoModule for Adsr {
 
     /// Return a reference to one of the module's input ports
     fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
         match port_id {
-            "gate" | "attack" | "decay"
-                | "sustain" | "release" => true,
-            _ => false,
+            "gate" => Some(&self.in_gate),
+            "attack" => Some(&self.in_attack),
+            "decay" => Some(&self.in_decay),
+            "sustain" => Some(&self.in_sustain),
+            "release" => Some(&self.in_release),
+            _ => None,
+        }
+    }
+
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
+        match port_id {
+            "gate" => Some(&mut self.in_gate),
+            "attack" => Some(&mut self.in_attack),
+            "decay" => Some(&mut self.in_decay),
+            "sustain" => Some(&mut self.in_sustain),
+            "release" => Some(&mut self.in_release),
+            _ => None,
         }
     }
 
@@ -245,7 +351,7 @@ impl IoModule for Adsr {
     }
 
     /// Set the value of a module's input port
-    fn set_in_port(&mut self, port_id: &str, out_port_ref: Weak<RwLock<Option<SampleType>>>) -> PortResult<String> {
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
         match port_id {
             "gate" => self.in_gate.set_value(out_port_ref),
             "attack" => self.in_attack.set_value(out_port_ref),
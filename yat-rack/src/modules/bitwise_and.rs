@@ -1,9 +1,10 @@
-use std::sync::{Arc, RwLock};
+use crate::in_port::InPort;
+use crate::modules::io_module::{self, IoModule};
+use crate::out_port::{OutPort, PortRef};
+use crate::types::{PortNotFoundError, PortResult, SampleType, AUDIO_BUF_SIZE};
 
-use crate::types::{IoPort, PortResult, PortNotFoundError};
-use crate::modules::io_module::IoModule;
-
-/// A module which adds its input signals and outputs the result
+/// A module which outputs the bitwise AND of its inputs (a and b). The inputs are
+/// truncated to integers first
 pub struct BitwiseAnd {
     /// A unique string used for identifying the module
     id: String,
@@ -15,23 +16,29 @@ pub struct BitwiseAnd {
 
     output_ports: Vec<String>,
 
-    in_a: IoPort,
+    in_a: InPort,
+
+    in_b: InPort,
 
-    in_b: IoPort,
+    out_result: OutPort,
 
-    out_result: IoPort,
+    /// Scratch buffers for reading a block of each input
+    a_block: Vec<SampleType>,
+
+    b_block: Vec<SampleType>,
 }
 
 impl BitwiseAnd {
     /// Create a new, unordered IoModule
     pub fn new(id: String) -> Self {
         let order = None;
-        let input_ports = vec!["in_a".to_string(), "in_b".to_string()];
+        let input_ports = vec!["a".to_string(), "b".to_string()];
         let output_ports = vec!["result".to_string()];
 
-        let in_a = Arc::new(RwLock::new(None));
-        let in_b = Arc::new(RwLock::new(None));
-        let out_result = Arc::new(RwLock::new(None));
+        let in_a = InPort::new("a".into(), SampleType::MIN, SampleType::MAX, 0.0);
+        let in_b = InPort::new("b".into(), SampleType::MIN, SampleType::MAX, 0.0);
+        let mut out_result = OutPort::new("result".into());
+        out_result.set_description("a & b");
 
         Self {
             id,
@@ -41,27 +48,40 @@ impl BitwiseAnd {
             in_a,
             in_b,
             out_result,
+            a_block: vec![0.0; AUDIO_BUF_SIZE],
+            b_block: vec![0.0; AUDIO_BUF_SIZE],
         }
     }
-
 }
 
 impl PartialEq for BitwiseAnd {
     fn eq(&self, other: &Self) -> bool {
-            self.id == other.id
+        self.id == other.id
     }
 }
 
 impl IoModule for BitwiseAnd {
     /// Read inputs and populate outputs
     fn process_inputs(&mut self) {
-        let a = self.in_a.read().expect("RwLock is poisoned").unwrap_or(0f64);
-        let b = self.in_b.read().expect("RwLock is poisoned").unwrap_or(0f64);
+        let a = self.in_a.get_value();
+        let b = self.in_b.get_value();
 
-        let result = a & b;
+        let result = (a as i64) & (b as i64);
 
-        let mut value = self.out_result.write().expect("RwLock is poisoned");
-        *value = Some(result);
+        self.out_result.set_value(result as SampleType);
+    }
+
+    /// Combine a block of both inputs
+    fn process_block(&mut self, frames: usize) {
+        io_module::process_binary_block(
+            &self.in_a,
+            &self.in_b,
+            &self.out_result,
+            &mut self.a_block,
+            &mut self.b_block,
+            frames,
+            |a, b| ((a as i64) & (b as i64)) as SampleType,
+        );
     }
 
     /// Return a module's ID
@@ -78,27 +98,41 @@ impl IoModule for BitwiseAnd {
     }
 
     /// Return a reference to one of the module's input ports
-    fn get_in_port_ref(&self, port_id: &str) -> Option<IoPort> {
+    fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
         match port_id {
-            "a" => Some(self.in_a.clone()),
-            "b" => Some(self.in_b.clone()),
+            "a" => Some(&self.in_a),
+            "b" => Some(&self.in_b),
             _ => None,
         }
     }
 
-    fn get_out_port_ref(&self, port_id: &str) -> Option<IoPort> {
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
         match port_id {
-            "result" => Some(self.out_result.clone()),
+            "a" => Some(&mut self.in_a),
+            "b" => Some(&mut self.in_b),
+            _ => None,
+        }
+    }
+
+    fn get_out_port_ref(&self, port_id: &str) -> Option<&OutPort> {
+        match port_id {
+            "result" => Some(&self.out_result),
             _ => None,
         }
     }
 
     /// Set the value of a module's input port
-    fn set_in_port(&mut self, port_id: &str, out_port: IoPort) -> PortResult<String> {
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
         match port_id {
-            "a" => self.in_a = out_port.clone(),
-            "b" => self.in_b = out_port.clone(),
-            _ => { return Err(PortNotFoundError) },
+            "a" => self.in_a.set_value(out_port_ref),
+            "b" => self.in_b.set_value(out_port_ref),
+            _ => return Err(PortNotFoundError),
         }
 
         Ok(format!("{}: Set port {}\n", self.get_id(), port_id))
@@ -112,4 +146,3 @@ impl IoModule for BitwiseAnd {
         self.order = new_order;
     }
 }
-
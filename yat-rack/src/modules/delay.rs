@@ -1,10 +1,14 @@
-use std::sync::{Arc, RwLock};
-
-use crate::clock::Clock;
-use crate::types::{IoPort,SampleType, PortResult, PortNotFoundError};
+use crate::in_port::InPort;
 use crate::modules::io_module::IoModule;
+use crate::out_port::{OutPort, PortRef};
+use crate::port_descriptor::PortUnit;
+use crate::types::{PortNotFoundError, PortResult, SampleType, AUDIO_BUF_SIZE};
+
+/// The longest delay, in seconds
+const MAX_DELAY: SampleType = 2.0;
 
-/// A module that outputs it's input at a delayed time
+/// A module which outputs its input some time later, e.g. to line a signal up with the audio
+/// input, see the `latency` port of `Input`
 pub struct Delay {
     /// A unique string used for identifying the module
     id: String,
@@ -16,80 +20,120 @@ pub struct Delay {
 
     output_ports: Vec<String>,
 
-    /// The input audio signal
-    in_audio: IoPort,
-
-    /// The signal which informs the envelope generator that a note is
-    /// active
-    in_trigger: IoPort,
+    in_signal_in: InPort,
 
     /// The delay time (seconds)
-    in_delay_time: IoPort,
+    in_time: InPort,
+
+    out_signal_out: OutPort,
 
-    /// The modulated output signal of the envelope generator
-    out_audio_out: IoPort,
+    /// The samples of the last `MAX_DELAY` seconds, written in a circle
+    line: Vec<SampleType>,
 
-    /// The current time for which a note has been active
-    active_time: SampleType,
+    /// The position in `line` which the next sample is written to
+    write_pos: usize,
 
-    /// The time at which a note was triggered
-    trigger_time: SampleType,
+    /// The number of frames per second
+    sample_rate: SampleType,
 
-    /// Time of the rack's clock
-    clock: Arc<RwLock<Clock>>,
+    /// Scratch buffers for reading a block of each input
+    signal_block: Vec<SampleType>,
+
+    time_block: Vec<SampleType>,
 }
 
 impl Delay {
     /// Create a new, unordered IoModule
-    pub fn new(id: String, time: Arc<RwLock<Clock>>) -> Self {
+    pub fn new(id: String, sample_rate: SampleType) -> Self {
         let order = None;
-        let input_ports = vec!["amp".to_string(), "freq".to_string()];
-        let output_ports = vec!["audio_out".to_string()];
+        let input_ports = vec!["signal_in".to_string(), "time".to_string()];
+        let output_ports = vec!["signal_out".to_string()];
+
+        let mut in_signal_in = InPort::new("signal_in".into(), -1.0, 1.0, 0.0);
+        in_signal_in.set_description("The signal to delay");
 
-        let in_audio = Arc::new(RwLock::new(None));
-        let in_trigger = Arc::new(RwLock::new(None));
-        let in_delay_time = Arc::new(RwLock::new(None));
-        let out_audio_out = Arc::new(RwLock::new(None));
+        let mut in_time = InPort::new("time".into(), 0.0, MAX_DELAY, 0.0);
+        in_time.set_unit(PortUnit::Seconds);
+        in_time.set_description("The time until the signal comes out, rounded to a frame");
 
-        let active_time = 0f64;
-        let trigger_time = 0f64;
+        let mut out_signal_out = OutPort::new("signal_out".into());
+        out_signal_out.set_range(-1.0, 1.0);
+        out_signal_out.set_description("The delayed signal");
 
         Self {
             id,
             order,
             input_ports,
             output_ports,
-            in_audio,
-            in_trigger,
-            in_attack,
-            in_decay,
-            in_sustain,
-            in_release,
-            out_audio_out,
-            active_time,
-            trigger_time,
-            clock: time,
+            in_signal_in,
+            in_time,
+            out_signal_out,
+            line: Self::line(sample_rate),
+            write_pos: 0,
+            sample_rate,
+            signal_block: vec![0.0; AUDIO_BUF_SIZE],
+            time_block: vec![0.0; AUDIO_BUF_SIZE],
         }
     }
 
+    /// A silent line long enough for the longest delay at the given rate
+    fn line(sample_rate: SampleType) -> Vec<SampleType> {
+        vec![0.0; (MAX_DELAY * sample_rate) as usize + 1]
+    }
+
+    /// Write a sample to the line and return the one from the given time ago
+    fn next_sample(&mut self, sample: SampleType, time: SampleType) -> SampleType {
+        let length = self.line.len();
+        let frames = (time.clamp(0.0, MAX_DELAY) * self.sample_rate).round() as usize;
+
+        self.line[self.write_pos] = sample;
+        let delayed = self.line[(self.write_pos + length - frames.min(length - 1)) % length];
+        self.write_pos = (self.write_pos + 1) % length;
+
+        delayed
+    }
 }
 
 impl PartialEq for Delay {
     fn eq(&self, other: &Self) -> bool {
-            self.id == other.id
+        self.id == other.id
     }
 }
 
 impl IoModule for Delay {
     /// Read inputs and populate outputs
-    // TODO: Write this function
     fn process_inputs(&mut self) {
+        let signal_in = self.in_signal_in.get_value();
+
+        let time = self.in_time.get_value();
+
+        let signal_out = self.next_sample(signal_in, time);
+
+        self.out_signal_out.set_value(signal_out);
+    }
 
+    /// Delay a block of the input, writing it over the signal block
+    fn process_block(&mut self, frames: usize) {
+        let mut signal_block = std::mem::take(&mut self.signal_block);
+        let mut time_block = std::mem::take(&mut self.time_block);
 
-        self.active_time += clock.time_delta;
+        self.in_signal_in.read_block(&mut signal_block[..frames]);
+        self.in_time.read_block(&mut time_block[..frames]);
 
-        let mut value = self.out_audio_out.write().expect("RwLock is poisoned");
-        *value = Some(audio_out);
+        for (sample, time) in signal_block[..frames].iter_mut().zip(time_block.iter()) {
+            *sample = self.next_sample(*sample, *time);
+        }
+        self.out_signal_out.write_block(&signal_block[..frames]);
+
+        self.signal_block = signal_block;
+        self.time_block = time_block;
+    }
+
+    /// Start with a silent line at the new sample rate
+    fn set_sample_rate(&mut self, sample_rate: SampleType) {
+        self.sample_rate = sample_rate;
+        self.line = Self::line(sample_rate);
+        self.write_pos = 0;
     }
 
     /// Return a module's ID
@@ -106,36 +150,41 @@ impl IoModule for Delay {
     }
 
     /// Return a reference to one of the module's input ports
-    fn get_in_port_ref(&self, port_id: &str) -> Option<IoPort> {
+    fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
         match port_id {
-            "audio_in" => Some(self.in_audio.clone()),
-            "trigger" => Some(self.in_trigger.clone()),
-            "attack" => Some(self.in_attack.clone()),
-            "decay" => Some(self.in_decay.clone()),
-            "sustain" => Some(self.in_sustain.clone()),
-            "release" => Some(self.in_release.clone()),
+            "signal_in" => Some(&self.in_signal_in),
+            "time" => Some(&self.in_time),
             _ => None,
         }
     }
 
-    fn get_out_port_ref(&self, port_id: &str) -> Option<IoPort> {
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
         match port_id {
-            "audio_out" => Some(self.out_audio_out.clone()),
+            "signal_in" => Some(&mut self.in_signal_in),
+            "time" => Some(&mut self.in_time),
             _ => None,
         }
     }
 
-    /// Set the value of a module's input port
-    fn set_in_port(&mut self, port_id: &str, out_port: IoPort) -> PortResult<String> {
+    fn get_out_port_ref(&self, port_id: &str) -> Option<&OutPort> {
+        match port_id {
+            "signal_out" => Some(&self.out_signal_out),
+            _ => None,
+        }
+    }
 
+    /// Set the value of a module's input port
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
         match port_id {
-           "audio_in" => self.in_audio = out_port.clone(),
-           "trigger" => self.in_trigger = out_port.clone(),
-           "attack" => self.in_attack = out_port.clone(),
-           "decay" => self.in_decay = out_port.clone(),
-           "sustain" => self.in_sustain = out_port.clone(),
-           "release" => self.in_release = out_port.clone(),
-            _ => { return Err(PortNotFoundError) },
+            "signal_in" => self.in_signal_in.set_value(out_port_ref),
+            "time" => self.in_time.set_value(out_port_ref),
+            _ => return Err(PortNotFoundError),
         }
 
         Ok(format!("{}: Set port {}\n", self.get_id(), port_id))
@@ -150,3 +199,24 @@ impl IoModule for Delay {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_its_input_the_given_time_later() {
+        let signal = OutPort::new("signal".into());
+        signal.write_block(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let time = OutPort::new("time".into());
+        time.fill_value(0.002);
+
+        let mut delay = Delay::new("d".into(), 1000.0);
+        delay.set_in_port("signal_in", signal.get_ref()).unwrap();
+        delay.set_in_port("time", time.get_ref()).unwrap();
+        delay.process_block(6);
+
+        let delayed = delay.out_signal_out.get_ref().upgrade().unwrap();
+        let delayed: Vec<_> = (0..6).map(|frame| delayed.get(frame).unwrap()).collect();
+        assert_eq!(delayed, [0.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+    }
+}
@@ -1,12 +1,10 @@
-use std::sync::{RwLock, Weak};
-
 use crate::in_port::InPort;
-use crate::modules::io_module::IoModule;
-use crate::out_port::OutPort;
-use crate::types::{PortNotFoundError, PortResult, SampleType};
+use crate::modules::io_module::{self, IoModule};
+use crate::out_port::{OutPort, PortRef};
+use crate::types::{PortNotFoundError, PortResult, SampleType, AUDIO_BUF_SIZE};
 
-/// A module which divides one input (in_a) by
-/// another (in_b) and outputs the result
+/// A module which divides one input (a) by
+/// another (b) and outputs the result
 pub struct Divider {
     /// A unique string used for identifying the module
     id: String,
@@ -23,18 +21,24 @@ pub struct Divider {
     in_b: InPort,
 
     out_div: OutPort,
+
+    /// Scratch buffers for reading a block of each input
+    a_block: Vec<SampleType>,
+
+    b_block: Vec<SampleType>,
 }
 
 impl Divider {
     /// Create a new, unordered IoModule
     pub fn new(id: String) -> Self {
         let order = None;
-        let input_ports = vec!["in_a".to_string(), "in_b".to_string()];
+        let input_ports = vec!["a".to_string(), "b".to_string()];
         let output_ports = vec!["result".to_string()];
 
-        let in_a = InPort::new("a".into(), 1.0, 1.0, 1.0);
-        let in_b = InPort::new("b".into(), 1.0, 1.0, 1.0);
-        let out_div = OutPort::new("div".into());
+        let in_a = InPort::new("a".into(), SampleType::MIN, SampleType::MAX, 1.0);
+        let in_b = InPort::new("b".into(), SampleType::MIN, SampleType::MAX, 1.0);
+        let mut out_div = OutPort::new("result".into());
+        out_div.set_description("a / b");
 
         Self {
             id,
@@ -44,6 +48,8 @@ impl Divider {
             in_a,
             in_b,
             out_div,
+            a_block: vec![0.0; AUDIO_BUF_SIZE],
+            b_block: vec![0.0; AUDIO_BUF_SIZE],
         }
     }
 }
@@ -65,6 +71,19 @@ impl IoModule for Divider {
         self.out_div.set_value(div);
     }
 
+    /// Combine a block of both inputs
+    fn process_block(&mut self, frames: usize) {
+        io_module::process_binary_block(
+            &self.in_a,
+            &self.in_b,
+            &self.out_div,
+            &mut self.a_block,
+            &mut self.b_block,
+            frames,
+            |a, b| a / b,
+        );
+    }
+
     /// Return a module's ID
     fn get_id(&self) -> &String {
         &self.id
@@ -80,9 +99,24 @@ impl IoModule for Divider {
 
     /// Return a reference to one of the module's input ports
     fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
         match port_id {
-            "a" | "b" => true,
-            _ => false,
+            "a" => Some(&self.in_a),
+            "b" => Some(&self.in_b),
+            _ => None,
+        }
+    }
+
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
+        match port_id {
+            "a" => Some(&mut self.in_a),
+            "b" => Some(&mut self.in_b),
+            _ => None,
         }
     }
 
@@ -94,7 +128,7 @@ impl IoModule for Divider {
     }
 
     /// Set the value of a module's input port
-    fn set_in_port(&mut self, port_id: &str, out_port_ref: Weak<RwLock<Option<SampleType>>>) -> PortResult<String> {
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
         match port_id {
             "a" => self.in_a.set_value(out_port_ref),
             "b" => self.in_b.set_value(out_port_ref),
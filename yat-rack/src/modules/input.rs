@@ -0,0 +1,472 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+use crate::in_port::InPort;
+use crate::out_port::{OutPort, PortRef};
+use crate::modules::io_module::IoModule;
+use crate::port_descriptor::PortUnit;
+use crate::ring_buffer::{self, Consumer, Producer};
+use crate::types::{PortNotFoundError, PortResult, SampleType, AUDIO_BUF_SIZE, SAMPLE_RATE};
+
+/// The latencies of the capture and playback devices, in frames, as last measured by their
+/// streams. The audio threads update them while they run, and keep doing so when a device is
+/// reopened, so the Rack always sees the current ones
+#[derive(Clone, Default)]
+pub struct DeviceLatency {
+    /// The time from capturing a frame to handing it to the Rack
+    pub capture: Arc<AtomicU64>,
+
+    /// The time from the Rack writing a frame to playing it, including the frames queued in
+    /// the output's ring buffer
+    pub playback: Arc<AtomicU64>,
+}
+
+impl DeviceLatency {
+    /// Returns the capture and playback latencies
+    pub fn get_frames(&self) -> (u64, u64) {
+        (self.capture.load(Relaxed), self.playback.load(Relaxed))
+    }
+}
+
+/// An entry point into a Rack, e.g. for audio input from a capture device.
+///
+/// This is the counterpart of `Output`. Frames of interleaved channels are read from a ring
+/// buffer, and each channel has its own output port, i.e., "left" and "right" for stereo, or
+/// "ch_1" to "ch_N" otherwise. The "signal_out" port carries the average of all channels.
+///
+/// The capture device and the Rack run on separate clocks, so samples would otherwise pile up
+/// in, or run out of, the ring buffer. The module keeps a fixed number of frames buffered
+/// instead: it waits until that many frames have been captured before passing any on, and drops
+/// the oldest frames if more than a block too many pile up.
+///
+/// By the time it's heard, the input lags behind the Rack's other signals by the round trip
+/// through both devices, i.e., the capture latency, the buffered frames and the playback
+/// latency. The "latency" port carries that time in seconds, so a patch can line other signals
+/// up with the input by delaying them, e.g. with a `delay` module.
+pub struct Input {
+    /// A unique string used for identifying the module
+    id: String,
+
+    /// Order of the module in the chain, where 0 (zero) means skipped
+    order: Option<u64>,
+
+    input_ports: Vec<String>,
+
+    output_ports: Vec<String>,
+
+    out_signal_out: OutPort,
+
+    /// An output port for each channel. A single channel only has `out_signal_out`
+    out_channels: Vec<OutPort>,
+
+    /// The round trip from capturing to playing a frame, in seconds
+    out_latency: OutPort,
+
+    /// The latencies of the devices, as measured by the audio threads
+    device_latency: DeviceLatency,
+
+    /// The number of frames per second
+    sample_rate: SampleType,
+
+    /// The number of channels
+    channels: usize,
+
+    /// A ring buffer for receiving data from outside the rack, e.g. from an audio callback
+    in_signal_rx: Consumer,
+
+    /// The number of frames kept buffered in the ring buffer
+    buffered_frames: usize,
+
+    /// Whether enough frames have been buffered to start reading. This is reset when the ring
+    /// buffer runs dry, so that the buffer is filled up again
+    primed: bool,
+
+    /// Scratch buffer for the interleaved frames read from the ring buffer
+    frames: Vec<SampleType>,
+
+    /// Scratch buffer for writing a block of one channel's output
+    channel_block: Vec<SampleType>,
+}
+
+impl Input {
+    /// Create a new, unordered IoModule with the given number of channels. The ring buffer
+    /// holds four blocks, which leaves room for a block of buffered frames and the capture
+    /// device's own buffering
+    pub fn new(id: String, channels: usize) -> (Self, Producer) {
+        let channels = channels.max(1);
+        let order = None;
+        let input_ports = Vec::new();
+
+        let mut out_signal_out = OutPort::new("signal_out".into());
+        out_signal_out.set_range(-1.0, 1.0);
+        out_signal_out.set_description("The average of all channels");
+        let out_channels: Vec<OutPort> = Self::channel_labels(channels)
+            .into_iter()
+            .map(|label| {
+                let mut port = OutPort::new(label);
+                port.set_range(-1.0, 1.0);
+                port.set_description("A single channel");
+                port
+            })
+            .collect();
+
+        let mut out_latency = OutPort::new("latency".into());
+        out_latency.set_range(0.0, SampleType::MAX);
+        out_latency.set_unit(PortUnit::Seconds);
+        out_latency.set_description("The round trip from capturing to playing the signal");
+
+        let mut output_ports = vec!["signal_out".to_string()];
+        output_ports.extend(out_channels.iter().map(|port| port.get_label().to_string()));
+        output_ports.push("latency".to_string());
+
+        let (signal_tx, in_signal_rx) = ring_buffer::ring_buffer(4 * AUDIO_BUF_SIZE * channels);
+        let buffered_frames = AUDIO_BUF_SIZE;
+        let primed = false;
+        let frames = vec![0.0; AUDIO_BUF_SIZE * channels];
+        let channel_block = vec![0.0; AUDIO_BUF_SIZE];
+
+        let input = Self {
+            id,
+            order,
+            input_ports,
+            output_ports,
+            out_signal_out,
+            out_channels,
+            out_latency,
+            device_latency: DeviceLatency::default(),
+            sample_rate: SAMPLE_RATE,
+            channels,
+            in_signal_rx,
+            buffered_frames,
+            primed,
+            frames,
+            channel_block,
+        };
+
+        (input, signal_tx)
+    }
+
+    /// Returns the number of channels
+    pub fn get_channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Replace the ring buffer, e.g. for a new capture stream, and return its writing side.
+    /// Frames are buffered up again before any are passed on
+    pub fn reopen(&mut self) -> Producer {
+        let (signal_tx, in_signal_rx) =
+            ring_buffer::ring_buffer(4 * AUDIO_BUF_SIZE * self.channels);
+        self.in_signal_rx = in_signal_rx;
+        self.primed = false;
+
+        signal_tx
+    }
+
+    /// Share the latencies which the audio threads measure, see `DeviceLatency`
+    pub fn set_device_latency(&mut self, device_latency: DeviceLatency) {
+        self.device_latency = device_latency;
+    }
+
+    /// Returns the round trip from capturing to playing a frame, in frames
+    pub fn get_latency(&self) -> u64 {
+        let (capture, playback) = self.device_latency.get_frames();
+        capture + self.buffered_frames as u64 + playback
+    }
+
+    /// Returns the number of frames kept buffered between the capture device and the Rack
+    pub fn get_buffered_frames(&self) -> usize {
+        self.buffered_frames
+    }
+
+    /// Set the number of frames kept buffered between the capture device and the Rack. Lower
+    /// values reduce the delay of the input, at the risk of running dry more often. The ring
+    /// buffer needs room for another block on top, so at most three blocks can be buffered
+    pub fn set_buffered_frames(
+        &mut self,
+        frames: usize,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let max_frames = 3 * AUDIO_BUF_SIZE;
+        if frames > max_frames {
+            return Err(format!(
+                "Can't buffer {} frames, the input buffers at most {}",
+                frames, max_frames
+            )
+            .into());
+        }
+
+        self.buffered_frames = frames;
+        self.primed = false;
+
+        Ok(format!("Input {} buffers {} frames", self.id, frames))
+    }
+
+    /// The labels of the per-channel output ports
+    fn channel_labels(channels: usize) -> Vec<String> {
+        match channels {
+            1 => Vec::new(),
+            2 => vec!["left".into(), "right".into()],
+            _ => (1..=channels).map(|channel| format!("ch_{}", channel)).collect(),
+        }
+    }
+
+    /// Read a number of interleaved frames into the scratch buffer, keeping the number of
+    /// buffered frames steady. Frames which haven't been captured yet are silent
+    fn read_frames(&mut self, frames: usize) {
+        let channels = self.channels;
+        let queued = self.in_signal_rx.len() / channels;
+
+        if !self.primed && queued >= self.buffered_frames + frames {
+            self.primed = true;
+        }
+
+        // Catch up with the capture device, if it has gotten more than a block ahead
+        let excess = queued.saturating_sub(self.buffered_frames + frames);
+        if self.primed && excess > frames {
+            self.in_signal_rx.skip(excess * channels);
+        }
+
+        let interleaved = &mut self.frames[..frames * channels];
+        interleaved.fill(0.0);
+
+        if self.primed {
+            for frame in interleaved.chunks_mut(channels) {
+                // Only take whole frames, so that the channels stay aligned
+                if self.in_signal_rx.len() < channels {
+                    self.primed = false;
+                    break;
+                }
+
+                for sample in frame.iter_mut() {
+                    *sample = self.in_signal_rx.pop().unwrap_or(0.0);
+                }
+            }
+        }
+    }
+}
+
+impl PartialEq for Input {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl IoModule for Input {
+    fn process_inputs(&mut self) {
+        self.read_frames(1);
+
+        let frame = &self.frames[..self.channels];
+        let signal_out = frame.iter().sum::<SampleType>() / self.channels as SampleType;
+        self.out_signal_out.set_value(signal_out);
+
+        for (port, sample) in self.out_channels.iter().zip(frame.iter()) {
+            port.set_value(*sample);
+        }
+
+        self.out_latency
+            .set_value(self.get_latency() as SampleType / self.sample_rate);
+    }
+
+    /// Receive a block of the input signal, splitting up the channels
+    fn process_block(&mut self, frames: usize) {
+        self.read_frames(frames);
+
+        let channels = self.channels;
+        let interleaved = &self.frames[..frames * channels];
+        let channel_block = &mut self.channel_block[..frames];
+
+        for (value, frame) in channel_block.iter_mut().zip(interleaved.chunks(channels)) {
+            *value = frame.iter().sum::<SampleType>() / channels as SampleType;
+        }
+        self.out_signal_out.write_block(channel_block);
+
+        for (channel, port) in self.out_channels.iter().enumerate() {
+            for (value, frame) in channel_block.iter_mut().zip(interleaved.chunks(channels)) {
+                *value = frame[channel];
+            }
+            port.write_block(channel_block);
+        }
+
+        self.out_latency
+            .fill_value(self.get_latency() as SampleType / self.sample_rate);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: SampleType) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Return a module's ID
+    fn get_id(&self) -> &String {
+        &self.id
+    }
+
+    fn get_in_ports(&self) -> &Vec<String> {
+        &self.input_ports
+    }
+
+    fn get_out_ports(&self) -> &Vec<String> {
+        &self.output_ports
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// The module has no input ports, as its signal comes from outside the rack
+    fn get_in_port_ref(&self, _port_id: &str) -> Option<&InPort> {
+        None
+    }
+
+    fn get_in_port_mut(&mut self, _port_id: &str) -> Option<&mut InPort> {
+        None
+    }
+
+    /// Return a reference to one of the module's output ports
+    fn get_out_port_ref(&self, port_id: &str) -> Option<&OutPort> {
+        match port_id {
+            "signal_out" => Some(&self.out_signal_out),
+            "latency" => Some(&self.out_latency),
+            _ => self
+                .out_channels
+                .iter()
+                .find(|port| port.get_label() == port_id),
+        }
+    }
+
+    fn set_in_port(&mut self, _port_id: &str, _out_port_ref: PortRef) -> PortResult<String> {
+        Err(PortNotFoundError)
+    }
+
+    fn get_module_order(&self) -> Option<u64> {
+        self.order
+    }
+
+    fn set_module_order(&mut self, new_order: Option<u64>) {
+        self.order = new_order;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stereo input, which keeps the given number of frames buffered
+    fn stereo_input(buffered_frames: usize) -> (Input, Producer) {
+        let (mut input, signal_tx) = Input::new("in".into(), 2);
+        input.set_buffered_frames(buffered_frames).unwrap();
+        (input, signal_tx)
+    }
+
+    /// Capture the given frames, numbered on the left channel and doubled on the right
+    fn capture(signal_tx: &Producer, frames: std::ops::Range<usize>) {
+        for frame in frames {
+            signal_tx.push(frame as SampleType);
+            signal_tx.push(2.0 * frame as SampleType);
+        }
+    }
+
+    /// The frames of a channel which the last block passed on
+    fn channel(input: &Input, port_id: &str, frames: usize) -> Vec<SampleType> {
+        let buffer = input
+            .get_out_port_ref(port_id)
+            .unwrap()
+            .get_ref()
+            .upgrade()
+            .unwrap();
+        (0..frames)
+            .map(|frame| buffer.get(frame).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn waits_for_the_buffered_frames_before_passing_any_on() {
+        let (mut input, signal_tx) = stereo_input(4);
+
+        capture(&signal_tx, 0..5);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [0.0, 0.0]);
+
+        capture(&signal_tx, 5..6);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [0.0, 1.0]);
+        assert_eq!(channel(&input, "right", 2), [0.0, 2.0]);
+        assert_eq!(channel(&input, "signal_out", 2), [0.0, 1.5]);
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_when_more_than_a_block_piles_up() {
+        let (mut input, signal_tx) = stereo_input(4);
+
+        // A block over the buffered frames is fine
+        capture(&signal_tx, 0..8);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [0.0, 1.0]);
+
+        // Three frames over that are too many, so they are skipped
+        capture(&signal_tx, 8..11);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [5.0, 6.0]);
+        assert_eq!(input.in_signal_rx.len(), 2 * 4);
+    }
+
+    #[test]
+    fn goes_silent_and_buffers_up_again_when_running_dry() {
+        let (mut input, signal_tx) = stereo_input(2);
+
+        capture(&signal_tx, 0..4);
+        input.process_block(2);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [2.0, 3.0]);
+
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [0.0, 0.0]);
+
+        // Frames are only passed on again once the buffer is filled up
+        capture(&signal_tx, 4..7);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [0.0, 0.0]);
+
+        capture(&signal_tx, 7..8);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [4.0, 5.0]);
+    }
+
+    #[test]
+    fn buffers_at_most_three_blocks() {
+        let (mut input, _signal_tx) = stereo_input(0);
+
+        assert!(input.set_buffered_frames(3 * AUDIO_BUF_SIZE).is_ok());
+        assert!(input.set_buffered_frames(3 * AUDIO_BUF_SIZE + 1).is_err());
+        assert_eq!(input.get_buffered_frames(), 3 * AUDIO_BUF_SIZE);
+    }
+
+    #[test]
+    fn carries_the_round_trip_through_both_devices() {
+        let (mut input, _signal_tx) = stereo_input(4);
+        let device_latency = DeviceLatency::default();
+        input.set_device_latency(device_latency.clone());
+        input.set_sample_rate(1000.0);
+
+        device_latency.capture.store(100, Relaxed);
+        device_latency.playback.store(200, Relaxed);
+        input.process_block(2);
+
+        assert_eq!(input.get_latency(), 304);
+        assert_eq!(channel(&input, "latency", 2), [0.304, 0.304]);
+    }
+
+    #[test]
+    fn buffers_up_again_once_reopened() {
+        let (mut input, signal_tx) = stereo_input(0);
+        capture(&signal_tx, 0..4);
+
+        let signal_tx = input.reopen();
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [0.0, 0.0]);
+
+        capture(&signal_tx, 10..12);
+        input.process_block(2);
+        assert_eq!(channel(&input, "left", 2), [10.0, 11.0]);
+    }
+}
@@ -1,12 +1,45 @@
-use std::sync::{RwLock, Weak};
-
+use crate::in_port::InPort;
+use crate::out_port::{OutPort, PortRef};
+use crate::port_descriptor::PortDescriptor;
 use crate::types::{PortResult, SampleType};
-use crate::out_port::OutPort;
 
 pub trait IoModule {
-    /// Calculate the module's outputs based on inputs
+    /// Calculate the module's outputs based on inputs, for a single frame
     fn process_inputs(&mut self);
 
+    /// Calculate the module's outputs for the first `frames` frames of a block.
+    ///
+    /// By default, this moves every port to each frame in turn and calls `process_inputs`,
+    /// so that per-sample modules work unchanged. This looks each port up by its ID for every
+    /// frame, so the built-in modules override it to work on whole blocks, using
+    /// `InPort::read_block` and `OutPort::write_block`.
+    fn process_block(&mut self, frames: usize) {
+        for frame in 0..frames {
+            self.seek_ports(frame);
+            self.process_inputs();
+        }
+    }
+
+    /// Move every port of the module to a frame of the block
+    fn seek_ports(&self, frame: usize) {
+        for port_id in self.get_in_ports() {
+            if let Some(port) = self.get_in_port_ref(port_id) {
+                port.seek(frame);
+            }
+        }
+
+        for port_id in self.get_out_ports() {
+            if let Some(port) = self.get_out_port_ref(port_id) {
+                port.seek(frame);
+            }
+        }
+    }
+
+    /// Adapt the module to a new sample rate. Time-dependent state, e.g. an oscillator's
+    /// phase, should be reset, as it doesn't carry over between rates. Modules which don't
+    /// depend on time can ignore this
+    fn set_sample_rate(&mut self, _sample_rate: SampleType) {}
+
     /// Get the module's unique ID
     fn get_id(&self) -> &String;
 
@@ -19,14 +52,33 @@ pub trait IoModule {
     /// Returns a reference to a single input port
     fn has_port_with_id(&self, port_id: &str) -> bool;
 
+    /// Returns a reference to a single input port
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort>;
+
+    /// Returns a mutable reference to a single input port
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort>;
+
     /// Returns a reference to a single output port
     fn get_out_port_ref(&self, port_id: &str) -> Option<&OutPort>;
 
+    /// Describe the module's ports, inputs first
+    fn get_port_descriptors(&self) -> Vec<PortDescriptor> {
+        let inputs = self
+            .get_in_ports()
+            .iter()
+            .filter_map(|port_id| self.get_in_port_ref(port_id))
+            .map(InPort::get_descriptor);
+        let outputs = self
+            .get_out_ports()
+            .iter()
+            .filter_map(|port_id| self.get_out_port_ref(port_id))
+            .map(OutPort::get_descriptor);
+
+        inputs.chain(outputs).collect()
+    }
+
     /// Set the value of a module's input port
-    fn set_in_port(
-        &mut self, port_id: &str,
-        out_port_ref: Weak<RwLock<Option<SampleType>>>)
-        -> PortResult<String>;
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String>;
 
     /// Get a modules processing order
     fn get_module_order(&self) -> Option<u64>;
@@ -34,3 +86,112 @@ pub trait IoModule {
     /// Set the modules processing order
     fn set_module_order(&mut self, new_order: Option<u64>);
 }
+
+/// Process a block of a module which combines two inputs into a single output, frame by
+/// frame. The inputs are read into the given scratch buffers, which hold at least `frames`
+/// samples
+pub(crate) fn process_binary_block<F>(
+    in_a: &InPort,
+    in_b: &InPort,
+    out: &OutPort,
+    a_block: &mut [SampleType],
+    b_block: &mut [SampleType],
+    frames: usize,
+    op: F,
+) where
+    F: Fn(SampleType, SampleType) -> SampleType,
+{
+    let (a_block, b_block) = (&mut a_block[..frames], &mut b_block[..frames]);
+    in_a.read_block(a_block);
+    in_b.read_block(b_block);
+
+    for (a, b) in a_block.iter_mut().zip(b_block.iter()) {
+        *a = op(*a, *b);
+    }
+    out.write_block(a_block);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::registry::{ModuleContext, ModuleRegistry, RackItem};
+
+    type ModuleMutex = Mutex<dyn IoModule + Send + Sync>;
+
+    /// A signal for one of a module's inputs, mixing zeros, negative and positive values
+    fn signal(input: usize, frames: usize) -> Vec<SampleType> {
+        let scale = if input.is_multiple_of(2) { 0.25 } else { 0.0001 };
+        (0..frames)
+            .map(|frame| ((frame * (2 * input + 3)) % 11) as SampleType - 4.0)
+            .map(|step| step * scale)
+            .collect()
+    }
+
+    /// Create a module of a built-in type, with each input fed by one of the sources
+    fn create(
+        registry: &ModuleRegistry,
+        module_type: &str,
+        sources: &[OutPort],
+    ) -> Arc<ModuleMutex> {
+        let Some(RackItem::Module(module)) =
+            registry.create(module_type, "m", &ModuleContext::default())
+        else {
+            panic!("{} isn't a module type", module_type);
+        };
+
+        {
+            let mut module = module.lock().expect("Mutex lock is poisoned");
+            for (port_id, source) in module.get_in_ports().clone().iter().zip(sources) {
+                module.set_in_port(port_id, source.get_ref()).unwrap();
+            }
+        }
+        module
+    }
+
+    #[test]
+    fn processes_a_block_of_built_in_modules_as_each_sample() {
+        let frames = 64;
+        let registry = ModuleRegistry::new();
+
+        for info in registry.types().filter(|info| !info.is_control) {
+            let sources: Vec<OutPort> = (0..8)
+                .map(|input| {
+                    let source = OutPort::new(format!("source{}", input));
+                    source.write_block(&signal(input, frames));
+                    source
+                })
+                .collect();
+            let by_block = create(&registry, &info.name, &sources);
+            let by_sample = create(&registry, &info.name, &sources);
+
+            let mut by_block = by_block.lock().unwrap();
+            by_block.process_block(frames);
+
+            let mut by_sample = by_sample.lock().unwrap();
+            for frame in 0..frames {
+                by_sample.seek_ports(frame);
+                by_sample.process_inputs();
+            }
+
+            for port_id in by_block.get_out_ports().clone() {
+                let block_ref = by_block.get_out_port_ref(&port_id).unwrap().get_ref();
+                let sample_ref = by_sample.get_out_port_ref(&port_id).unwrap().get_ref();
+                let (block_buffer, sample_buffer) =
+                    (block_ref.upgrade().unwrap(), sample_ref.upgrade().unwrap());
+
+                for frame in 0..frames {
+                    assert_eq!(
+                        block_buffer.get(frame).map(SampleType::to_bits),
+                        sample_buffer.get(frame).map(SampleType::to_bits),
+                        "{} {} differs at frame {}",
+                        info.name,
+                        port_id,
+                        frame
+                    );
+                }
+            }
+        }
+    }
+}
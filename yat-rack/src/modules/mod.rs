@@ -1,6 +1,10 @@
 pub mod adder;
 pub mod adsr;
+pub mod bitwise_and;
+pub mod bitwise_or;
+pub mod delay;
 pub mod divider;
+pub mod input;
 pub mod io_module;
 pub mod modulo;
 pub mod multiplier;
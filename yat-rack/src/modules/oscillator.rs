@@ -1,9 +1,8 @@
-use std::sync::{RwLock, Weak};
-
 use crate::modules::io_module::IoModule;
-use crate::types::{PortNotFoundError, PortResult, SampleType, SAMPLE_RATE};
+use crate::types::{AUDIO_BUF_SIZE, PortNotFoundError, PortResult, SampleType};
 use crate::in_port::InPort;
-use crate::out_port::OutPort;
+use crate::out_port::{OutPort, PortRef};
+use crate::port_descriptor::PortUnit;
 
 /// An oscillator IoModule
 pub struct Oscillator {
@@ -25,18 +24,34 @@ pub struct Oscillator {
 
     /// Value for phase acucumulator
     phase: SampleType,
+
+    /// The number of frames per second
+    sample_rate: SampleType,
+
+    /// Scratch buffers for reading a block of each input
+    amp_block: Vec<SampleType>,
+
+    freq_block: Vec<SampleType>,
 }
 
 impl Oscillator {
     /// Create a new, unordered IoModule
-    pub fn new(id: String) -> Self {
+    pub fn new(id: String, sample_rate: SampleType) -> Self {
         let order = None;
         let input_ports = vec!["amp".to_string(), "freq".to_string()];
         let output_ports = vec!["audio_out".to_string()];
 
-        let in_amp = InPort::new("amp".into(), 0.0, 1.0, 0.5);
-        let in_freq = InPort::new("freq".into(), 0.0, 20_000.0, 1000.0);
-        let out_audio_out = OutPort::new("audio_out".into());
+        let mut in_amp = InPort::new("amp".into(), 0.0, 1.0, 0.5);
+        in_amp.set_unit(PortUnit::Gain);
+        in_amp.set_description("The amplitude of the wave");
+
+        let mut in_freq = InPort::new("freq".into(), 0.0, 20_000.0, 1000.0);
+        in_freq.set_unit(PortUnit::Hz);
+        in_freq.set_description("The frequency of the wave");
+
+        let mut out_audio_out = OutPort::new("audio_out".into());
+        out_audio_out.set_range(-1.0, 1.0);
+        out_audio_out.set_description("A sine wave");
         let phase = 0f64;
 
         Self {
@@ -48,8 +63,19 @@ impl Oscillator {
             in_freq,
             out_audio_out,
             phase,
+            sample_rate,
+            amp_block: vec![0.0; AUDIO_BUF_SIZE],
+            freq_block: vec![0.0; AUDIO_BUF_SIZE],
         }
     }
+
+    /// Advance the phase by one frame and return the next sample of the wave
+    fn next_sample(&mut self, amp: SampleType, freq: SampleType) -> SampleType {
+        let pi = std::f64::consts::PI;
+
+        self.phase += (2.0 * pi * freq) / self.sample_rate;
+        amp * self.phase.sin()
+    }
 }
 
 impl PartialEq for Oscillator {
@@ -61,18 +87,38 @@ impl PartialEq for Oscillator {
 impl IoModule for Oscillator {
     /// Read inputs and populate outputs
     fn process_inputs(&mut self) {
-        let pi = std::f64::consts::PI;
-
         let amp = self.in_amp.get_value();
 
         let freq = self.in_freq.get_value();
 
-        self.phase += (2.0 * pi * freq) / SAMPLE_RATE;
-        let audio_out = amp * self.phase.sin();
+        let audio_out = self.next_sample(amp, freq);
 
         self.out_audio_out.set_value(audio_out);
     }
 
+    /// Generate a block of the wave, writing it over the amplitude block
+    fn process_block(&mut self, frames: usize) {
+        let mut amp_block = std::mem::take(&mut self.amp_block);
+        let mut freq_block = std::mem::take(&mut self.freq_block);
+
+        self.in_amp.read_block(&mut amp_block[..frames]);
+        self.in_freq.read_block(&mut freq_block[..frames]);
+
+        for (sample, freq) in amp_block[..frames].iter_mut().zip(freq_block.iter()) {
+            *sample = self.next_sample(*sample, *freq);
+        }
+        self.out_audio_out.write_block(&amp_block[..frames]);
+
+        self.amp_block = amp_block;
+        self.freq_block = freq_block;
+    }
+
+    /// Restart the phase at the new sample rate
+    fn set_sample_rate(&mut self, sample_rate: SampleType) {
+        self.sample_rate = sample_rate;
+        self.phase = 0.0;
+    }
+
     /// Return a module's ID
     fn get_id(&self) -> &String {
         &self.id
@@ -88,9 +134,24 @@ impl IoModule for Oscillator {
 
     /// Return a reference to one of the module's input ports
     fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
         match port_id {
-            "amp" | "freq"  => true,
-            _ => false,
+            "amp" => Some(&self.in_amp),
+            "freq" => Some(&self.in_freq),
+            _ => None,
+        }
+    }
+
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
+        match port_id {
+            "amp" => Some(&mut self.in_amp),
+            "freq" => Some(&mut self.in_freq),
+            _ => None,
         }
     }
 
@@ -102,7 +163,7 @@ impl IoModule for Oscillator {
     }
 
     /// Set the value of a module's input port
-    fn set_in_port(&mut self, port_id: &str, out_port_ref: Weak<RwLock<Option<SampleType>>>) -> PortResult<String> {
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
         match port_id {
             "amp" => self.in_amp.set_value(out_port_ref),
             "freq" => self.in_freq.set_value(out_port_ref),
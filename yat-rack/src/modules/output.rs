@@ -1,12 +1,15 @@
-use std::sync::mpsc::{self, Receiver};
-use std::sync::{RwLock, Weak};
-
 use crate::in_port::InPort;
-use crate::out_port::OutPort;
+use crate::out_port::{OutPort, PortRef};
 use crate::modules::io_module::IoModule;
+use crate::recorder::Tap;
+use crate::ring_buffer::{self, Consumer, Pacer, Producer};
 use crate::types::{PortNotFoundError, PortResult, SampleType, AUDIO_BUF_SIZE};
 
-/// An exit point from a Rack, e.g. for audio output
+/// An exit point from a Rack, e.g. for audio output.
+///
+/// The signal is split into channels, which are interleaved frame by frame. Each channel has
+/// its own input port, i.e., "left" and "right" for stereo, or "ch_1" to "ch_N" otherwise. The
+/// "signal_in" port is added to every channel, so that a mono patch plays on all of them.
 pub struct Output {
     /// A unique string used for identifying the module
     id: String,
@@ -20,21 +23,56 @@ pub struct Output {
 
     in_signal_in: InPort,
 
-    /// A channel for sending data from the rack's chain to outside the rack
-    out_signal_tx: mpsc::SyncSender<SampleType>,
+    /// An input port for each channel. A single channel only has `in_signal_in`
+    in_channels: Vec<InPort>,
+
+    /// The number of channels
+    channels: usize,
+
+    /// A ring buffer for sending data from the rack's chain to outside the rack, e.g. to an
+    /// audio callback. Processing never waits for it, so samples which don't fit are dropped.
+    /// An `Engine` can wait for space before processing instead, see `get_pacer`
+    out_signal_tx: Producer,
+
+    /// A copy of the frames sent, e.g. for recording them
+    tap: Tap,
+
+    /// Scratch buffer for reading a block of the input signal
+    block: Vec<SampleType>,
+
+    /// Scratch buffer for reading a block of one channel's input
+    channel_block: Vec<SampleType>,
+
+    /// Scratch buffer for the interleaved frames sent to the ring buffer
+    frames: Vec<SampleType>,
 }
 
 impl Output {
-    /// Create a new, unordered IoModule
-    //pub fn new(id: String, audio_out_ref: IoPort) -> (Self, Receiver<SampleType>) {
-    pub fn new(id: String) -> (Self, Receiver<SampleType>) {
+    /// Create a new, unordered IoModule with the given number of channels.
+    /// The ring buffer holds two blocks, so that one can be written while the other is read
+    pub fn new(id: String, channels: usize) -> (Self, Consumer) {
+        let channels = channels.max(1);
         let order = None;
-        let input_ports = vec!["signal_in".to_string()];
         let output_ports = vec!["signal_out".to_string()];
 
-        let in_signal_in = InPort::new("signal_in".into(), -1.0, 1.0, 0.0);
-
-        let (out_signal_tx, signal_rx) = mpsc::sync_channel(AUDIO_BUF_SIZE);
+        let mut in_signal_in = InPort::new("signal_in".into(), -1.0, 1.0, 0.0);
+        in_signal_in.set_description("Played on every channel, mixed with the channel's own port");
+        let in_channels: Vec<InPort> = Self::channel_labels(channels)
+            .into_iter()
+            .map(|label| {
+                let mut port = InPort::new(label, -1.0, 1.0, 0.0);
+                port.set_description("Played on this channel only");
+                port
+            })
+            .collect();
+
+        let mut input_ports = vec!["signal_in".to_string()];
+        input_ports.extend(in_channels.iter().map(|port| port.get_label().to_string()));
+
+        let (out_signal_tx, signal_rx) = ring_buffer::ring_buffer(2 * AUDIO_BUF_SIZE * channels);
+        let block = vec![0.0; AUDIO_BUF_SIZE];
+        let channel_block = vec![0.0; AUDIO_BUF_SIZE];
+        let frames = vec![0.0; AUDIO_BUF_SIZE * channels];
 
         let output = Self {
             id,
@@ -42,11 +80,42 @@ impl Output {
             input_ports,
             output_ports,
             in_signal_in,
+            in_channels,
+            channels,
             out_signal_tx,
+            tap: Tap::default(),
+            block,
+            channel_block,
+            frames,
         };
 
         (output, signal_rx)
     }
+
+    /// Returns the number of channels
+    pub fn get_channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Returns the tap which receives a copy of the frames sent
+    pub fn get_tap(&self) -> Tap {
+        self.tap.clone()
+    }
+
+    /// Returns a pacer, which lets an `Engine` wait until the reading side has made space for
+    /// the next block
+    pub fn get_pacer(&self) -> Pacer {
+        Pacer::new(self.out_signal_tx.watch(), self.channels)
+    }
+
+    /// The labels of the per-channel input ports
+    fn channel_labels(channels: usize) -> Vec<String> {
+        match channels {
+            1 => Vec::new(),
+            2 => vec!["left".into(), "right".into()],
+            _ => (1..=channels).map(|channel| format!("ch_{}", channel)).collect(),
+        }
+    }
 }
 
 impl PartialEq for Output {
@@ -59,7 +128,38 @@ impl IoModule for Output {
     fn process_inputs(&mut self) {
         let signal_in = self.in_signal_in.get_value();
 
-        self.out_signal_tx.send(signal_in).unwrap();
+        let frame = &mut self.frames[..self.channels];
+        frame.fill(signal_in);
+        for (sample, port) in frame.iter_mut().zip(&self.in_channels) {
+            *sample += port.get_value();
+        }
+
+        self.tap.write(frame, self.channels);
+        self.out_signal_tx.push_slice(frame);
+    }
+
+    /// Send a block of the input signal, interleaving the channels
+    fn process_block(&mut self, frames: usize) {
+        let channels = self.channels;
+        let block = &mut self.block[..frames];
+        self.in_signal_in.read_block(block);
+
+        let interleaved = &mut self.frames[..frames * channels];
+        for (frame, signal_in) in interleaved.chunks_mut(channels).zip(block.iter()) {
+            frame.fill(*signal_in);
+        }
+
+        let channel_block = &mut self.channel_block[..frames];
+        for (channel, port) in self.in_channels.iter().enumerate() {
+            port.read_block(channel_block);
+
+            for (frame, sample) in interleaved.chunks_mut(channels).zip(channel_block.iter()) {
+                frame[channel] += sample;
+            }
+        }
+
+        self.tap.write(interleaved, channels);
+        self.out_signal_tx.push_slice(interleaved);
     }
 
     /// Return a module's ID
@@ -77,9 +177,28 @@ impl IoModule for Output {
 
     /// Return a reference to the module's input ports
     fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
+        match port_id {
+            "signal_in" => Some(&self.in_signal_in),
+            _ => self
+                .in_channels
+                .iter()
+                .find(|port| port.get_label() == port_id),
+        }
+    }
+
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
         match port_id {
-            "signal_in" => true,
-            _ => false,
+            "signal_in" => Some(&mut self.in_signal_in),
+            _ => self
+                .in_channels
+                .iter_mut()
+                .find(|port| port.get_label() == port_id),
         }
     }
 
@@ -88,10 +207,10 @@ impl IoModule for Output {
     }
 
     /// Set the value of a module's input port
-    fn set_in_port(&mut self, port_id: &str, out_port_ref: Weak<RwLock<Option<SampleType>>>) -> PortResult<String> {
-        match port_id {
-            "signal_in" => self.in_signal_in.set_value(out_port_ref),
-            _ => return Err(PortNotFoundError),
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
+        match self.get_in_port_mut(port_id) {
+            Some(port) => port.set_value(out_port_ref),
+            None => return Err(PortNotFoundError),
         }
 
         Ok(format!("{}: Set port {}\n", self.get_id(), port_id))
@@ -0,0 +1,689 @@
+use std::error::Error;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::command::{Command, Response};
+use crate::event::Event;
+use crate::rack::Rack;
+use crate::types::SampleType;
+
+/// How often the listener checks whether it should stop
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The largest packet which can be received over UDP
+const MAX_PACKET_SIZE: usize = 65536;
+
+/// The longest pattern, for a single part of an address, which is matched at all
+const MAX_PATTERN_LEN: usize = 256;
+
+/// An argument of an OSC message
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Blob(Vec<u8>),
+    Bool(bool),
+    Nil,
+    Impulse,
+}
+
+impl OscArg {
+    /// The argument as a value for a control, if it is a number or boolean
+    pub fn as_value(&self) -> Option<SampleType> {
+        match self {
+            OscArg::Int(value) => Some(*value as SampleType),
+            OscArg::Long(value) => Some(*value as SampleType),
+            OscArg::Float(value) => Some(*value as SampleType),
+            OscArg::Double(value) => Some(*value as SampleType),
+            OscArg::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+}
+
+/// An OSC message. Its address can be a pattern, matching several addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Decode the messages of an OSC packet. The messages of a bundle are returned in order, and
+/// are meant to be applied immediately, whatever their time tag
+pub fn decode_packet(packet: &[u8]) -> Result<Vec<OscMessage>, String> {
+    let mut messages = Vec::new();
+    decode_into(packet, &mut messages)?;
+    Ok(messages)
+}
+
+fn decode_into(packet: &[u8], messages: &mut Vec<OscMessage>) -> Result<(), String> {
+    let mut reader = Reader { data: packet };
+
+    if packet.starts_with(b"#bundle\0") {
+        reader.take(8)?;
+        // The time tag, which is ignored
+        reader.take(8)?;
+        while !reader.data.is_empty() {
+            let size = reader.int()?;
+            let size = usize::try_from(size).map_err(|_| "Invalid bundle element size")?;
+            decode_into(reader.take(size)?, messages)?;
+        }
+        return Ok(());
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(format!("Invalid OSC address: {}", address));
+    }
+
+    // Very old implementations leave out the type tags, along with the arguments
+    let tags = if reader.data.is_empty() {
+        String::from(",")
+    } else {
+        reader.string()?
+    };
+    let tags = tags
+        .strip_prefix(',')
+        .ok_or_else(|| format!("{}: invalid type tags {}", address, tags))?;
+
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(reader.int()?),
+            'h' => OscArg::Long(i64::from_be_bytes(reader.array()?)),
+            'f' => OscArg::Float(f32::from_be_bytes(reader.array()?)),
+            'd' => OscArg::Double(f64::from_be_bytes(reader.array()?)),
+            's' | 'S' => OscArg::String(reader.string()?),
+            'b' => {
+                let size = usize::try_from(reader.int()?).map_err(|_| "Invalid blob size")?;
+                let blob = reader.take(size)?.to_vec();
+                reader.take(padding(size))?;
+                OscArg::Blob(blob)
+            }
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            'N' => OscArg::Nil,
+            'I' => OscArg::Impulse,
+            _ => return Err(format!("{}: unsupported type tag {}", address, tag)),
+        });
+    }
+
+    messages.push(OscMessage { address, args });
+    Ok(())
+}
+
+/// The number of bytes which pad data of the given size to a multiple of 4
+fn padding(size: usize) -> usize {
+    (4 - size % 4) % 4
+}
+
+/// Reads the big-endian, 4-byte aligned fields of a packet
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < size {
+            return Err(String::from("OSC packet is cut short"));
+        }
+        let (taken, rest) = self.data.split_at(size);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn int(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    /// A null terminated string, padded to a multiple of 4 bytes
+    fn string(&mut self) -> Result<String, String> {
+        let len = self
+            .data
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or("OSC string isn't terminated")?;
+        let string = String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())?;
+        self.take(1 + padding(len + 1))?;
+        Ok(string)
+    }
+}
+
+/// Whether an OSC address pattern matches an address. Each part of the pattern, in between
+/// '/', matches the same part of the address, where `?` matches any character, `*` any
+/// sequence of characters, `[a-z]` and `[!a-z]` one character in or out of a set, and
+/// `{foo,bar}` any of the given strings. A part of the pattern which is longer than 256 bytes
+/// matches nothing
+pub fn matches(pattern: &str, address: &str) -> bool {
+    let patterns: Vec<&str> = pattern.split('/').collect();
+    let parts: Vec<&str> = address.split('/').collect();
+
+    patterns.len() == parts.len()
+        && patterns
+            .iter()
+            .zip(&parts)
+            .all(|(pattern, part)| matches_part(pattern.as_bytes(), part.as_bytes()))
+}
+
+/// Whether a part of a pattern matches a part of an address. Rather than backtracking, this
+/// keeps track of every position in the name which the pattern so far can end at, so that
+/// patterns like `*a*a*a*b` can't take exponential time
+fn matches_part(pattern: &[u8], name: &[u8]) -> bool {
+    if pattern.len() > MAX_PATTERN_LEN {
+        return false;
+    }
+    let Some(tokens) = tokenize(pattern) else {
+        return false;
+    };
+
+    let mut ends = vec![false; name.len() + 1];
+    ends[0] = true;
+    for token in &tokens {
+        let mut next = vec![false; name.len() + 1];
+        for pos in (0..=name.len()).filter(|&pos| ends[pos]) {
+            match token {
+                // Everything from the first end on is matched, so the rest can be skipped
+                Token::AnySequence => {
+                    next[pos..].fill(true);
+                    break;
+                }
+                Token::Choice(choices) => {
+                    for choice in choices.split(|&byte| byte == b',') {
+                        if name[pos..].starts_with(choice) {
+                            next[pos + choice.len()] = true;
+                        }
+                    }
+                }
+                token => {
+                    if pos < name.len() && token.matches_byte(name[pos]) {
+                        next[pos + 1] = true;
+                    }
+                }
+            }
+        }
+        ends = next;
+    }
+
+    ends[name.len()]
+}
+
+/// An element of an address pattern
+enum Token<'a> {
+    Byte(u8),
+
+    /// `?`
+    AnyByte,
+
+    /// `*`
+    AnySequence,
+
+    /// `[a-z]`, or `[!a-z]` if negated
+    Set {
+        negate: bool,
+        set: &'a [u8],
+    },
+
+    /// `{foo,bar}`, with the choices still separated by ','
+    Choice(&'a [u8]),
+}
+
+impl Token<'_> {
+    /// Whether a token which matches a single byte matches the given one
+    fn matches_byte(&self, byte: u8) -> bool {
+        match self {
+            Token::Byte(expected) => *expected == byte,
+            Token::AnyByte => true,
+            Token::Set { negate, set } => {
+                let mut in_set = false;
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == b'-' {
+                        in_set |= set[i] <= byte && byte <= set[i + 2];
+                        i += 3;
+                    } else {
+                        in_set |= set[i] == byte;
+                        i += 1;
+                    }
+                }
+                in_set != *negate
+            }
+            Token::AnySequence | Token::Choice(_) => false,
+        }
+    }
+}
+
+/// Split a part of a pattern into its tokens. Returns None if a `[` or `{` isn't closed
+fn tokenize(pattern: &[u8]) -> Option<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+
+    while let Some((&first, tail)) = rest.split_first() {
+        rest = tail;
+        let token = match first {
+            // Consecutive stars match the same as a single one
+            b'*' if matches!(tokens.last(), Some(Token::AnySequence)) => continue,
+            b'*' => Token::AnySequence,
+            b'?' => Token::AnyByte,
+            b'[' => {
+                let end = rest.iter().position(|&byte| byte == b']')?;
+                let (negate, set) = match rest[..end].split_first() {
+                    Some((b'!', set)) => (true, set),
+                    _ => (false, &rest[..end]),
+                };
+                rest = &rest[end + 1..];
+                Token::Set { negate, set }
+            }
+            b'{' => {
+                let end = rest.iter().position(|&byte| byte == b'}')?;
+                let choices = &rest[..end];
+                rest = &rest[end + 1..];
+                Token::Choice(choices)
+            }
+            byte => Token::Byte(byte),
+        };
+        tokens.push(token);
+    }
+
+    Some(tokens)
+}
+
+/// Listens for OSC messages over UDP, and applies them to a Rack:
+///
+/// - `/yat/ctrl/<ctrl_id>/<port_id>` with a number sets a control's value, like `set`. The
+///   control and port can be patterns, e.g. `/yat/ctrl/knob*/value`
+/// - `/yat/cmd` with the words of a command, e.g. `"add" "osc" "osc1"`, or the whole
+///   command as a single string, applies it like a command typed into the TUI
+///
+/// Commands are applied by the engine, in between blocks. `quit`, `save`, `load` and `plugin`
+/// aren't accepted. The messages of a bundle are applied one by one, and each failure is
+/// written to the message queue.
+pub struct OscListener {
+    address: SocketAddr,
+
+    /// Tells the listener thread to stop
+    stop: Arc<AtomicBool>,
+
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OscListener {
+    /// Listen on a UDP address, e.g. "127.0.0.1:9000"
+    pub fn bind(
+        address: &str,
+        rack: Arc<Mutex<Rack>>,
+        event_tx: Sender<Event>,
+        msg_tx: Sender<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        let address = socket.local_addr()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let l_stop = stop.clone();
+
+        let thread = thread::spawn(move || {
+            // Successful responses aren't of interest, and would flood the message queue
+            let (reply_tx, reply_rx) = mpsc::channel::<Response>();
+            let mut packet = vec![0; MAX_PACKET_SIZE];
+
+            while !l_stop.load(Relaxed) {
+                if let Ok((size, _)) = socket.recv_from(&mut packet) {
+                    // A failing message doesn't keep the rest of its bundle from being applied
+                    let results = match decode_packet(&packet[..size]) {
+                        Ok(messages) => messages
+                            .iter()
+                            .map(|message| dispatch(message, &rack, &event_tx, &reply_tx))
+                            .collect(),
+                        Err(e) => vec![Err(e)],
+                    };
+
+                    for e in results.into_iter().filter_map(Result::err) {
+                        let _ = msg_tx.send(format!("OSC: {}", e));
+                    }
+                }
+
+                for response in reply_rx.try_iter() {
+                    if response.result.is_err() {
+                        let _ = msg_tx.send(format!("OSC: {}", response));
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            address,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for OscListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Send the commands for a message to the engine
+fn dispatch(
+    message: &OscMessage,
+    rack: &Arc<Mutex<Rack>>,
+    event_tx: &Sender<Event>,
+    reply_tx: &Sender<Response>,
+) -> Result<(), String> {
+    let address = &message.address;
+    let parts: Vec<&str> = address.split('/').skip(1).collect();
+
+    let commands = match parts.as_slice() {
+        _ if matches(address, "/yat/cmd") => {
+            let words: Vec<String> = message
+                .args
+                .iter()
+                .map(|arg| match arg {
+                    OscArg::String(word) => Ok(word.clone()),
+                    arg => match arg.as_value() {
+                        Some(value) => Ok(value.to_string()),
+                        None => Err(format!("{}: unexpected argument {:?}", address, arg)),
+                    },
+                })
+                .collect::<Result<_, _>>()?;
+
+            match words.join(" ").parse::<Command>() {
+                Ok(command) if !command.is_accepted_remotely() => {
+                    return Err(String::from(
+                        "quit, save, load and plugin aren't accepted over OSC",
+                    ))
+                }
+                Ok(command) => vec![command],
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        [yat, ctrl, ctrl_id, port_id]
+            if matches_part(yat.as_bytes(), b"yat") && matches_part(ctrl.as_bytes(), b"ctrl") =>
+        {
+            let value = message
+                .args
+                .first()
+                .and_then(OscArg::as_value)
+                .ok_or_else(|| format!("{}: expected a number", address))?;
+
+            let is_pattern = |part: &str| part.contains(['*', '?', '[', '{']);
+            let targets = if is_pattern(ctrl_id) || is_pattern(port_id) {
+                // Don't keep the Rack locked while matching
+                let control_ports = rack
+                    .lock()
+                    .expect("Mutex lock is poisoned")
+                    .get_control_ports();
+                control_ports
+                    .into_iter()
+                    .filter(|(ctrl, port)| {
+                        matches_part(ctrl_id.as_bytes(), ctrl.as_bytes())
+                            && matches_part(port_id.as_bytes(), port.as_bytes())
+                    })
+                    .collect()
+            } else {
+                vec![(ctrl_id.to_string(), port_id.to_string())]
+            };
+
+            if targets.is_empty() {
+                return Err(format!("{}: no control matches", address));
+            }
+
+            targets
+                .into_iter()
+                .map(|(ctrl, port)| Command::Set { ctrl, port, value })
+                .collect()
+        }
+        _ => return Err(format!("Unknown address: {}", address)),
+    };
+
+    for command in commands {
+        event_tx
+            .send(Event::Request(command, reply_tx.clone()))
+            .map_err(|_| String::from("The engine has stopped"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use std::sync::mpsc::Receiver;
+    use std::time::Instant;
+
+    /// A null terminated string, padded to a multiple of 4 bytes
+    fn osc_string(string: &str) -> Vec<u8> {
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.push(0);
+        bytes.resize(bytes.len() + padding(bytes.len()), 0);
+        bytes
+    }
+
+    fn message(address: &str, tags: &str, args: &[u8]) -> Vec<u8> {
+        let mut packet = osc_string(address);
+        packet.extend(osc_string(tags));
+        packet.extend(args);
+        packet
+    }
+
+    #[test]
+    fn decodes_every_type_tag() {
+        let mut args = Vec::new();
+        args.extend(7i32.to_be_bytes());
+        args.extend((-8i64).to_be_bytes());
+        args.extend(0.5f32.to_be_bytes());
+        args.extend(0.25f64.to_be_bytes());
+        args.extend(osc_string("osc1"));
+        args.extend(osc_string("sym"));
+        args.extend(3i32.to_be_bytes());
+        args.extend([1, 2, 3, 0]);
+        let packet = message("/yat/test", ",ihfdsSbTFNI", &args);
+
+        assert_eq!(
+            decode_packet(&packet).unwrap(),
+            vec![OscMessage {
+                address: String::from("/yat/test"),
+                args: vec![
+                    OscArg::Int(7),
+                    OscArg::Long(-8),
+                    OscArg::Float(0.5),
+                    OscArg::Double(0.25),
+                    OscArg::String(String::from("osc1")),
+                    OscArg::String(String::from("sym")),
+                    OscArg::Blob(vec![1, 2, 3]),
+                    OscArg::Bool(true),
+                    OscArg::Bool(false),
+                    OscArg::Nil,
+                    OscArg::Impulse,
+                ],
+            }]
+        );
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = osc_string("#bundle");
+        packet.extend([0; 8]);
+        for element in elements {
+            packet.extend((element.len() as i32).to_be_bytes());
+            packet.extend(element);
+        }
+        packet
+    }
+
+    #[test]
+    fn decodes_bundles_in_order() {
+        let first = message("/a", ",i", &1i32.to_be_bytes());
+        let second = message("/b", ",", &[]);
+        let packet = bundle(&[first, second]);
+
+        let addresses: Vec<String> = decode_packet(&packet)
+            .unwrap()
+            .into_iter()
+            .map(|message| message.address)
+            .collect();
+        assert_eq!(addresses, ["/a", "/b"]);
+    }
+
+    #[test]
+    fn decodes_a_message_without_type_tags() {
+        let packet = osc_string("/yat/cmd");
+        assert_eq!(decode_packet(&packet).unwrap()[0].args, vec![]);
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        let valid = message("/yat/ctrl/k/value", ",f", &0.5f32.to_be_bytes());
+        assert!(decode_packet(&valid).is_ok());
+
+        // Cut short in the middle of the argument
+        assert!(decode_packet(&valid[..valid.len() - 2]).is_err());
+        // The address isn't terminated
+        assert!(decode_packet(b"/yat").is_err());
+        // The address doesn't start with '/'
+        assert!(decode_packet(&message("yat", ",", &[])).is_err());
+        // The type tags don't start with ','
+        assert!(decode_packet(&message("/yat", "f", &0.5f32.to_be_bytes())).is_err());
+        // Unknown type tag
+        assert!(decode_packet(&message("/yat", ",x", &[0; 4])).is_err());
+        // A negative blob size
+        assert!(decode_packet(&message("/yat", ",b", &(-1i32).to_be_bytes())).is_err());
+
+        // A bundle element which is larger than the rest of the bundle
+        let mut bundle = osc_string("#bundle");
+        bundle.extend([0; 8]);
+        bundle.extend(64i32.to_be_bytes());
+        bundle.extend(osc_string("/a"));
+        assert!(decode_packet(&bundle).is_err());
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("/yat/ctrl/knob1/value", "/yat/ctrl/knob1/value"));
+        assert!(matches("/yat/ctrl/knob*/value", "/yat/ctrl/knob12/value"));
+        assert!(matches("/yat/ctrl/*/value", "/yat/ctrl/k/value"));
+        assert!(matches("/yat/ctrl/k**b/*", "/yat/ctrl/knob/value"));
+        assert!(matches("/yat/ctrl/knob?/value", "/yat/ctrl/knob3/value"));
+        assert!(matches(
+            "/yat/ctrl/knob[1-3]/value",
+            "/yat/ctrl/knob2/value"
+        ));
+        assert!(matches(
+            "/yat/ctrl/knob[!1-3]/value",
+            "/yat/ctrl/knob4/value"
+        ));
+        assert!(matches(
+            "/yat/ctrl/{knob,slider}1/value",
+            "/yat/ctrl/slider1/value"
+        ));
+
+        assert!(!matches("/yat/ctrl/knob?/value", "/yat/ctrl/knob/value"));
+        assert!(!matches(
+            "/yat/ctrl/knob[1-3]/value",
+            "/yat/ctrl/knob4/value"
+        ));
+        assert!(!matches(
+            "/yat/ctrl/knob[!1-3]/value",
+            "/yat/ctrl/knob2/value"
+        ));
+        assert!(!matches(
+            "/yat/ctrl/{knob,slider}1/value",
+            "/yat/ctrl/button1/value"
+        ));
+        // A star doesn't match across parts
+        assert!(!matches("/yat/*/value", "/yat/ctrl/knob1/value"));
+        // Unclosed sets and choices match nothing
+        assert!(!matches("/yat/ctrl/knob[1/value", "/yat/ctrl/knob1/value"));
+        assert!(!matches("/yat/ctrl/{knob/value", "/yat/ctrl/knob/value"));
+    }
+
+    #[test]
+    fn matches_many_stars_quickly() {
+        let name = format!("/{}", "a".repeat(200));
+        let pattern = format!("/{}b", "*a".repeat(100));
+
+        let started = Instant::now();
+        assert!(!matches(&pattern, &name));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let too_long = format!("/{}", "*".repeat(MAX_PATTERN_LEN + 1));
+        assert!(!matches(&too_long, "/a"));
+    }
+
+    /// The commands which changed the Rack, until none has for a while
+    fn changes(responses: &Receiver<Response>) -> Vec<String> {
+        std::iter::from_fn(|| responses.recv_timeout(Duration::from_millis(300)).ok())
+            .filter(|response| response.result.is_ok())
+            .map(|response| response.command.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn applies_messages_sent_over_udp() {
+        let rack = Arc::new(Mutex::new(Rack::new()));
+        rack.lock().unwrap().run_script("add control k").unwrap();
+        let responses = rack.lock().unwrap().subscribe();
+        let (engine, event_tx) = Engine::new(rack.clone());
+        thread::spawn(move || engine.run());
+        let (msg_tx, msg_rx) = mpsc::channel();
+        let listener = OscListener::bind("127.0.0.1:0", rack, event_tx.clone(), msg_tx).unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let send = |packet: &[u8]| socket.send_to(packet, listener.get_address()).unwrap();
+
+        send(&message("/yat/ctrl/k/value", ",f", &0.5f32.to_be_bytes()));
+        let mut words = osc_string("add");
+        words.extend(osc_string("adder"));
+        words.extend(osc_string("a"));
+        send(&message("/yat/cmd", ",sss", &words));
+        send(&message("/yat/cmd", ",s", &osc_string("add adder b")));
+        assert_eq!(
+            changes(&responses),
+            ["set k value 0.5", "add adder a", "add adder b"]
+        );
+        assert!(msg_rx.try_recv().is_err());
+
+        // Each message of a bundle is applied on its own, and each failure reported
+        send(&bundle(&[
+            message("/yat/nothing", ",", &[]),
+            message("/yat/ctrl/k/value", ",f", &0.25f32.to_be_bytes()),
+            message("/yat/cmd", ",s", &osc_string("quit")),
+            message("/yat/cmd", ",s", &osc_string("remove x")),
+            message("/yat/ctrl/k/value", ",f", &0.75f32.to_be_bytes()),
+        ]));
+        assert_eq!(
+            changes(&responses),
+            ["set k value 0.25", "set k value 0.75"]
+        );
+        let failures: Vec<String> = msg_rx.try_iter().collect();
+        assert_eq!(failures.len(), 3, "{:?}", failures);
+        assert!(failures[0].contains("/yat/nothing"), "{}", failures[0]);
+        assert!(failures[1].contains("quit"), "{}", failures[1]);
+        assert!(failures[2].starts_with("OSC: remove"), "{}", failures[2]);
+
+        drop(listener);
+        event_tx.send(Event::Command(Command::Quit)).unwrap();
+    }
+}
@@ -1,24 +1,132 @@
-use std::sync::{Arc, RwLock, Weak};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Weak};
 
+use crate::port_descriptor::{PortDescriptor, PortDirection, PortUnit};
+use crate::types::{SampleType, AUDIO_BUF_SIZE};
 
-use crate::types::SampleType;
+/// A reference to an output port's buffer, used for connecting input ports
+pub type PortRef = Weak<PortBuffer>;
+
+/// A block of samples written by an output port and read by its connected input ports.
+///
+/// Samples are stored as the bits of each value in atomics, so that reading and writing never
+/// takes a lock. Neither side can block the other, e.g., a control being set from the UI thread
+/// while the chain is processed, and there is no lock to be poisoned.
+pub struct PortBuffer {
+    /// One sample per frame of the block
+    samples: Box<[AtomicU64]>,
+
+    /// Whether the buffer has been written. Until then, readers fall back to their default
+    written: AtomicBool,
+}
+
+impl PortBuffer {
+    fn new() -> Self {
+        let samples = (0..AUDIO_BUF_SIZE).map(|_| AtomicU64::new(0)).collect();
+        let written = AtomicBool::new(false);
+
+        Self { samples, written }
+    }
+
+    /// Get the sample of a frame, or None if the buffer was never written
+    pub fn get(&self, frame: usize) -> Option<SampleType> {
+        if self.written.load(Acquire) {
+            Some(SampleType::from_bits(self.samples[frame].load(Relaxed)))
+        } else {
+            None
+        }
+    }
+
+    /// Copy the samples of the first frames into the given slice.
+    /// Returns false, leaving the slice untouched, if the buffer was never written
+    pub fn read_block(&self, block: &mut [SampleType]) -> bool {
+        if !self.written.load(Acquire) {
+            return false;
+        }
+
+        for (value, sample) in block.iter_mut().zip(self.samples.iter()) {
+            *value = SampleType::from_bits(sample.load(Relaxed));
+        }
+
+        true
+    }
+
+    /// Add the samples of the first frames, multiplied by a gain, to the given slice.
+    /// Returns false, leaving the slice untouched, if the buffer was never written
+    pub fn add_to_block(&self, block: &mut [SampleType], gain: SampleType) -> bool {
+        if !self.written.load(Acquire) {
+            return false;
+        }
+
+        for (value, sample) in block.iter_mut().zip(self.samples.iter()) {
+            *value += SampleType::from_bits(sample.load(Relaxed)) * gain;
+        }
+
+        true
+    }
+
+    fn set(&self, frame: usize, value: SampleType) {
+        // Fill the whole buffer on the first write, so that no frame reads as zero
+        if !self.written.load(Relaxed) {
+            self.fill(value);
+        } else {
+            self.samples[frame].store(value.to_bits(), Relaxed);
+        }
+    }
+
+    fn write_block(&self, block: &[SampleType]) {
+        for (value, sample) in block.iter().zip(self.samples.iter()) {
+            sample.store(value.to_bits(), Relaxed);
+        }
+        self.written.store(true, Release);
+    }
+
+    fn fill(&self, value: SampleType) {
+        for sample in self.samples.iter() {
+            sample.store(value.to_bits(), Relaxed);
+        }
+        self.written.store(true, Release);
+    }
+}
 
 pub struct OutPort {
     /// The port's ID label
     label: String,
 
-    /// A weak pointer to an output port's value
-    value: Arc<RwLock<Option<SampleType>>>,
+    /// The port's samples for the current block
+    value: Arc<PortBuffer>,
+
+    /// The frame of the block which is being processed
+    frame: AtomicUsize,
+
+    /// Suggested bounds of the port's value, unbounded unless the module sets them
+    lower_range: SampleType,
+    upper_range: SampleType,
+
+    /// What the port's value means, e.g. a frequency
+    unit: PortUnit,
+
+    /// What the port does, for showing to the user
+    description: String,
 }
 
 impl OutPort {
     pub fn new(label: String) -> Self {
         // Will be initialized upon first process
-        let value = Arc::new(RwLock::new(None));
+        let value = Arc::new(PortBuffer::new());
+        let frame = AtomicUsize::new(0);
+        let unit = PortUnit::None;
+        let description = String::new();
 
         Self {
             label,
             value,
+            frame,
+            lower_range: SampleType::MIN,
+            upper_range: SampleType::MAX,
+            unit,
+            description,
         }
     }
 
@@ -30,12 +138,59 @@ impl OutPort {
         self.label = new_label;
     }
 
+    /// Set the value of the current frame
     pub fn set_value(&self, new: f64) {
-        let mut value = self.value.write().expect("RwLock is poisoned");
-        *value = Some(new);
+        self.value.set(self.frame.load(Relaxed), new);
+    }
+
+    /// Get the value of the current frame, or None if the port was never written
+    pub fn get_value(&self) -> Option<f64> {
+        self.value.get(self.frame.load(Relaxed))
+    }
+
+    /// Set the value of every frame. This is meant for controls, whose value doesn't change
+    /// over the course of a block
+    pub fn fill_value(&self, new: f64) {
+        self.value.fill(new);
     }
 
-    pub fn get_ref(&self) -> Weak<RwLock<Option<SampleType>>> {
+    /// Write a block of samples, starting at the first frame
+    pub fn write_block(&self, block: &[SampleType]) {
+        self.value.write_block(block);
+    }
+
+    /// Move to a frame of the block, for per-sample processing
+    pub fn seek(&self, frame: usize) {
+        self.frame.store(frame, Relaxed);
+    }
+
+    pub fn get_ref(&self) -> PortRef {
         Arc::downgrade(&self.value)
     }
+
+    pub fn set_range(&mut self, lower_range: SampleType, upper_range: SampleType) {
+        self.lower_range = lower_range;
+        self.upper_range = upper_range;
+    }
+
+    pub fn set_unit(&mut self, new_unit: PortUnit) {
+        self.unit = new_unit;
+    }
+
+    pub fn set_description(&mut self, new_description: &str) {
+        self.description = new_description.into();
+    }
+
+    /// Describe the port's label, range and unit
+    pub fn get_descriptor(&self) -> PortDescriptor {
+        PortDescriptor {
+            label: self.label.clone(),
+            direction: PortDirection::Output,
+            lower_range: self.lower_range,
+            upper_range: self.upper_range,
+            default: None,
+            unit: self.unit,
+            description: self.description.clone(),
+        }
+    }
 }
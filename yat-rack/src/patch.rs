@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::SampleType;
+
+/// The version of the patch format written by `Rack::save`. Patches of later versions are
+/// refused when loading
+pub const PATCH_VERSION: u32 = 1;
+
+/// A complete Rack, as saved to a patch file by `Rack::save`.
+///
+/// Patches are written as TOML, e.g.:
+///
+/// ```toml
+/// version = 1
+///
+/// [[modules]]
+/// type = "osc"
+/// id = "osc1"
+///
+/// [[controls]]
+/// type = "control"
+/// id = "knob1"
+///
+/// [controls.values]
+/// value = 440.0
+///
+/// [[connections]]
+/// out_module = "knob1"
+/// out_port = "value"
+/// in_module = "osc1"
+/// in_port = "freq"
+/// ```
+///
+/// Only modules which were added by type are saved. Modules added by the host, e.g. an
+/// `Output`, aren't, but connections to them are, and they have to exist when loading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Patch {
+    pub version: u32,
+
+    #[serde(default)]
+    pub modules: Vec<ModuleEntry>,
+
+    #[serde(default)]
+    pub controls: Vec<ControlEntry>,
+
+    /// The input ports which sum their cables, rather than keeping the latest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub summing: Vec<PortEntry>,
+
+    /// In the order the connections were made
+    #[serde(default)]
+    pub connections: Vec<ConnectionEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleEntry {
+    #[serde(rename = "type")]
+    pub module_type: String,
+
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlEntry {
+    #[serde(rename = "type")]
+    pub control_type: String,
+
+    pub id: String,
+
+    /// The values of the control's output ports. Ports which were never set are left out
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub values: BTreeMap<String, SampleType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PortEntry {
+    pub module: String,
+    pub port: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionEntry {
+    pub out_module: String,
+    pub out_port: String,
+    pub in_module: String,
+    pub in_port: String,
+
+    #[serde(default = "unity_gain", skip_serializing_if = "is_unity_gain")]
+    pub gain: SampleType,
+}
+
+fn unity_gain() -> SampleType {
+    1.0
+}
+
+fn is_unity_gain(gain: &SampleType) -> bool {
+    *gain == 1.0
+}
+
+impl Patch {
+    /// Read a patch from a TOML file
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    /// Write the patch to a TOML file
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The problems which kept a patch from being loaded, e.g. unknown module types or ports
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchError {
+    pub problems: Vec<String>,
+}
+
+impl Error for PatchError {}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The patch can't be loaded:")?;
+        for problem in &self.problems {
+            write!(f, "\n    {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
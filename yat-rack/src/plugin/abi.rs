@@ -0,0 +1,149 @@
+use std::ffi::{c_char, c_void};
+
+use crate::types::SampleType;
+
+/// The version of the plugin ABI described by this module. It changes whenever any of the
+/// types below do, and libraries built against another version are refused
+pub const ABI_VERSION: u32 = 2;
+
+/// The symbol of `extern "C" fn() -> u32`, returning the `ABI_VERSION` a library was built with
+pub const ABI_VERSION_SYMBOL: &[u8] = b"yat_plugin_abi_version\0";
+
+/// The symbol of `extern "C" fn() -> *const PluginDescriptor`. It is only looked up once the
+/// ABI version matches
+pub const DESCRIPTOR_SYMBOL: &[u8] = b"yat_plugin_descriptor\0";
+
+/// Describes a plugin library and the types it provides.
+///
+/// Every string is NUL-terminated UTF-8, and every pointer has to stay valid for as long as
+/// the library is loaded, e.g. by pointing into statics. Both symbols are exported by
+/// `export_plugin!`.
+///
+/// Instances are created and used by the host, from any thread, but never from two threads
+/// at once. Functions must not unwind: a panic aborts the host.
+#[repr(C)]
+pub struct PluginDescriptor {
+    /// The plugin's name, e.g. for `info plugins`
+    pub name: *const c_char,
+
+    pub modules: *const ModuleDescriptor,
+    pub module_count: usize,
+
+    pub controls: *const ControlDescriptor,
+    pub control_count: usize,
+}
+
+/// The values of `PortDescriptor::unit`, see `PortUnit`
+pub const UNIT_NONE: u32 = 0;
+pub const UNIT_HZ: u32 = 1;
+pub const UNIT_SECONDS: u32 = 2;
+pub const UNIT_GAIN: u32 = 3;
+pub const UNIT_GATE: u32 = 4;
+pub const UNIT_VOLT_PER_OCTAVE: u32 = 5;
+
+/// An input or output port
+#[repr(C)]
+pub struct PortDescriptor {
+    pub name: *const c_char,
+
+    /// Suggested bounds of the port's value. Values set on a control's port have to lie
+    /// within them
+    pub lower_range: SampleType,
+    pub upper_range: SampleType,
+
+    /// The value of an unconnected input port. Ignored for output ports
+    pub default: SampleType,
+
+    /// One of the `UNIT_*` constants
+    pub unit: u32,
+
+    /// What the port does, e.g. for `ports`. May be null
+    pub description: *const c_char,
+}
+
+/// A module type, which processes blocks of samples like an `IoModule`
+#[repr(C)]
+pub struct ModuleDescriptor {
+    /// The name which the type is added by. It has to be unique among all registered types
+    pub type_name: *const c_char,
+    pub description: *const c_char,
+
+    pub in_ports: *const PortDescriptor,
+    pub in_port_count: usize,
+
+    pub out_ports: *const PortDescriptor,
+    pub out_port_count: usize,
+
+    /// Create an instance for the given sample rate. Instances without state may be null
+    pub create: unsafe extern "C" fn(sample_rate: SampleType) -> *mut c_void,
+
+    /// Free an instance
+    pub destroy: unsafe extern "C" fn(instance: *mut c_void),
+
+    /// Adapt an instance to a new sample rate, resetting its time-dependent state
+    pub set_sample_rate: unsafe extern "C" fn(instance: *mut c_void, sample_rate: SampleType),
+
+    /// Process `frames` frames. `inputs` holds a block of `frames` samples for each input
+    /// port, one after the other and in the order of `in_ports`. The outputs are to be written
+    /// to `outputs` in the same way
+    pub process: unsafe extern "C" fn(
+        instance: *mut c_void,
+        inputs: *const SampleType,
+        outputs: *mut SampleType,
+        frames: usize,
+    ),
+}
+
+/// A control type, whose output values are set by the user, like a `Control`
+#[repr(C)]
+pub struct ControlDescriptor {
+    /// The name which the type is added by. It has to be unique among all registered types
+    pub type_name: *const c_char,
+    pub description: *const c_char,
+
+    pub out_ports: *const PortDescriptor,
+    pub out_port_count: usize,
+
+    /// Create an instance. Instances without state may be null
+    pub create: unsafe extern "C" fn() -> *mut c_void,
+
+    /// Free an instance
+    pub destroy: unsafe extern "C" fn(instance: *mut c_void),
+
+    /// The value of an output port, by its index in `out_ports`
+    pub get_value: unsafe extern "C" fn(instance: *mut c_void, port: usize) -> SampleType,
+
+    /// Set the value of an output port, by its index in `out_ports`
+    pub set_value: unsafe extern "C" fn(instance: *mut c_void, port: usize, value: SampleType),
+
+    /// Handle a control key, as a Unicode scalar value
+    pub recv_key: unsafe extern "C" fn(instance: *mut c_void, key: u32),
+}
+
+// The descriptors are never written to, so plugins can keep them in statics
+unsafe impl Sync for PluginDescriptor {}
+unsafe impl Sync for PortDescriptor {}
+unsafe impl Sync for ModuleDescriptor {}
+unsafe impl Sync for ControlDescriptor {}
+
+/// Export a plugin's descriptor, along with the ABI version it was built against, e.g.
+///
+/// ```ignore
+/// static PLUGIN: PluginDescriptor = PluginDescriptor { ... };
+///
+/// yat_rack::export_plugin!(PLUGIN);
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($descriptor:path) => {
+        #[no_mangle]
+        pub extern "C" fn yat_plugin_abi_version() -> u32 {
+            $crate::plugin::abi::ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn yat_plugin_descriptor() -> *const $crate::plugin::abi::PluginDescriptor {
+            &$descriptor
+        }
+    };
+}
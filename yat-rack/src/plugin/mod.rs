@@ -0,0 +1,408 @@
+pub mod abi;
+mod plugin_control;
+mod plugin_module;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::ffi::{c_char, c_void, CStr};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex};
+
+use libloading::Library;
+
+use crate::plugin::abi::{
+    ControlDescriptor, ModuleDescriptor, PluginDescriptor, PortDescriptor, ABI_VERSION,
+    ABI_VERSION_SYMBOL, DESCRIPTOR_SYMBOL, UNIT_GAIN, UNIT_GATE, UNIT_HZ, UNIT_NONE, UNIT_SECONDS,
+    UNIT_VOLT_PER_OCTAVE,
+};
+use crate::plugin::plugin_control::PluginControl;
+use crate::plugin::plugin_module::PluginModule;
+use crate::port_descriptor::{self, PortDirection, PortUnit};
+use crate::registry::ModuleRegistry;
+use crate::types::SampleType;
+
+/// A shared library providing module and control types, see `abi::PluginDescriptor`.
+///
+/// The library stays loaded for as long as the plugin, or any module or control created from
+/// it, exists.
+pub struct Plugin {
+    name: String,
+
+    path: PathBuf,
+
+    modules: Vec<ModuleType>,
+
+    controls: Vec<ControlType>,
+
+    /// Declared last, so that it is closed after everything pointing into it
+    _library: Library,
+}
+
+/// A port, as described by a plugin
+pub(crate) struct PortType {
+    pub(crate) name: String,
+    pub(crate) lower_range: SampleType,
+    pub(crate) upper_range: SampleType,
+    pub(crate) default: SampleType,
+    pub(crate) unit: PortUnit,
+    pub(crate) description: String,
+}
+
+impl PortType {
+    /// Describe the port, as one of the given direction
+    pub(crate) fn get_descriptor(
+        &self,
+        direction: PortDirection,
+    ) -> port_descriptor::PortDescriptor {
+        port_descriptor::PortDescriptor {
+            label: self.name.clone(),
+            direction,
+            lower_range: self.lower_range,
+            upper_range: self.upper_range,
+            default: match direction {
+                PortDirection::Input => Some(self.default),
+                PortDirection::Output => None,
+            },
+            unit: self.unit,
+            description: self.description.clone(),
+        }
+    }
+}
+
+/// A module type, copied from its `ModuleDescriptor`
+pub(crate) struct ModuleType {
+    pub(crate) name: String,
+    pub(crate) description: String,
+    pub(crate) in_ports: Vec<PortType>,
+    pub(crate) out_ports: Vec<PortType>,
+    pub(crate) create: unsafe extern "C" fn(SampleType) -> *mut c_void,
+    pub(crate) destroy: unsafe extern "C" fn(*mut c_void),
+    pub(crate) set_sample_rate: unsafe extern "C" fn(*mut c_void, SampleType),
+    pub(crate) process:
+        unsafe extern "C" fn(*mut c_void, *const SampleType, *mut SampleType, usize),
+}
+
+/// A control type, copied from its `ControlDescriptor`
+pub(crate) struct ControlType {
+    pub(crate) name: String,
+    pub(crate) description: String,
+
+    /// These point into the library, as `Control::get_out_ports` returns borrowed names
+    pub(crate) out_ports: Vec<&'static str>,
+
+    pub(crate) ports: Vec<PortType>,
+
+    pub(crate) create: unsafe extern "C" fn() -> *mut c_void,
+    pub(crate) destroy: unsafe extern "C" fn(*mut c_void),
+    pub(crate) get_value: unsafe extern "C" fn(*mut c_void, usize) -> SampleType,
+    pub(crate) set_value: unsafe extern "C" fn(*mut c_void, usize, SampleType),
+    pub(crate) recv_key: unsafe extern "C" fn(*mut c_void, u32),
+}
+
+/// An instance created by a plugin. The host never uses an instance from two threads at once
+pub(crate) struct Instance(pub(crate) *mut c_void);
+
+unsafe impl Send for Instance {}
+unsafe impl Sync for Instance {}
+
+impl Plugin {
+    /// Load a plugin library. Fails if it wasn't built for this version of the ABI, or if its
+    /// descriptor is invalid.
+    ///
+    /// A copy of the library is loaded, rather than the library itself. Otherwise, loading it
+    /// again after it was rebuilt would just give the loaded library, and overwriting the
+    /// loaded file could crash the host
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, Box<dyn Error>> {
+        static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("{} isn't a file", path.display()))?;
+        let copy = std::env::temp_dir().join(format!(
+            "yat-{}-{}-{}",
+            process::id(),
+            COPIES.fetch_add(1, Relaxed),
+            file_name.to_string_lossy()
+        ));
+        fs::copy(path, &copy).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        // Loading a library runs its initialisers, which is as safe as the plugin is
+        let library = unsafe { Library::new(&copy) };
+        // A loaded library doesn't need its file anymore, except on Windows, where removing
+        // it fails and the copy is left behind
+        let _ = fs::remove_file(&copy);
+        let library = library.map_err(|e| {
+            // The loader's message names the copy, which the user doesn't know about
+            e.to_string()
+                .replace(&copy.display().to_string(), &path.display().to_string())
+        })?;
+
+        Self::from_library(path, library)
+            .map(Arc::new)
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Load the plugin's library again, e.g. after it was rebuilt. Fails if the library now
+    /// has another name. The plugin itself is left as it is, and stays usable if this fails
+    pub fn reload(&self) -> Result<Arc<Self>, Box<dyn Error>> {
+        let new = Self::load(&self.path)?;
+        if new.name != self.name {
+            return Err(format!(
+                "{} is now named {}, rather than {}",
+                self.path.display(),
+                new.name,
+                self.name
+            )
+            .into());
+        }
+
+        Ok(new)
+    }
+
+    fn from_library(path: &Path, library: Library) -> Result<Self, String> {
+        let version = unsafe {
+            let abi_version = library
+                .get::<unsafe extern "C" fn() -> u32>(ABI_VERSION_SYMBOL)
+                .map_err(|_| String::from("not a plugin, yat_plugin_abi_version is missing"))?;
+            abi_version()
+        };
+        if version != ABI_VERSION {
+            return Err(format!(
+                "built for plugin ABI version {}, but version {} is required",
+                version, ABI_VERSION
+            ));
+        }
+
+        let descriptor = unsafe {
+            let descriptor = library
+                .get::<unsafe extern "C" fn() -> *const PluginDescriptor>(DESCRIPTOR_SYMBOL)
+                .map_err(|_| String::from("yat_plugin_descriptor is missing"))?;
+            descriptor().as_ref()
+        }
+        .ok_or_else(|| String::from("the plugin descriptor is null"))?;
+
+        // The descriptor lives as long as the library, which the plugin keeps loaded
+        let (name, modules, controls) = unsafe {
+            let name = read_str(descriptor.name, "plugin name")?.to_string();
+            let modules = read_slice(descriptor.modules, descriptor.module_count, "modules")?
+                .iter()
+                .map(|module| read_module(module))
+                .collect::<Result<Vec<_>, _>>()?;
+            let controls = read_slice(descriptor.controls, descriptor.control_count, "controls")?
+                .iter()
+                .map(|control| read_control(control))
+                .collect::<Result<Vec<_>, _>>()?;
+            (name, modules, controls)
+        };
+
+        let mut type_names = HashSet::new();
+        let names = modules
+            .iter()
+            .map(|module| &module.name)
+            .chain(controls.iter().map(|control| &control.name));
+        for type_name in names {
+            if !type_names.insert(type_name) {
+                return Err(format!("type {} is described twice", type_name));
+            }
+        }
+
+        Ok(Self {
+            name,
+            path: path.to_path_buf(),
+            modules,
+            controls,
+            _library: library,
+        })
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The path which the library was loaded from
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The names of the module and control types which the plugin provides
+    pub fn types(&self) -> impl Iterator<Item = &str> {
+        self.modules
+            .iter()
+            .map(|module| module.name.as_str())
+            .chain(self.controls.iter().map(|control| control.name.as_str()))
+    }
+
+    /// Whether the plugin provides a type of the given name
+    pub fn has_type(&self, type_name: &str) -> bool {
+        self.types().any(|name| name == type_name)
+    }
+
+    /// Register the plugin's types. If any of their names is taken, none are registered
+    pub fn register(self: &Arc<Self>, registry: &mut ModuleRegistry) -> Result<(), Box<dyn Error>> {
+        if let Some(type_name) = self.types().find(|name| registry.get_info(name).is_some()) {
+            return Err(format!("{}: type {} is already registered", self.name, type_name).into());
+        }
+
+        for (index, module) in self.modules.iter().enumerate() {
+            let plugin = self.clone();
+            registry.register_module(&module.name, &module.description, move |id, ctx| {
+                Arc::new(Mutex::new(PluginModule::new(
+                    id,
+                    plugin.clone(),
+                    index,
+                    ctx.sample_rate,
+                )))
+            })?;
+        }
+
+        for (index, control) in self.controls.iter().enumerate() {
+            let plugin = self.clone();
+            registry.register_control(&control.name, &control.description, move |_| {
+                Arc::new(Mutex::new(PluginControl::new(plugin.clone(), index)))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the plugin's types from the registry
+    pub fn unregister(&self, registry: &mut ModuleRegistry) {
+        for type_name in self.types() {
+            registry.unregister(type_name);
+        }
+    }
+
+    pub(crate) fn get_module_type(&self, index: usize) -> &ModuleType {
+        &self.modules[index]
+    }
+
+    pub(crate) fn get_control_type(&self, index: usize) -> &ControlType {
+        &self.controls[index]
+    }
+}
+
+/// The plugin libraries in a directory, i.e. its files with the platform's library
+/// extension, ordered by name
+pub fn find_plugins<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path.extension().and_then(|ext| ext.to_str())
+                    == Some(std::env::consts::DLL_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+unsafe fn read_module(module: &ModuleDescriptor) -> Result<ModuleType, String> {
+    let name = read_str(module.type_name, "type name")?.to_string();
+    let in_ports = read_ports(module.in_ports, module.in_port_count)
+        .map_err(|e| format!("{}: {}", name, e))?;
+    let out_ports = read_ports(module.out_ports, module.out_port_count)
+        .map_err(|e| format!("{}: {}", name, e))?;
+
+    Ok(ModuleType {
+        description: read_str(module.description, "description")?.to_string(),
+        name,
+        in_ports,
+        out_ports,
+        create: module.create,
+        destroy: module.destroy,
+        set_sample_rate: module.set_sample_rate,
+        process: module.process,
+    })
+}
+
+unsafe fn read_control(control: &ControlDescriptor) -> Result<ControlType, String> {
+    let name = read_str(control.type_name, "type name")?.to_string();
+    let out_ports = read_slice(control.out_ports, control.out_port_count, "ports")
+        .and_then(|ports| {
+            ports
+                .iter()
+                .map(|port| read_str(port.name, "port name"))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| format!("{}: {}", name, e))?;
+    let ports = read_ports(control.out_ports, control.out_port_count)
+        .map_err(|e| format!("{}: {}", name, e))?;
+
+    Ok(ControlType {
+        description: read_str(control.description, "description")?.to_string(),
+        name,
+        out_ports,
+        ports,
+        create: control.create,
+        destroy: control.destroy,
+        get_value: control.get_value,
+        set_value: control.set_value,
+        recv_key: control.recv_key,
+    })
+}
+
+unsafe fn read_ports(ports: *const PortDescriptor, count: usize) -> Result<Vec<PortType>, String> {
+    read_slice(ports, count, "ports")?
+        .iter()
+        .map(|port| {
+            let name = read_str(port.name, "port name")?.to_string();
+            let unit = read_unit(port.unit).map_err(|e| format!("{}: {}", name, e))?;
+            let description = if port.description.is_null() {
+                String::new()
+            } else {
+                read_str(port.description, "port description")?.to_string()
+            };
+
+            Ok(PortType {
+                name,
+                lower_range: port.lower_range,
+                upper_range: port.upper_range,
+                default: port.default,
+                unit,
+                description,
+            })
+        })
+        .collect()
+}
+
+fn read_unit(unit: u32) -> Result<PortUnit, String> {
+    match unit {
+        UNIT_NONE => Ok(PortUnit::None),
+        UNIT_HZ => Ok(PortUnit::Hz),
+        UNIT_SECONDS => Ok(PortUnit::Seconds),
+        UNIT_GAIN => Ok(PortUnit::Gain),
+        UNIT_GATE => Ok(PortUnit::Gate),
+        UNIT_VOLT_PER_OCTAVE => Ok(PortUnit::VoltPerOctave),
+        _ => Err(format!("unknown unit {}", unit)),
+    }
+}
+
+/// Read a NUL-terminated UTF-8 string, which has to stay valid while the library is loaded
+unsafe fn read_str(ptr: *const c_char, what: &str) -> Result<&'static str, String> {
+    if ptr.is_null() {
+        return Err(format!("the {} is null", what));
+    }
+
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| format!("the {} isn't valid UTF-8", what))
+}
+
+/// Read an array, which has to stay valid while the library is loaded
+unsafe fn read_slice<T>(ptr: *const T, count: usize, what: &str) -> Result<&'static [T], String> {
+    match count {
+        0 => Ok(&[]),
+        _ if ptr.is_null() => Err(format!("the {} are null", what)),
+        _ => Ok(std::slice::from_raw_parts(ptr, count)),
+    }
+}
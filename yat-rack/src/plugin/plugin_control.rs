@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+
+use crate::controls::control::Control;
+use crate::out_port::{OutPort, PortRef};
+use crate::plugin::{ControlType, Instance, Plugin};
+use crate::port_descriptor::{PortDescriptor, PortDirection};
+use crate::types::SampleType;
+
+/// A control whose values are kept by a plugin instance
+pub(crate) struct PluginControl {
+    /// The control's output ports, holding the instance's values
+    out_ports: Vec<OutPort>,
+
+    /// Controls are used through shared references, so the instance is locked for each call
+    instance: Mutex<Instance>,
+
+    /// The index of the control's type in the plugin
+    index: usize,
+
+    /// Declared last, so that the library stays loaded until the instance is destroyed
+    plugin: Arc<Plugin>,
+}
+
+impl PluginControl {
+    /// Create a control of one of the plugin's control types
+    pub(crate) fn new(plugin: Arc<Plugin>, index: usize) -> Self {
+        let control_type = plugin.get_control_type(index);
+        let out_ports = control_type
+            .out_ports
+            .iter()
+            .map(|port_id| OutPort::new(port_id.to_string()))
+            .collect();
+        let instance = Mutex::new(Instance(unsafe { (control_type.create)() }));
+
+        let control = Self {
+            out_ports,
+            instance,
+            index,
+            plugin,
+        };
+        control.update_ports(&control.instance.lock().expect("Mutex lock is poisoned"));
+
+        control
+    }
+
+    fn get_type(&self) -> &ControlType {
+        self.plugin.get_control_type(self.index)
+    }
+
+    fn get_port_index(&self, port_id: &str) -> Option<usize> {
+        self.get_type()
+            .out_ports
+            .iter()
+            .position(|id| *id == port_id)
+    }
+
+    /// Copy the instance's values to the output ports
+    fn update_ports(&self, instance: &Instance) {
+        for (index, port) in self.out_ports.iter().enumerate() {
+            port.fill_value(unsafe { (self.get_type().get_value)(instance.0, index) });
+        }
+    }
+}
+
+impl Drop for PluginControl {
+    fn drop(&mut self) {
+        let instance = self.instance.get_mut().expect("Mutex lock is poisoned");
+        unsafe { (self.plugin.get_control_type(self.index).destroy)(instance.0) };
+    }
+}
+
+impl Control for PluginControl {
+    /// Get a reference to the control's output port
+    fn get_port_reference(&self, port_id: &str) -> Option<PortRef> {
+        Some(self.out_ports[self.get_port_index(port_id)?].get_ref())
+    }
+
+    fn get_out_ports(&self) -> &[&str] {
+        &self.get_type().out_ports
+    }
+
+    fn get_port_descriptors(&self) -> Vec<PortDescriptor> {
+        self.get_type()
+            .ports
+            .iter()
+            .map(|port| port.get_descriptor(PortDirection::Output))
+            .collect()
+    }
+
+    /// Set the controls output value. The plugin may adjust it, e.g. to keep it in range
+    fn set_value(&self, port_id: &str, new_value: SampleType) {
+        if let Some(index) = self.get_port_index(port_id) {
+            let instance = self.instance.lock().expect("Mutex lock is poisoned");
+            unsafe { (self.get_type().set_value)(instance.0, index, new_value) };
+            self.update_ports(&instance);
+        }
+    }
+
+    /// Pass a control key to the plugin, which may change any of the values
+    fn recv_control_key(&self, key: char) {
+        let instance = self.instance.lock().expect("Mutex lock is poisoned");
+        unsafe { (self.get_type().recv_key)(instance.0, key as u32) };
+        self.update_ports(&instance);
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use crate::in_port::InPort;
+use crate::modules::io_module::IoModule;
+use crate::out_port::{OutPort, PortRef};
+use crate::plugin::{Instance, ModuleType, Plugin};
+use crate::types::{PortNotFoundError, PortResult, SampleType, AUDIO_BUF_SIZE};
+
+/// A module whose processing is done by a plugin instance
+pub(crate) struct PluginModule {
+    /// A unique string used for identifying the module
+    id: String,
+
+    /// Order of the module in the chain, where 0 (zero) means skipped
+    order: Option<u64>,
+
+    input_ports: Vec<String>,
+
+    output_ports: Vec<String>,
+
+    /// In the order of the type's input ports
+    in_ports: Vec<InPort>,
+
+    /// In the order of the type's output ports
+    out_ports: Vec<OutPort>,
+
+    /// Scratch buffer holding a block of each input, as passed to the plugin
+    inputs: Vec<SampleType>,
+
+    /// Scratch buffer receiving a block of each output from the plugin
+    outputs: Vec<SampleType>,
+
+    instance: Instance,
+
+    /// The index of the module's type in the plugin
+    index: usize,
+
+    /// Declared last, so that the library stays loaded until the instance is destroyed
+    plugin: Arc<Plugin>,
+}
+
+impl PluginModule {
+    /// Create a new, unordered IoModule of one of the plugin's module types
+    pub(crate) fn new(
+        id: String,
+        plugin: Arc<Plugin>,
+        index: usize,
+        sample_rate: SampleType,
+    ) -> Self {
+        let module_type = plugin.get_module_type(index);
+        let order = None;
+        let input_ports = module_type
+            .in_ports
+            .iter()
+            .map(|port| port.name.clone())
+            .collect();
+        let output_ports = module_type
+            .out_ports
+            .iter()
+            .map(|port| port.name.clone())
+            .collect();
+
+        let in_ports = module_type
+            .in_ports
+            .iter()
+            .map(|port| {
+                let mut in_port = InPort::new(
+                    port.name.clone(),
+                    port.lower_range,
+                    port.upper_range,
+                    port.default,
+                );
+                in_port.set_unit(port.unit);
+                in_port.set_description(&port.description);
+                in_port
+            })
+            .collect();
+        let out_ports = module_type
+            .out_ports
+            .iter()
+            .map(|port| {
+                let mut out_port = OutPort::new(port.name.clone());
+                out_port.set_range(port.lower_range, port.upper_range);
+                out_port.set_unit(port.unit);
+                out_port.set_description(&port.description);
+                out_port
+            })
+            .collect();
+
+        let inputs = vec![0.0; AUDIO_BUF_SIZE * module_type.in_ports.len()];
+        let outputs = vec![0.0; AUDIO_BUF_SIZE * module_type.out_ports.len()];
+        let instance = Instance(unsafe { (module_type.create)(sample_rate) });
+
+        Self {
+            id,
+            order,
+            input_ports,
+            output_ports,
+            in_ports,
+            out_ports,
+            inputs,
+            outputs,
+            instance,
+            index,
+            plugin,
+        }
+    }
+
+    fn get_type(&self) -> &ModuleType {
+        self.plugin.get_module_type(self.index)
+    }
+
+    /// Let the plugin process the frames in the scratch buffers
+    fn process_frames(&mut self, frames: usize) {
+        unsafe {
+            (self.get_type().process)(
+                self.instance.0,
+                self.inputs.as_ptr(),
+                self.outputs.as_mut_ptr(),
+                frames,
+            );
+        }
+    }
+}
+
+impl Drop for PluginModule {
+    fn drop(&mut self) {
+        unsafe { (self.get_type().destroy)(self.instance.0) };
+    }
+}
+
+impl IoModule for PluginModule {
+    /// Process a single frame
+    fn process_inputs(&mut self) {
+        for (input, port) in self.inputs.iter_mut().zip(&self.in_ports) {
+            *input = port.get_value();
+        }
+
+        self.process_frames(1);
+
+        for (output, port) in self.outputs.iter().zip(&self.out_ports) {
+            port.set_value(*output);
+        }
+    }
+
+    /// Process a whole block at once, as the plugin works on blocks anyway
+    fn process_block(&mut self, frames: usize) {
+        if frames == 0 {
+            return;
+        }
+
+        // Blocks are at most AUDIO_BUF_SIZE frames, so this shouldn't allocate
+        self.inputs.resize(frames * self.in_ports.len(), 0.0);
+        self.outputs.resize(frames * self.out_ports.len(), 0.0);
+
+        for (block, port) in self.inputs.chunks_mut(frames).zip(&self.in_ports) {
+            port.read_block(block);
+        }
+
+        self.process_frames(frames);
+
+        for (block, port) in self.outputs.chunks(frames).zip(&self.out_ports) {
+            port.write_block(block);
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: SampleType) {
+        unsafe { (self.get_type().set_sample_rate)(self.instance.0, sample_rate) };
+    }
+
+    /// Return a module's ID
+    fn get_id(&self) -> &String {
+        &self.id
+    }
+
+    fn get_in_ports(&self) -> &Vec<String> {
+        &self.input_ports
+    }
+
+    fn get_out_ports(&self) -> &Vec<String> {
+        &self.output_ports
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn has_port_with_id(&self, port_id: &str) -> bool {
+        self.get_in_port_ref(port_id).is_some()
+    }
+
+    /// Return a reference to one of the module's input ports
+    fn get_in_port_ref(&self, port_id: &str) -> Option<&InPort> {
+        let index = self.input_ports.iter().position(|id| id == port_id)?;
+        self.in_ports.get(index)
+    }
+
+    /// Return a mutable reference to one of the module's input ports
+    fn get_in_port_mut(&mut self, port_id: &str) -> Option<&mut InPort> {
+        let index = self.input_ports.iter().position(|id| id == port_id)?;
+        self.in_ports.get_mut(index)
+    }
+
+    fn get_out_port_ref(&self, port_id: &str) -> Option<&OutPort> {
+        let index = self.output_ports.iter().position(|id| id == port_id)?;
+        self.out_ports.get(index)
+    }
+
+    /// Set the value of a module's input port
+    fn set_in_port(&mut self, port_id: &str, out_port_ref: PortRef) -> PortResult<String> {
+        self.get_in_port_mut(port_id)
+            .ok_or(PortNotFoundError)?
+            .set_value(out_port_ref);
+
+        Ok(format!("{}: Set port {}\n", self.get_id(), port_id))
+    }
+
+    fn get_module_order(&self) -> Option<u64> {
+        self.order
+    }
+
+    fn set_module_order(&mut self, new_order: Option<u64>) {
+        self.order = new_order;
+    }
+}
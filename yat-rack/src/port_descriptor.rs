@@ -0,0 +1,131 @@
+use std::fmt;
+
+use crate::types::SampleType;
+
+/// Whether a port receives or sends a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortDirection {
+    Input,
+    Output,
+}
+
+/// What a port's signal means
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PortUnit {
+    /// A plain number, e.g. audio or the operands of an adder
+    #[default]
+    None,
+
+    /// A frequency
+    Hz,
+
+    /// A duration
+    Seconds,
+
+    /// A factor applied to another signal, e.g. an amplitude
+    Gain,
+
+    /// On (1) or off (0), e.g. whether a key is held down
+    Gate,
+
+    /// A pitch, where each volt is an octave
+    VoltPerOctave,
+}
+
+impl fmt::Display for PortUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self {
+            PortUnit::None => "",
+            PortUnit::Hz => "Hz",
+            PortUnit::Seconds => "s",
+            PortUnit::Gain => "gain",
+            PortUnit::Gate => "gate",
+            PortUnit::VoltPerOctave => "V/oct",
+        };
+
+        write!(f, "{}", unit)
+    }
+}
+
+/// Describes a module's or control's port, e.g. for showing it to the user, or for checking
+/// the values sent to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortDescriptor {
+    /// The port's ID, as used when connecting it
+    pub label: String,
+
+    pub direction: PortDirection,
+
+    /// Suggested bounds of the port's value. Unbounded ports span the whole `SampleType`
+    pub lower_range: SampleType,
+    pub upper_range: SampleType,
+
+    /// The value of an unconnected input port. Output ports have none
+    pub default: Option<SampleType>,
+
+    pub unit: PortUnit,
+
+    /// What the port does, if the module describes it
+    pub description: String,
+}
+
+impl PortDescriptor {
+    /// An unbounded output port, without unit or description
+    pub fn output(label: &str) -> Self {
+        Self {
+            label: label.into(),
+            direction: PortDirection::Output,
+            lower_range: SampleType::MIN,
+            upper_range: SampleType::MAX,
+            default: None,
+            unit: PortUnit::None,
+            description: String::new(),
+        }
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.lower_range > SampleType::MIN || self.upper_range < SampleType::MAX
+    }
+
+    /// The range as text, e.g. `0 to 1`, or `at least 0` if only the lower bound is set
+    pub fn range_text(&self) -> String {
+        match (
+            self.lower_range > SampleType::MIN,
+            self.upper_range < SampleType::MAX,
+        ) {
+            (true, true) => format!("{} to {}", self.lower_range, self.upper_range),
+            (true, false) => format!("at least {}", self.lower_range),
+            (false, true) => format!("at most {}", self.upper_range),
+            (false, false) => String::from("any value"),
+        }
+    }
+
+    /// Whether a value lies within the port's range
+    pub fn in_range(&self, value: SampleType) -> bool {
+        self.lower_range <= value && value <= self.upper_range
+    }
+}
+
+impl fmt::Display for PortDescriptor {
+    /// Write the label, followed by the unit, range and default, where there are any, e.g.
+    /// `freq (Hz, 0 to 20000, default 1000)`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut details = Vec::new();
+        if self.unit != PortUnit::None {
+            details.push(self.unit.to_string());
+        }
+        if self.is_bounded() {
+            details.push(self.range_text());
+        }
+        if let Some(default) = self.default {
+            details.push(format!("default {}", default));
+        }
+
+        write!(f, "{}", self.label)?;
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,22 +1,29 @@
 use hashbrown::HashMap;
+use std::collections::VecDeque;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
-use std::thread;
 
 use crate::clock::Clock;
-use crate::controls::basic_keyboard::BasicKeyboard;
-use crate::controls::button::Button;
+use crate::command::{Command, CommandError, CommandOutput, InfoData, PluginAction, Response};
+use crate::connection::Connection;
 use crate::controls::control::Control;
-use crate::controls::control_knob::ControlKnob;
 use crate::event::Event;
-use crate::modules::adsr::Adsr;
+use crate::info::{Info, ItemPorts, PluginInfo};
 use crate::modules::io_module::IoModule;
-use crate::modules::oscillator::Oscillator;
+use crate::out_port::PortRef;
+use crate::patch::{
+    ConnectionEntry, ControlEntry, ModuleEntry, Patch, PatchError, PortEntry, PATCH_VERSION,
+};
+use crate::plugin::{self, Plugin};
+use crate::registry::{ModuleContext, ModuleRegistry, RackItem};
+use crate::worker_pool::WorkerPool;
 use crate::types::{
     ConflictingModuleIdError, ModuleNotFoundError, ModuleResult, PortNotFoundError, SampleType,
+    AUDIO_BUF_SIZE, SAMPLE_RATE,
 };
 
 /// A Rack encompasses a group of conntected modules
@@ -27,15 +34,39 @@ pub struct Rack {
     /// Controls: these don't require an order to be processed
     controls: HashMap<String, Arc<Mutex<dyn Control + Send + Sync>>>,
 
+    /// The module and control types which can be added by name
+    registry: ModuleRegistry,
+
+    /// The types of the modules and controls which were added by type. Modules which were
+    /// added directly, e.g. by the host, have none
+    module_types: HashMap<String, String>,
+
+    /// The plugin libraries whose types were registered, in the order they were loaded
+    plugins: Vec<Arc<Plugin>>,
+
     /// The control which currently holds the focus
     focussed_control: Option<Arc<Mutex<dyn Control + Send + Sync>>>,
 
-    /// Ordered modules for sequential processing
-    module_chain: HashMap<u64, Vec<Arc<Mutex<dyn IoModule + Send + Sync>>>>,
+    /// Connections between module/control outputs and module inputs, in the order they were made
+    connections: Vec<Connection>,
+
+    /// The index to be given to the next connection
+    next_connection_index: u64,
+
+    /// Ordered modules for sequential processing. Each entry holds the modules of one position
+    /// in the chain, which only depend on modules in earlier positions
+    module_chain: Vec<Vec<Arc<Mutex<dyn IoModule + Send + Sync>>>>,
+
+    msg_queue: Option<mpsc::Sender<Response>>,
+
+    /// Queues which are sent the response to every command, see `subscribe`
+    subscribers: Vec<mpsc::Sender<Response>>,
 
-    msg_queue: Option<mpsc::Sender<String>>,
+    /// The number of frames processed per call to `process_module_chain`
+    block_size: usize,
 
-    event_queue: Option<mpsc::Receiver<Event>>,
+    /// Worker threads for processing modules in parallel. None for single-threaded processing
+    worker_pool: Option<WorkerPool>,
 
     /// The Rack's clock keeps track of timing. This is passed to modules
     /// whose output rely on time
@@ -46,72 +77,190 @@ pub struct Rack {
 }
 
 impl Rack {
-    /// Create a new, empty Rack
+    /// Create a new, empty Rack, running at the default sample rate
     pub fn new() -> Self {
+        Self::with_sample_rate(SAMPLE_RATE)
+    }
+
+    /// Create a new, empty Rack, running at the given sample rate, e.g. the audio device's
+    pub fn with_sample_rate(sample_rate: SampleType) -> Self {
         let modules = HashMap::new();
         let controls = HashMap::new();
         let focussed_control = None;
-        let module_chain = HashMap::new();
-        let clock = Arc::new(RwLock::new(Clock::new()));
+        let connections = Vec::new();
+        let module_chain = Vec::new();
+        let block_size = AUDIO_BUF_SIZE;
+        let clock = Arc::new(RwLock::new(Clock::new(sample_rate)));
         let running = AtomicBool::new(true);
 
-        let rack = Self {
+        Self {
             modules,
             controls,
+            registry: ModuleRegistry::new(),
+            module_types: HashMap::new(),
+            plugins: Vec::new(),
             focussed_control,
+            connections,
+            next_connection_index: 0,
             module_chain,
             msg_queue: None,
-            event_queue: None,
+            subscribers: Vec::new(),
+            block_size,
+            worker_pool: None,
             clock,
             running,
-        };
-
-        rack
+        }
     }
 
     // TODO: Event logic should be handled in the Event server
-    pub fn get_msg_sender(&self) -> Option<Sender<String>> {
+    pub fn get_msg_sender(&self) -> Option<Sender<Response>> {
         self.msg_queue.clone()
     }
 
-    pub fn recv_msg(&mut self) {
-        thread::scope(|c_scope| {
-            c_scope.spawn(move || {
-                if let Some(queue) = &self.event_queue {
-                    let event = queue.recv().unwrap();
-                    self.parse_event(event);
-                };
-            });
-        });
+    /// Set the queue which responses to commands are written to
+    pub fn set_msg_queue(&mut self, msg_queue: Sender<Response>) {
+        self.msg_queue = Some(msg_queue);
     }
 
-    fn write_msg_queue(&mut self, msg: String) {
+    /// Receive the responses to every command from now on, wherever it was sent from, e.g. to
+    /// follow the changes made to the Rack. The queue is dropped once the receiver is
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Response> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn write_msg_queue(&mut self, response: Response) {
         if let Some(queue) = &self.msg_queue {
-            queue.send(msg).expect("TODO: panic message");
-        }
-    }
-
-    fn parse_event(&mut self, msg: Event) {
-        let mut response = String::new();
-        match msg {
-            Event::Command(cmd, args) => {
-                // Lets say this is for info
-                if cmd == "info" {
-                    match args.get("data").expect("data for info not specified").as_str() {
-                        "ports" => response = self.print_ports(None),
-                        "modules" => response = self.print_modules(),
-                        "connections" => response = self.print_connection(),
-                        "module_order" => response = self.print_module_order(),
-                        _ => response = String::from("Unknow command"),
+            // Nobody is listening for responses anymore, which is fine
+            let _ = queue.send(response);
+        }
+    }
+
+    fn write_subscribers(&mut self, response: &Response) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(response.clone()).is_ok());
+    }
+
+    /// Apply an event to the Rack and write the response to a command to the message queue,
+    /// or to the request's own queue
+    pub fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Command(command) => {
+                let result = self.handle_command(&command);
+                self.respond(Response { command, result }, None);
+            }
+            Event::Request(command, reply) => {
+                let result = self.handle_command(&command);
+                self.respond(Response { command, result }, Some(reply));
+            }
+            Event::Midi(status, data1, data2) => {
+                self.recv_midi(0, &[status, data1, data2]);
+            }
+        }
+    }
+
+    /// Write the response to a command which was applied by other means than `handle_event`,
+    /// e.g. by the engine, to the subscribers and the message queue, or to a request's queue
+    pub fn respond(&mut self, response: Response, reply: Option<Sender<Response>>) {
+        self.write_subscribers(&response);
+        match reply {
+            // The requester might have gone away in the meantime
+            Some(reply) => {
+                let _ = reply.send(response);
+            }
+            None => self.write_msg_queue(response),
+        }
+    }
+
+    /// Apply a script of commands, one per line, as parsed by `Command::from_str`. Empty
+    /// lines and lines starting with '#' are skipped. Stops at the first failing command
+    pub fn run_script(&mut self, script: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let mut responses = String::new();
+
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let result = line
+                .parse::<Command>()
+                .and_then(|command| self.handle_command(&command));
+
+            match result {
+                Ok(output) => {
+                    responses.push_str(&output.to_string());
+                    responses.push('\n');
+                }
+                Err(e) => return Err(format!("line {}: {}", number + 1, e).into()),
+            }
+        }
+
+        Ok(responses)
+    }
+
+    /// Apply a command and return its output
+    pub fn handle_command(&mut self, command: &Command) -> Result<CommandOutput, CommandError> {
+        match command {
+            Command::Add { module_type, id } => Ok(self.add_module_type(module_type, id)?.into()),
+            Command::Remove { id } => Ok(self.remove_module(id)?.into()),
+            Command::Connect {
+                out_module,
+                out_port,
+                in_module,
+                in_port,
+                gain,
+            } => {
+                let response = self.connect_modules(out_module, out_port, in_module, in_port)?;
+                match gain {
+                    Some(gain) => {
+                        self.set_cable_gain(out_module, out_port, in_module, in_port, *gain)?;
+                        Ok(format!("{}, with a gain of {}", response, gain).into())
                     }
+                    None => Ok(response.into()),
                 }
             }
-            Event::Midi(_status, _data1, _data2) => {
-                // TODO: Handle MIDI
-                response = String::from("MIDI not implemented");
+            Command::Disconnect {
+                in_module,
+                in_port,
+                out,
+            } => match out {
+                Some((out_module, out_port)) => Ok(self
+                    .disconnect_cable(out_module, out_port, in_module, in_port)?
+                    .into()),
+                None => Ok(self.disconnect_module(in_module, in_port)?.into()),
+            },
+            Command::Summing {
+                module,
+                port,
+                summing,
+            } => Ok(self.set_summing(module, port, *summing)?.into()),
+            Command::Set { ctrl, port, value } => {
+                self.set_ctrl_value(ctrl, port, *value)?;
+                Ok(CommandOutput::Value {
+                    ctrl: ctrl.clone(),
+                    port: port.clone(),
+                    value: *value,
+                })
+            }
+            Command::Focus { ctrl } => Ok(self.set_focus_control(ctrl)?.into()),
+            Command::Run => {
+                self.run();
+                Ok(String::from("Running").into())
             }
-        };
-        self.write_msg_queue(response);
+            Command::Stop => {
+                self.stop();
+                Ok(String::from("Stopped").into())
+            }
+            Command::Info(data) => Ok(CommandOutput::Info(self.get_info(data))),
+            Command::Save { path } => Ok(self.save(path)?.into()),
+            Command::Load { path } => Ok(self.load(path)?.into()),
+            Command::Plugin(PluginAction::Load { path }) => Ok(self.load_plugin(path)?.into()),
+            Command::Plugin(PluginAction::Reload { name }) => Ok(self.reload_plugin(name)?.into()),
+            // Quitting is up to the engine, there's nothing to do for the Rack
+            Command::Quit => Ok(String::new().into()),
+        }
     }
     // -------------------------------------------------
 
@@ -129,16 +278,22 @@ impl Rack {
         &mut self,
         module: Arc<Mutex<dyn IoModule + Send + Sync>>
     ) -> Result<String, Box<dyn std::error::Error>> {
-        let module_id = module.lock().expect("Mutex poisoned").get_id().clone();
+        let module_id = {
+            let mut module = module.lock().expect("Mutex poisoned");
+            // The module may have been created for a different rate
+            module.set_sample_rate(self.get_sample_rate());
+            module.get_id().clone()
+        };
         if self.modules.contains_key(&module_id) || self.controls.contains_key(&module_id) {
             return Err(Box::new(ConflictingModuleIdError));
         }
         self.modules.insert(module_id.clone(), module.to_owned());
+        self.update_module_chain();
 
         Ok(format!("Added module: {}", module_id))
     }
 
-    // TODO: Remove function; The Rack only needs to know about IoModule and Controls
+    /// Add a module or control of a type from the registry
     pub fn add_module_type(
         &mut self,
         module_type: &str,
@@ -150,33 +305,38 @@ impl Rack {
             return Err(Box::new(ConflictingModuleIdError));
         }
 
-        match module_type {
-            // Controls
-            "control" => {
-                let control_knob = Arc::new(Mutex::new(ControlKnob::new(module_id.into())));
-                self.controls.insert(module_id.into(), control_knob);
-            }
-            "button" => {
-                let button = Arc::new(Mutex::new(Button::new(module_id.into())));
-                self.controls.insert(module_id.into(), button);
-            }
-            "keyboard" => {
-                let keyboard = Arc::new(Mutex::new(BasicKeyboard::new(module_id.into())));
-                self.controls.insert(module_id.into(), keyboard);
-            }
-            // Modules
-            "osc" => {
-                let oscillator = Arc::new(Mutex::new(Oscillator::new(module_id.into())));
-                self.modules.insert(module_id.into(), oscillator);
+        let item = self
+            .create_item(module_type, module_id)
+            .ok_or_else(|| format!("Unknown type {}, see \"info types\"", module_type))?;
+        self.insert_item(module_type, module_id, item);
+        self.update_module_chain();
+
+        Ok(format!("Add {} with id {}", module_type, module_id))
+    }
+
+    /// Create a module or control of the given type, if the type is registered
+    fn create_item(&self, module_type: &str, module_id: &str) -> Option<RackItem> {
+        let context = ModuleContext {
+            sample_rate: self.get_sample_rate(),
+            clock: self.clock.clone(),
+        };
+
+        self.registry.create(module_type, module_id, &context)
+    }
+
+    /// Put a module or control, created by type, into the Rack. The module chain has to be
+    /// updated afterwards
+    fn insert_item(&mut self, module_type: &str, module_id: &str, item: RackItem) {
+        match item {
+            RackItem::Module(module) => {
+                self.modules.insert(module_id.into(), module);
             }
-            "adsr" => {
-                let adsr = Arc::new(Mutex::new(Adsr::new(module_id.into(), self.clock.clone())));
-                self.modules.insert(module_id.into(), adsr);
+            RackItem::Control(control) => {
+                self.controls.insert(module_id.into(), control);
             }
-            _ => return Err(Box::new(ModuleNotFoundError)),
         }
-
-        Ok(format!("Add {} with id {}", module_type, module_id))
+        self.module_types
+            .insert(module_id.into(), module_type.into());
     }
     // ----------------------------------------------------------------------
 
@@ -195,148 +355,77 @@ impl Rack {
             }
         }
 
-        // TODO: Is there a more sane way to do this?
-        // Check for presence of the key and remove the module from the hash map if it exists
-        // This is the only way I see to have mutible references to two elements of the HashMap
-        // Get output module and port references
-        let (out_module, out_port) = match self.modules.get(out_module_id) {
+        // Get output port reference
+        let out_port = match self.modules.get(out_module_id) {
             Some(module) => {
                 let out_module = module.lock().expect("Mutex lock is poisoned");
                 match out_module.get_out_port_ref(out_port_id) {
-                    Some(port) => (module, port.get_ref()),
+                    Some(port) => port.get_ref(),
                     None => return Err(Box::new(PortNotFoundError)),
                 }
             }
-            None => {
-                println!("Module {} does not exist", out_module_id);
-                return Err(Box::new(ModuleNotFoundError));
-            }
+            None => return Err(Box::new(ModuleNotFoundError)),
         };
 
+        // Attach output port to input port
+        let summing = self.attach_cable(in_module_id, in_port_id, out_port)?;
 
-        // Attach output port to input port and get input module reference
-        let in_module = match self.modules.get(in_module_id) {
-            Some(module) => {
-                let mut in_module = module.lock().expect("Mutex lock is poisoned");
+        self.add_connection(out_module_id, out_port_id, in_module_id, in_port_id, summing);
+        self.update_module_chain();
 
-                if in_module.has_port_with_id(in_port_id) {
-                    in_module.set_in_port(in_port_id, out_port)?;
-                    module
-                } else {
-                    return Err(Box::new(ModuleNotFoundError));
-                }
-            }
-            None => {
-                println!("Module {} does not exist", out_module_id);
-                return Err(Box::new(ModuleNotFoundError));
-            }
+        let feedback = match self.connections.last() {
+            Some(conn) if conn.is_feedback() => " (feedback, delayed by one sample)",
+            _ => "",
         };
 
-        // Set module order
-        let out_module_order = out_module
-            .lock()
-            .expect("Mutex lock is poisoned")
-            .get_module_order();
-
-        let in_module_order = in_module
-            .lock()
-            .expect("Mutex lock is poisoned")
-            .get_module_order();
+        Ok(format!(
+            "connected module {} -> {} to {} -> {}{}",
+            out_module_id, out_port_id, in_module_id, in_port_id, feedback
+        ))
+    }
 
-        match (out_module_order, in_module_order) {
-            (None, None) => {
-                out_module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .set_module_order(Some(1));
-                in_module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .set_module_order(Some(2));
+    /// Remove a module or control from the Rack, disconnecting every input it was connected to
+    pub fn remove_module(&mut self, module_id: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let removed = if let Some(module) = self.modules.remove(module_id) {
+            RackItem::Module(module)
+        } else if let Some(control) = self.controls.remove(module_id) {
+            let is_focussed = self
+                .focussed_control
+                .as_ref()
+                .is_some_and(|focussed| Arc::ptr_eq(focussed, &control));
+            if is_focussed {
+                self.focussed_control = None;
             }
-            (None, Some(order)) => {
-                self.module_chain
-                    .get_mut(&order)
-                    .unwrap()
-                    .retain(|module| !Arc::ptr_eq(module, &in_module));
-                if order == 1 {
-                    out_module
-                        .lock()
-                        .expect("Mutex lock is poisoned")
-                        .set_module_order(Some(order));
-                    in_module
-                        .lock()
-                        .expect("Mutex lock is poisoned")
-                        .set_module_order(Some(order + 1));
-                } else {
-                    out_module
-                        .lock()
-                        .expect("Mutex lock is poisoned")
-                        .set_module_order(Some(order - 1));
-                    in_module
-                        .lock()
-                        .expect("Mutex lock is poisoned")
-                        .set_module_order(Some(order));
-                }
-            }
-            (Some(order), None) => {
-                self.module_chain
-                    .get_mut(&order)
-                    .unwrap()
-                    .retain(|module| !Arc::ptr_eq(module, out_module));
-                in_module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .set_module_order(Some(order + 1));
+            RackItem::Control(control)
+        } else {
+            return Err(Box::new(ModuleNotFoundError));
+        };
+
+        // Disconnect the inputs fed by the removed module, so they don't hold on to its outputs
+        for conn in &self.connections {
+            if conn.get_out_module_id() != module_id || conn.get_in_module_id() == module_id {
+                continue;
             }
-            (Some(_), Some(order)) => {
-                self.module_chain
-                    .get_mut(&out_module_order.unwrap())
-                    .unwrap()
-                    .retain(|module| !Arc::ptr_eq(module, out_module));
-                self.module_chain
-                    .get_mut(&order)
-                    .unwrap()
-                    .retain(|module| !Arc::ptr_eq(module, &in_module));
-                in_module
+
+            let out_port = removed.get_port_reference(conn.get_out_port_id());
+            if let (Some(out_port), Some(module)) =
+                (out_port, self.modules.get(conn.get_in_module_id()))
+            {
+                if let Some(in_port) = module
                     .lock()
                     .expect("Mutex lock is poisoned")
-                    .set_module_order(Some(order + 1));
+                    .get_in_port_mut(conn.get_in_port_id())
+                {
+                    in_port.remove_source(&out_port);
+                }
             }
         }
 
-        // Add entry to module position for this functions order
-        self.module_chain
-            .entry(
-                out_module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .get_module_order()
-                    .unwrap(),
-            )
-            // If there's no entry for the key, create a new Vec and return a mutable ref to it
-            .or_default()
-            // and insert the item onto the Vec
-            //.insert(out_module.get_id().clone());
-            .push(out_module.clone());
-
-        self.module_chain
-            .entry(
-                in_module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .get_module_order()
-                    .unwrap(),
-            )
-            // If there's no entry for the key, create a new Vec and return a mutable ref to it
-            .or_default()
-            // and insert the item onto the Vec
-            .push(in_module.clone());
+        self.connections.retain(|conn| !conn.involves(module_id));
+        self.module_types.remove(module_id);
+        self.update_module_chain();
 
-        Ok(format!(
-            "connected module {} -> {} to {} -> {}",
-            out_module_id, out_port_id, in_module_id, in_port_id
-        ))
+        Ok(format!("Removed {}", module_id))
     }
 
     pub fn disconnect_module(
@@ -354,6 +443,9 @@ impl Rack {
             .expect("Mutex lock is poisoned")
             .set_in_port(port_id, Weak::new())?;
 
+        self.connections.retain(|conn| !conn.feeds(module_id, port_id));
+        self.update_module_chain();
+
         Ok(format!(
             "disconnected {} from module {}",
             port_id, module_id
@@ -382,20 +474,174 @@ impl Rack {
             None => return Err(Box::new(PortNotFoundError)),
         };
 
-        let in_module = self.modules.get(in_module_id);
+        let summing = self.attach_cable(in_module_id, in_port_id, ctrl_port)?;
+
+        self.add_connection(ctrl_id, ctrl_port_id, in_module_id, in_port_id, summing);
+        self.update_module_chain();
+
+        Ok(format!(
+            "connected control {} -> {} to module {} -> {}",
+            ctrl_id, ctrl_port_id, in_module_id, in_port_id
+        ))
+    }
+
+    /// Disconnect a single cable, leaving any other cables into the input port in place
+    pub fn disconnect_cable(
+        &mut self,
+        out_module_id: &str,
+        out_port_id: &str,
+        in_module_id: &str,
+        in_port_id: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let out_port = self.get_out_port(out_module_id, out_port_id)?;
+
+        let removed = self
+            .get_in_module(in_module_id)?
+            .lock()
+            .expect("Mutex lock is poisoned")
+            .get_in_port_mut(in_port_id)
+            .ok_or(PortNotFoundError)?
+            .remove_source(&out_port);
+        if !removed {
+            return Err(Box::new(PortNotFoundError));
+        }
+
+        self.connections.retain(|conn| {
+            !(conn.feeds(in_module_id, in_port_id) && conn.comes_from(out_module_id, out_port_id))
+        });
+        self.update_module_chain();
+
+        Ok(format!(
+            "disconnected {} -> {} from {} -> {}",
+            out_module_id, out_port_id, in_module_id, in_port_id
+        ))
+    }
+
+    /// Set the gain of a single cable, e.g. to attenuate one of the signals summed by an input
+    pub fn set_cable_gain(
+        &mut self,
+        out_module_id: &str,
+        out_port_id: &str,
+        in_module_id: &str,
+        in_port_id: &str,
+        gain: SampleType,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let out_port = self.get_out_port(out_module_id, out_port_id)?;
+
+        let updated = self
+            .get_in_module(in_module_id)?
+            .lock()
+            .expect("Mutex lock is poisoned")
+            .get_in_port_mut(in_port_id)
+            .ok_or(PortNotFoundError)?
+            .set_source_gain(&out_port, gain);
+        if !updated {
+            return Err(Box::new(PortNotFoundError));
+        }
+
+        for conn in self.connections.iter_mut() {
+            if conn.feeds(in_module_id, in_port_id) && conn.comes_from(out_module_id, out_port_id) {
+                conn.set_gain(gain);
+            }
+        }
+
+        Ok(format!(
+            "set gain of {} -> {} to {} -> {} to {}",
+            out_module_id, out_port_id, in_module_id, in_port_id, gain
+        ))
+    }
+
+    /// Allow an input port to take several cables, whose signals are summed. When summing is
+    /// turned off, only the most recent cable into the port is kept
+    pub fn set_summing(
+        &mut self,
+        module_id: &str,
+        port_id: &str,
+        summing: bool,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.get_in_module(module_id)?
+            .lock()
+            .expect("Mutex lock is poisoned")
+            .get_in_port_mut(port_id)
+            .ok_or(PortNotFoundError)?
+            .set_summing(summing);
+
+        if !summing {
+            let latest = self
+                .connections
+                .iter()
+                .filter(|conn| conn.feeds(module_id, port_id))
+                .map(|conn| conn.get_index())
+                .max();
+            self.connections.retain(|conn| {
+                !conn.feeds(module_id, port_id) || Some(conn.get_index()) == latest
+            });
+            self.update_module_chain();
+        }
+
+        Ok(format!(
+            "{} -> {} summing {}",
+            module_id,
+            port_id,
+            if summing { "on" } else { "off" }
+        ))
+    }
 
-        let message = match in_module {
-            Some(module) => module
+    /// Get a reference to a module's or control's output port
+    fn get_out_port(
+        &self,
+        module_id: &str,
+        port_id: &str,
+    ) -> Result<PortRef, Box<dyn std::error::Error>> {
+        let port = if let Some(module) = self.modules.get(module_id) {
+            module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_out_port_ref(port_id)
+                .map(|port| port.get_ref())
+        } else if let Some(control) = self.controls.get(module_id) {
+            control
                 .lock()
                 .expect("Mutex lock is poisoned")
-                .set_in_port(in_port_id, ctrl_port)?,
+                .get_port_reference(port_id)
+        } else {
+            return Err(Box::new(ModuleNotFoundError));
+        };
+
+        match port {
+            Some(port) => Ok(port),
+            None => Err(Box::new(PortNotFoundError)),
+        }
+    }
+
+    fn get_in_module(
+        &self,
+        module_id: &str,
+    ) -> ModuleResult<&Arc<Mutex<dyn IoModule + Send + Sync>>> {
+        self.modules.get(module_id).ok_or(ModuleNotFoundError)
+    }
+
+    /// Connect an output port to a module's input port.
+    /// Returns whether the input port is summing, i.e., whether its other cables were kept
+    fn attach_cable(
+        &self,
+        in_module_id: &str,
+        in_port_id: &str,
+        out_port: PortRef,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let module = match self.modules.get(in_module_id) {
+            Some(module) => module,
             None => return Err(Box::new(ModuleNotFoundError)),
         };
 
-        Ok(format!(
-            "connected control {} -> {} to module {} -> {}. {}",
-            ctrl_id, ctrl_port_id, in_module_id, in_port_id, message
-        ))
+        let mut in_module = module.lock().expect("Mutex lock is poisoned");
+        let in_port = match in_module.get_in_port_mut(in_port_id) {
+            Some(port) => port,
+            None => return Err(Box::new(PortNotFoundError)),
+        };
+        in_port.add_source(out_port, 1.0);
+
+        Ok(in_port.is_summing())
     }
 
     pub fn set_focus_control(&mut self, ctrl_id: &str) -> ModuleResult<String> {
@@ -417,161 +663,896 @@ impl Rack {
         }
     }
 
+    /// Set the value of a control's output port. Values outside the port's range are refused
     pub fn set_ctrl_value(
         &mut self,
         ctrl_id: &str,
         port_id: &str,
         value: SampleType,
-    ) -> ModuleResult<String> {
-        match self.controls.get(ctrl_id) {
-            Some(ctrl) => ctrl
-                .lock()
-                .expect("Mutex lock is poisoned")
-                .set_value(port_id, value),
-            None => return Err(ModuleNotFoundError),
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let ctrl = self.controls.get(ctrl_id).ok_or(ModuleNotFoundError)?;
+        let ctrl = ctrl.lock().expect("Mutex lock is poisoned");
+
+        let port = ctrl
+            .get_port_descriptors()
+            .into_iter()
+            .find(|port| port.label == port_id)
+            .ok_or(PortNotFoundError)?;
+        if !port.in_range(value) {
+            return Err(format!(
+                "{} is out of range for {} {}, which takes {}",
+                value,
+                ctrl_id,
+                port_id,
+                port.range_text()
+            )
+            .into());
         }
 
+        ctrl.set_value(port_id, value);
+
         Ok(format!("Updated control {}", ctrl_id))
     }
 
-    pub fn print_ports(&self, module_id: Option<&str>) -> String {
-        let mut output = String::from("Ports: \n");
-        if let Some(module_id) = module_id {
-            //if self.modules.contains_key(module_id) {
-            if let Some(module) = self.modules.get(module_id) {
-                output.push_str("Module - ");
-                output.push_str(module_id);
-                output.push_str(":\n");
-
-                output.push_str("    inputs:\n");
-                for id in module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .get_in_ports()
+    pub fn get_registry(&self) -> &ModuleRegistry {
+        &self.registry
+    }
+
+    /// Get the registry, e.g. to register a crate's own module types
+    pub fn get_registry_mut(&mut self) -> &mut ModuleRegistry {
+        &mut self.registry
+    }
+
+    /// Load a plugin library and register its module and control types
+    pub fn load_plugin<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.add_plugin(Plugin::load(path)?)
+    }
+
+    /// Register the types of a loaded plugin library, see `Plugin::load`
+    pub fn add_plugin(
+        &mut self,
+        plugin: Arc<Plugin>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if self.plugins.iter().any(|loaded| loaded.get_name() == plugin.get_name()) {
+            return Err(format!("Plugin {} is already loaded", plugin.get_name()).into());
+        }
+        plugin.register(&mut self.registry)?;
+
+        let types: Vec<&str> = plugin.types().collect();
+        let msg = format!(
+            "Loaded plugin {} with types: {}",
+            plugin.get_name(),
+            types.join(", ")
+        );
+        self.plugins.push(plugin);
+
+        Ok(msg)
+    }
+
+    /// Load every plugin library in a directory. A plugin which fails to load doesn't keep
+    /// the others from loading, its error is part of the output
+    pub fn load_plugin_dir<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let paths = plugin::find_plugins(dir.as_ref())?;
+        if paths.is_empty() {
+            return Ok(format!("No plugins in {}", dir.as_ref().display()));
+        }
+
+        let mut output = String::new();
+        for path in paths {
+            match self.load_plugin(&path) {
+                Ok(msg) => output.push_str(&format!("{}\n", msg)),
+                Err(e) => output.push_str(&format!("{}\n", e)),
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Returns a loaded plugin library
+    pub fn get_plugin(&self, name: &str) -> Option<Arc<Plugin>> {
+        self.plugins
+            .iter()
+            .find(|plugin| plugin.get_name() == name)
+            .cloned()
+    }
+
+    /// Load a plugin's library again, e.g. after rebuilding it, and swap it in, see
+    /// `swap_plugin`. If the library fails to load, the old one stays in place
+    pub fn reload_plugin(&mut self, name: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let old = self
+            .get_plugin(name)
+            .ok_or_else(|| format!("Plugin {} isn't loaded", name))?;
+
+        self.swap_plugin(old.reload()?)
+    }
+
+    /// Swap the loaded plugin of the same name for a new library, see `Plugin::reload`, and
+    /// each module and control of its types for a new one. These keep their IDs, connections,
+    /// summing inputs and control values, but the state of the plugin's instances is lost.
+    ///
+    /// The engine loads the library before locking the Rack, so only the swap happens in
+    /// between blocks
+    pub fn swap_plugin(&mut self, new: Arc<Plugin>) -> Result<String, Box<dyn std::error::Error>> {
+        let name = new.get_name().to_string();
+        let index = self
+            .plugins
+            .iter()
+            .position(|plugin| plugin.get_name() == name)
+            .ok_or_else(|| format!("Plugin {} isn't loaded", name))?;
+        let old = self.plugins[index].clone();
+
+        old.unregister(&mut self.registry);
+        if let Err(e) = new.register(&mut self.registry) {
+            // None of the new types were registered, so the old ones can be put back
+            old.register(&mut self.registry)?;
+            return Err(e);
+        }
+        self.plugins[index] = new;
+
+        let mut items: Vec<(String, String)> = self
+            .module_types
+            .iter()
+            .filter(|(_, module_type)| old.has_type(module_type))
+            .map(|(id, module_type)| (id.clone(), module_type.clone()))
+            .collect();
+        items.sort();
+
+        let mut output = String::new();
+        let mut swapped = 0;
+        for (id, module_type) in items {
+            let result = match self.create_item(&module_type, &id) {
+                Some(item) => self.replace_item(&id, item),
+                None => Err(format!("{}: kept, as type {} is gone", id, module_type)),
+            };
+
+            match result {
+                Ok(dropped) => {
+                    swapped += 1;
+                    for conn in dropped {
+                        output.push_str(&format!(
+                            "\n{}: dropped connection {}, as the port is gone",
+                            id, conn
+                        ));
+                    }
+                }
+                Err(msg) => output.push_str(&format!("\n{}", msg)),
+            }
+        }
+        self.update_module_chain();
+
+        Ok(format!(
+            "Reloaded plugin {}, swapped {} modules and controls{}",
+            name, swapped, output
+        ))
+    }
+
+    /// Swap a module or control for a new one of the same kind, moving its connections,
+    /// summing inputs and control values over. Returns the connections which were dropped,
+    /// as the new one lacks their ports
+    fn replace_item(&mut self, id: &str, item: RackItem) -> Result<Vec<Connection>, String> {
+        let old = match (&item, self.modules.get(id), self.controls.get(id)) {
+            (RackItem::Module(new_module), Some(old_module), _) => {
                 {
-                    output.push_str("        ");
-                    output.push_str(id);
-                    output.push('\n');
+                    let old_module = old_module.lock().expect("Mutex lock is poisoned");
+                    let mut new_module = new_module.lock().expect("Mutex lock is poisoned");
+                    for port_id in old_module.get_in_ports() {
+                        let old_port = old_module.get_in_port_ref(port_id);
+                        let new_port = new_module.get_in_port_mut(port_id);
+                        if let (Some(old_port), Some(new_port)) = (old_port, new_port) {
+                            new_port.set_summing(old_port.is_summing());
+                        }
+                    }
                 }
-                output.push_str("    outputs:\n");
-                for id in module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .get_out_ports()
+
+                let old_module = self.modules.insert(id.into(), new_module.clone());
+                RackItem::Module(old_module.expect("the module was just found"))
+            }
+            (RackItem::Control(new_control), _, Some(old_control)) => {
                 {
-                    output.push_str("        ");
-                    output.push_str(id);
-                    output.push('\n');
+                    let old_control = old_control.lock().expect("Mutex lock is poisoned");
+                    let new_control = new_control.lock().expect("Mutex lock is poisoned");
+                    for port_id in old_control.get_out_ports() {
+                        if !new_control.get_out_ports().contains(port_id) {
+                            continue;
+                        }
+                        if let Some(value) = old_control.get_value(port_id) {
+                            new_control.set_value(port_id, value);
+                        }
+                    }
+                }
+
+                let is_focussed = self
+                    .focussed_control
+                    .as_ref()
+                    .is_some_and(|focussed| Arc::ptr_eq(focussed, old_control));
+                if is_focussed {
+                    self.focussed_control = Some(new_control.clone());
                 }
+
+                let old_control = self.controls.insert(id.into(), new_control.clone());
+                RackItem::Control(old_control.expect("the control was just found"))
             }
-        } else {
-            for (module_id, module) in &self.modules {
-                output.push_str("Module - ");
-                output.push_str(module_id);
-                output.push_str(":\n");
+            _ => {
+                return Err(format!(
+                    "{}: kept, as its type changed between module and control",
+                    id
+                ))
+            }
+        };
 
-                output.push_str("    inputs:\n");
-                for id in module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .get_in_ports()
-                {
-                    output.push_str("        ");
-                    output.push_str(id);
-                    output.push('\n');
+        // Cables into the new module are connected from scratch, cables out of the old one
+        // are moved over to the new one
+        let mut dropped = Vec::new();
+        for conn in self.connections.clone() {
+            if !conn.involves(id) {
+                continue;
+            }
+
+            let new_out = self
+                .get_out_port(conn.get_out_module_id(), conn.get_out_port_id())
+                .ok();
+            let old_out = if conn.get_in_module_id() == id {
+                None
+            } else {
+                old.get_port_reference(conn.get_out_port_id())
+            };
+
+            let mut rewired = false;
+            if let Some(module) = self.modules.get(conn.get_in_module_id()) {
+                let mut module = module.lock().expect("Mutex lock is poisoned");
+                if let Some(in_port) = module.get_in_port_mut(conn.get_in_port_id()) {
+                    if let Some(old_out) = old_out {
+                        in_port.remove_source(&old_out);
+                    }
+                    if let Some(new_out) = new_out {
+                        in_port.add_source(new_out, conn.get_gain());
+                        rewired = true;
+                    }
                 }
-                output.push_str("    outputs:\n");
-                for id in module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
+            }
+
+            if !rewired {
+                self.connections
+                    .retain(|other| other.get_index() != conn.get_index());
+                dropped.push(conn);
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    pub fn print_plugins(&self) -> String {
+        self.get_info(&InfoData::Plugins).to_string()
+    }
+
+    /// Returns the output ports of every control, as (control ID, port ID) pairs
+    pub fn get_control_ports(&self) -> Vec<(String, String)> {
+        let mut ports = Vec::new();
+        for (ctrl_id, ctrl) in &self.controls {
+            for port_id in ctrl.lock().expect("Mutex lock is poisoned").get_out_ports() {
+                ports.push((ctrl_id.clone(), port_id.to_string()));
+            }
+        }
+
+        ports
+    }
+
+    /// Save the Rack's modules, controls, connections and control values to a patch file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<String, Box<dyn std::error::Error>> {
+        self.to_patch().write(path.as_ref())?;
+
+        Ok(format!("Saved patch to {}", path.as_ref().display()))
+    }
+
+    /// Replace the Rack's patch with one loaded from a file, see `apply_patch`
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<String, Box<dyn std::error::Error>> {
+        let patch = Patch::read(path.as_ref())?;
+        let msg = self.apply_patch(&patch)?;
+
+        Ok(format!("Loaded {} from {}", msg, path.as_ref().display()))
+    }
+
+    /// Describe the Rack as a patch. Modules which weren't added by type, e.g. by the host,
+    /// are left out, but their connections aren't
+    pub fn to_patch(&self) -> Patch {
+        let mut modules = Vec::new();
+        let mut controls = Vec::new();
+        for (id, module_type) in &self.module_types {
+            if let Some(control) = self.controls.get(id) {
+                let control = control.lock().expect("Mutex lock is poisoned");
+                let values = control
                     .get_out_ports()
+                    .iter()
+                    .filter_map(|port_id| Some((port_id.to_string(), control.get_value(port_id)?)))
+                    .collect();
+
+                controls.push(ControlEntry {
+                    control_type: module_type.clone(),
+                    id: id.clone(),
+                    values,
+                });
+            } else {
+                modules.push(ModuleEntry {
+                    module_type: module_type.clone(),
+                    id: id.clone(),
+                });
+            }
+        }
+        modules.sort_by(|a, b| a.id.cmp(&b.id));
+        controls.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut summing = Vec::new();
+        for (id, module) in &self.modules {
+            let module = module.lock().expect("Mutex lock is poisoned");
+            for port_id in module.get_in_ports() {
+                if module
+                    .get_in_port_ref(port_id)
+                    .is_some_and(|port| port.is_summing())
                 {
-                    output.push_str("        ");
-                    output.push_str(id);
-                    output.push('\n');
+                    summing.push(PortEntry {
+                        module: id.clone(),
+                        port: port_id.clone(),
+                    });
+                }
+            }
+        }
+        summing.sort();
+
+        let connections = self
+            .connections
+            .iter()
+            .map(|conn| ConnectionEntry {
+                out_module: conn.get_out_module_id().into(),
+                out_port: conn.get_out_port_id().into(),
+                in_module: conn.get_in_module_id().into(),
+                in_port: conn.get_in_port_id().into(),
+                gain: conn.get_gain(),
+            })
+            .collect();
+
+        Patch {
+            version: PATCH_VERSION,
+            modules,
+            controls,
+            summing,
+            connections,
+        }
+    }
+
+    /// Replace the Rack's modules, controls and connections with those of a patch. Modules
+    /// which weren't added by type, e.g. by the host, are kept, but disconnected.
+    ///
+    /// The whole patch is checked before the Rack is changed, and every problem is reported,
+    /// e.g. unknown module types and ports, or connections to missing modules
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<String, PatchError> {
+        if patch.version > PATCH_VERSION {
+            return Err(PatchError {
+                problems: vec![format!(
+                    "version {} is newer than the supported version {}",
+                    patch.version, PATCH_VERSION
+                )],
+            });
+        }
+
+        let mut problems = Vec::new();
+
+        // Create the patch's modules and controls up front, so that their ports can be checked
+        let entries = patch
+            .modules
+            .iter()
+            .map(|module| (&module.module_type, &module.id, false))
+            .chain(
+                patch
+                    .controls
+                    .iter()
+                    .map(|control| (&control.control_type, &control.id, true)),
+            );
+        let mut items: HashMap<&str, (&str, RackItem)> = HashMap::new();
+        for (module_type, id, is_control) in entries {
+            if items.contains_key(id.as_str()) || self.is_host_item(id) {
+                problems.push(format!("{}: the ID is already taken", id));
+                continue;
+            }
+
+            match self.create_item(module_type, id) {
+                Some(item) if matches!(item, RackItem::Control(_)) == is_control => {
+                    items.insert(id, (module_type, item));
+                }
+                Some(_) if is_control => {
+                    problems.push(format!("{}: {} is a module, not a control", id, module_type))
+                }
+                Some(_) => {
+                    problems.push(format!("{}: {} is a control, not a module", id, module_type))
+                }
+                None => problems.push(format!("{}: unknown type {}", id, module_type)),
+            }
+        }
+
+        // The ports of everything the patch can refer to, by ID: inputs, outputs and whether
+        // it is a control
+        let mut ports: HashMap<String, (Vec<String>, Vec<String>, bool)> = HashMap::new();
+        for (id, (_, item)) in &items {
+            let is_control = matches!(item, RackItem::Control(_));
+            ports.insert(id.to_string(), (item.get_in_ports(), item.get_out_ports(), is_control));
+        }
+        for (id, module) in &self.modules {
+            if self.is_host_item(id) {
+                let item = RackItem::Module(module.clone());
+                ports.insert(id.clone(), (item.get_in_ports(), item.get_out_ports(), false));
+            }
+        }
+        for (id, control) in &self.controls {
+            if self.is_host_item(id) {
+                let item = RackItem::Control(control.clone());
+                ports.insert(id.clone(), (item.get_in_ports(), item.get_out_ports(), true));
+            }
+        }
+
+        let check_in_port = |problems: &mut Vec<String>, context: &str, module: &str, port| {
+            match ports.get(module) {
+                Some((_, _, true)) => problems.push(format!(
+                    "{}: {} is a control, which has no inputs",
+                    context, module
+                )),
+                Some((in_ports, _, _)) if !in_ports.iter().any(|id| id == port) => problems
+                    .push(format!("{}: {} has no input port {}", context, module, port)),
+                Some(_) => {}
+                None => problems.push(format!("{}: there is no module {}", context, module)),
+            }
+        };
+
+        for control in &patch.controls {
+            let descriptors = match items.get(control.id.as_str()) {
+                Some((_, item)) => item.get_port_descriptors(),
+                None => continue,
+            };
+            for (port, value) in &control.values {
+                match descriptors.iter().find(|descriptor| descriptor.label == *port) {
+                    Some(descriptor) if !descriptor.in_range(*value) => problems.push(format!(
+                        "{}: {} is out of range for {}, which takes {}",
+                        control.id,
+                        value,
+                        port,
+                        descriptor.range_text()
+                    )),
+                    Some(_) => {}
+                    None => problems.push(format!("{}: no output port {}", control.id, port)),
+                }
+            }
+        }
+
+        for entry in &patch.summing {
+            check_in_port(&mut problems, "summing", &entry.module, &entry.port);
+        }
+
+        for (index, conn) in patch.connections.iter().enumerate() {
+            let context = format!("connection {}", index + 1);
+            match ports.get(conn.out_module.as_str()) {
+                Some((_, out_ports, _)) if !out_ports.contains(&conn.out_port) => {
+                    problems.push(format!(
+                        "{}: {} has no output port {}",
+                        context, conn.out_module, conn.out_port
+                    ))
+                }
+                Some(_) => {}
+                None => problems.push(format!(
+                    "{}: there is no module or control {}",
+                    context, conn.out_module
+                )),
+            }
+            check_in_port(&mut problems, &context, &conn.in_module, &conn.in_port);
+        }
+
+        if !problems.is_empty() {
+            return Err(PatchError { problems });
+        }
+
+        // Clear the current patch, keeping the host's modules
+        let typed_ids: Vec<String> = self.module_types.keys().cloned().collect();
+        for id in typed_ids {
+            let _ = self.remove_module(&id);
+        }
+        for conn in self.connections.clone() {
+            let _ = self.disconnect_cable(
+                conn.get_out_module_id(),
+                conn.get_out_port_id(),
+                conn.get_in_module_id(),
+                conn.get_in_port_id(),
+            );
+        }
+        for module in self.modules.values() {
+            let mut module = module.lock().expect("Mutex lock is poisoned");
+            for port_id in module.get_in_ports().clone() {
+                if let Some(port) = module.get_in_port_mut(&port_id) {
+                    port.set_summing(false);
                 }
             }
         }
 
-        output.push('\n');
-        output
+        let (module_count, control_count) = (patch.modules.len(), patch.controls.len());
+        for (id, (module_type, item)) in items {
+            self.insert_item(module_type, id, item);
+        }
+        self.update_module_chain();
+
+        // Everything was checked, so these shouldn't fail, but any failure is still reported
+        for entry in &patch.summing {
+            if let Err(e) = self.set_summing(&entry.module, &entry.port, true) {
+                problems.push(format!("summing {} -> {}: {}", entry.module, entry.port, e));
+            }
+        }
+        for control in &patch.controls {
+            for (port, value) in &control.values {
+                if let Err(e) = self.set_ctrl_value(&control.id, port, *value) {
+                    problems.push(format!("{}: {}", control.id, e));
+                }
+            }
+        }
+        for (index, conn) in patch.connections.iter().enumerate() {
+            let result = self
+                .connect_modules(&conn.out_module, &conn.out_port, &conn.in_module, &conn.in_port)
+                .and_then(|_| {
+                    self.set_cable_gain(
+                        &conn.out_module,
+                        &conn.out_port,
+                        &conn.in_module,
+                        &conn.in_port,
+                        conn.gain,
+                    )
+                });
+            if let Err(e) = result {
+                problems.push(format!("connection {}: {}", index + 1, e));
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(PatchError { problems });
+        }
+
+        Ok(format!(
+            "{} modules, {} controls and {} connections",
+            module_count,
+            control_count,
+            patch.connections.len()
+        ))
+    }
+
+    /// Whether the module or control was added by the host, rather than by type
+    fn is_host_item(&self, id: &str) -> bool {
+        (self.modules.contains_key(id) || self.controls.contains_key(id))
+            && !self.module_types.contains_key(id)
+    }
+
+    /// Print the ports of every module and control, with their units, ranges and defaults. The
+    /// ports of a single module or control are printed along with their descriptions
+    pub fn print_ports(&self, module_id: Option<&str>) -> String {
+        self.get_info(&InfoData::Ports(module_id.map(String::from)))
+            .to_string()
+    }
+
+    /// Returns the connections between the Rack's items, in the order they were made
+    pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.iter()
     }
 
     /// Print the connections between a Rack's items
     pub fn print_connection(&self) -> String {
-        // TODO
-        String::from("Not implemented")
+        self.get_info(&InfoData::Connections).to_string()
     }
 
     pub fn print_module_order(&self) -> String {
-        let mut output = String::from("Module order:\n");
-        for (order, modules) in self.module_chain.iter() {
-            output.push_str("    Modules in position ");
-            output.push_str(&order.to_string());
-            output.push_str(":\n");
-            for module in modules {
-                output.push_str("        ");
-                output.push_str(module.lock().expect("Mutex lock is poisoned").get_id());
-                output.push('\n');
+        self.get_info(&InfoData::ModuleOrder).to_string()
+    }
+
+    pub fn print_modules(&self) -> String {
+        self.get_info(&InfoData::Modules).to_string()
+    }
+
+    /// Describe a part of the Rack, as asked for by an `info` command
+    pub fn get_info(&self, data: &InfoData) -> Info {
+        match data {
+            InfoData::Ports(module_id) => self.get_ports_info(module_id.as_deref()),
+            InfoData::Modules => Info::Modules {
+                modules: self.modules.keys().cloned().collect(),
+                controls: self.controls.keys().cloned().collect(),
+            },
+            InfoData::Connections => Info::Connections(self.connections.clone()),
+            InfoData::ModuleOrder => Info::ModuleOrder(
+                self.module_chain
+                    .iter()
+                    .map(|modules| {
+                        modules
+                            .iter()
+                            .map(|module| {
+                                module
+                                    .lock()
+                                    .expect("Mutex lock is poisoned")
+                                    .get_id()
+                                    .clone()
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            ),
+            InfoData::Types => self.registry.get_types_info(),
+            InfoData::Plugins => Info::Plugins(
+                self.plugins
+                    .iter()
+                    .map(|plugin| PluginInfo {
+                        name: plugin.get_name().to_string(),
+                        path: plugin.get_path().to_path_buf(),
+                        types: plugin.types().map(String::from).collect(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The ports of every module and control, or with their descriptions, of a single one
+    fn get_ports_info(&self, module_id: Option<&str>) -> Info {
+        let modules = self.modules.iter().map(|(id, module)| ItemPorts {
+            id: id.clone(),
+            is_control: false,
+            ports: module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_port_descriptors(),
+        });
+        let controls = self.controls.iter().map(|(id, control)| ItemPorts {
+            id: id.clone(),
+            is_control: true,
+            ports: control
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_port_descriptors(),
+        });
+
+        let items = modules
+            .chain(controls)
+            .filter(|item| module_id.is_none_or(|module_id| item.id == module_id))
+            .collect();
+
+        Info::Ports {
+            items,
+            with_descriptions: module_id.is_some(),
+        }
+    }
+
+    /// Process one block of `block_size` frames
+    pub fn process_module_chain(&mut self) {
+        // Feedback connections must be delayed by exactly one sample, rather than one block,
+        // so a patch containing feedback is processed a single frame at a time
+        let has_feedback = self
+            .connections
+            .iter()
+            .any(|conn| conn.is_feedback());
+        let (passes, frames) = if has_feedback {
+            (self.block_size, 1)
+        } else {
+            (1, self.block_size)
+        };
+
+        // Process modules in order. Modules of equal order don't depend on each other, so they
+        // can be processed at the same time if the Rack has worker threads
+        for _ in 0..passes {
+            for modules in &self.module_chain {
+                match &self.worker_pool {
+                    Some(pool) if modules.len() > 1 => pool.process(modules, frames),
+                    _ => {
+                        for module in modules {
+                            module
+                                .lock()
+                                .expect("Mutex lock is poisoned")
+                                .process_block(frames);
+                        }
+                    }
+                }
             }
         }
 
-        output.push('\n');
-        output
+        // After each module has been processed update the time for the next round of processing
+        self.clock
+            .write()
+            .expect("RwLock is poisoned")
+            .advance(self.block_size);
     }
 
-    pub fn print_modules(&self) -> String {
-        let mut output = String::from("Modules:\n");
-        for module in self.modules.keys() {
-            output.push_str("    ");
-            output.push_str(module);
-            output.push('\n');
+    /// Set the number of frames processed per block. This is limited to `AUDIO_BUF_SIZE`,
+    /// which is the size of each port's buffer
+    pub fn set_block_size(&mut self, block_size: usize) -> String {
+        self.block_size = block_size.clamp(1, AUDIO_BUF_SIZE);
+
+        format!("Block size set to {}", self.block_size)
+    }
+
+    pub fn get_block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Set the number of worker threads used for processing independent modules in parallel.
+    /// With fewer than two workers, the chain is processed on the calling thread only
+    pub fn set_worker_threads(&mut self, workers: usize) -> String {
+        if workers < 2 {
+            self.worker_pool = None;
+            return String::from("Processing on a single thread");
         }
 
-        output.push('\n');
+        self.worker_pool = Some(WorkerPool::new(workers));
+
+        format!("Processing with {} worker threads", workers)
+    }
+
+    pub fn get_worker_threads(&self) -> usize {
+        self.worker_pool.as_ref().map_or(0, |pool| pool.size())
+    }
 
-        output.push_str("Controls:\n");
-        for control in self.controls.keys() {
-            output.push_str("    ");
-            output.push_str(control);
-            output.push('\n');
+    /// Change the sample rate of the clock and every module, e.g. when the audio device is
+    /// opened at a different rate. The clock and all time-dependent module state is reset
+    pub fn set_sample_rate(
+        &mut self,
+        sample_rate: SampleType,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if !(sample_rate.is_finite() && sample_rate > 0.0) {
+            return Err(format!("Invalid sample rate: {}", sample_rate).into());
         }
 
-        output.push('\n');
+        self.clock
+            .write()
+            .expect("RwLock is poisoned")
+            .set_sample_rate(sample_rate);
 
-        output
+        for module in self.modules.values() {
+            module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .set_sample_rate(sample_rate);
+        }
+
+        Ok(format!("Sample rate set to {}", sample_rate))
     }
 
-    pub fn process_module_chain(&mut self) {
-        let order_max = self.get_order_max().unwrap_or(&0).to_owned();
-
-        // Process modules in order
-        // FIXME - This has potential to be parallelised as modules of
-        // equal order should be able to process at the same time
-        for position in 1..=order_max {
-            for module in self.module_chain.get(&position).unwrap() {
-                module
-                    .lock()
-                    .expect("Mutex lock is poisoned")
-                    .process_inputs();
+    pub fn get_sample_rate(&self) -> SampleType {
+        self.clock
+            .read()
+            .expect("RwLock is poisoned")
+            .get_sample_rate()
+    }
+
+    /// Record a connection. Unless the input port is summing, this replaces any previous
+    /// connection to it, as the port can then only be fed by a single output
+    fn add_connection(
+        &mut self,
+        out_module_id: &str,
+        out_port_id: &str,
+        in_module_id: &str,
+        in_port_id: &str,
+        summing: bool,
+    ) {
+        self.connections.retain(|conn| {
+            !(conn.feeds(in_module_id, in_port_id)
+                && (!summing || conn.comes_from(out_module_id, out_port_id)))
+        });
+
+        self.connections.push(Connection::new(
+            self.next_connection_index,
+            out_module_id.into(),
+            out_port_id.into(),
+            in_module_id.into(),
+            in_port_id.into(),
+        ));
+        self.next_connection_index += 1;
+    }
+
+    /// Find the connections which close a feedback loop.
+    ///
+    /// Connections are visited in the order they were made. A connection closes a loop if its
+    /// output module can already be reached from its input module through the connections
+    /// visited before it, so the most recent connection of a loop is always the one which is
+    /// delayed.
+    fn mark_feedback_connections(&mut self) {
+        let mut feedback_paths = Vec::with_capacity(self.connections.len());
+        {
+            let mut successors: HashMap<&str, Vec<&str>> = HashMap::new();
+            for conn in &self.connections {
+                let out_id = conn.get_out_module_id();
+                let in_id = conn.get_in_module_id();
+                if !self.modules.contains_key(out_id) || !self.modules.contains_key(in_id) {
+                    feedback_paths.push(None);
+                    continue;
+                }
+
+                let path = find_path(&successors, in_id, out_id);
+                if path.is_none() {
+                    successors.entry(out_id).or_default().push(in_id);
+                }
+                feedback_paths.push(path);
             }
         }
 
-        // After each module has been processed update the time for the next round of processing
-        self.clock.write().expect("RwLock is poisoned").increment();
+        for (conn, path) in self.connections.iter_mut().zip(feedback_paths) {
+            conn.set_feedback_path(path);
+        }
     }
 
-    // Returns the highest value order in the module_chain hashmap
-    fn get_order_max(&self) -> Option<&u64> {
-        self.module_chain.keys().to_owned().reduce(
-            |accum, item| {
-                if accum >= item {
-                    accum
-                } else {
-                    item
+    /// Recalculate the processing order of the modules from the connection graph.
+    ///
+    /// This is a topological sort (Kahn's algorithm), where each module is placed one position
+    /// after the latest of the modules feeding into it. Modules without any module inputs, e.g.
+    /// unconnected modules or those only fed by controls, are placed in the first position.
+    /// Feedback connections are left out of the sort, which leaves the graph acyclic.
+    fn update_module_chain(&mut self) {
+        self.mark_feedback_connections();
+
+        // Sort IDs so that the order within a position is stable
+        let mut module_ids: Vec<&String> = self.modules.keys().collect();
+        module_ids.sort();
+
+        // Count the distinct modules feeding each module, and the modules each one feeds into.
+        // Controls aren't part of the chain, so their connections are ignored
+        let mut in_degree: HashMap<&str, usize> =
+            module_ids.iter().map(|id| (id.as_str(), 0)).collect();
+        let mut successors: HashMap<&str, Vec<&str>> = HashMap::new();
+        for conn in &self.connections {
+            let out_id = conn.get_out_module_id();
+            let in_id = conn.get_in_module_id();
+            if !self.modules.contains_key(out_id)
+                || !self.modules.contains_key(in_id)
+                || conn.is_feedback()
+            {
+                continue;
+            }
+
+            let next = successors.entry(out_id).or_default();
+            if !next.contains(&in_id) {
+                next.push(in_id);
+                *in_degree.get_mut(in_id).expect("Module is in the Rack") += 1;
+            }
+        }
+
+        let mut position: HashMap<&str, usize> = HashMap::new();
+        let mut ready: Vec<&str> = module_ids
+            .iter()
+            .map(|id| id.as_str())
+            .filter(|id| in_degree[id] == 0)
+            .collect();
+        ready.reverse();
+
+        while let Some(id) = ready.pop() {
+            let pos = *position.entry(id).or_insert(0);
+            for &next in successors.get(id).into_iter().flatten() {
+                let next_pos = position.entry(next).or_insert(0);
+                *next_pos = (*next_pos).max(pos + 1);
+
+                let degree = in_degree.get_mut(next).expect("Module is in the Rack");
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push(next);
                 }
-            },
-        )
+            }
+        }
+
+        let mut module_chain: Vec<Vec<Arc<Mutex<dyn IoModule + Send + Sync>>>> = Vec::new();
+        for id in module_ids {
+            let pos = position[id.as_str()];
+            if module_chain.len() <= pos {
+                module_chain.resize_with(pos + 1, Vec::new);
+            }
+
+            let module = &self.modules[id];
+            module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .set_module_order(Some(pos as u64 + 1));
+            module_chain[pos].push(module.clone());
+        }
+
+        self.module_chain = module_chain;
     }
 
     // TODO: Should this be handled in Rack and if so, would it be more appropriate
@@ -586,8 +1567,526 @@ impl Rack {
     // ----------------
 }
 
+/// Find a path from one module to another, following the given successors.
+/// Returns the module IDs along the path, including both ends.
+fn find_path(successors: &HashMap<&str, Vec<&str>>, from: &str, to: &str) -> Option<Vec<String>> {
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+
+    while let Some(id) = queue.pop_front() {
+        if id == to {
+            let mut path = vec![id.to_string()];
+            let mut current = id;
+            while current != from {
+                current = previous[current];
+                path.push(current.to_string());
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for &next in successors.get(id).into_iter().flatten() {
+            if next != from && !previous.contains_key(next) {
+                previous.insert(next, id);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
 impl Default for Rack {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::output::Output;
+    use crate::ring_buffer::Consumer;
+
+    /// A Rack built from a script, with a small block size
+    fn rack_with(script: &str) -> Rack {
+        let mut rack = Rack::new();
+        rack.set_block_size(4);
+        rack.run_script(script).unwrap();
+        rack
+    }
+
+    fn order(rack: &Rack, module_id: &str) -> Option<u64> {
+        rack.modules[module_id]
+            .lock()
+            .expect("Mutex lock is poisoned")
+            .get_module_order()
+    }
+
+    /// The IDs of the modules in each position of the chain
+    fn chain(rack: &Rack) -> Vec<Vec<String>> {
+        rack.module_chain
+            .iter()
+            .map(|modules| {
+                modules
+                    .iter()
+                    .map(|module| {
+                        module
+                            .lock()
+                            .expect("Mutex lock is poisoned")
+                            .get_id()
+                            .clone()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn orders_modules_after_their_inputs() {
+        let rack = rack_with(
+            "add adder d
+            add adder c
+            add adder b
+            add adder a
+            add adder e
+            connect a sum b a
+            connect a sum c a
+            connect b sum d a
+            connect c sum d b",
+        );
+
+        assert_eq!(chain(&rack), [vec!["a", "e"], vec!["b", "c"], vec!["d"]]);
+        assert_eq!(order(&rack, "d"), Some(3));
+    }
+
+    #[test]
+    fn places_modules_after_their_latest_input() {
+        let rack = rack_with(
+            "add adder a
+            add adder b
+            add adder c
+            connect a sum b a
+            connect b sum c a
+            connect a sum c b",
+        );
+
+        assert_eq!(chain(&rack), [["a"], ["b"], ["c"]]);
+    }
+
+    #[test]
+    fn ignores_controls_when_ordering() {
+        let rack = rack_with(
+            "add control k
+            add adder a
+            add adder b
+            connect k value b a",
+        );
+
+        assert_eq!(chain(&rack), [["a", "b"]]);
+    }
+
+    fn feedback_paths(rack: &Rack) -> Vec<Option<Vec<String>>> {
+        rack.connections()
+            .map(|conn| conn.get_feedback_path().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn delays_the_connection_closing_a_loop() {
+        let rack = rack_with(
+            "add adder a
+            add adder b
+            add adder c
+            connect a sum b a
+            connect b sum c a
+            connect c sum a a",
+        );
+
+        assert_eq!(
+            feedback_paths(&rack),
+            [None, None, Some(vec!["a".into(), "b".into(), "c".into()])]
+        );
+        assert_eq!(chain(&rack), [["a"], ["b"], ["c"]]);
+        assert!(rack
+            .print_connection()
+            .contains("feedback loop, delayed by one sample: a -> b -> c -> a"));
+    }
+
+    #[test]
+    fn delays_the_most_recent_connection_of_a_loop() {
+        let rack = rack_with(
+            "add adder a
+            add adder b
+            connect b sum a a
+            connect a sum b a",
+        );
+
+        assert_eq!(
+            feedback_paths(&rack),
+            [None, Some(vec!["b".into(), "a".into()])]
+        );
+        assert_eq!(chain(&rack), [["b"], ["a"]]);
+    }
+
+    #[test]
+    fn delays_a_module_feeding_itself() {
+        let rack = rack_with("add adder a\nconnect a sum a a");
+
+        assert_eq!(feedback_paths(&rack), [Some(vec!["a".into()])]);
+        assert_eq!(chain(&rack), [["a"]]);
+    }
+
+    #[test]
+    fn processes_feedback_one_sample_at_a_time() {
+        let mut rack = rack_with(
+            "add control k
+            add adder a
+            add adder b
+            set k value 1
+            connect k value a b
+            connect a sum b a
+            connect b sum a a",
+        );
+        let (output, consumer) = Output::new("out".into(), 1);
+        rack.add_module(Arc::new(Mutex::new(output))).unwrap();
+        rack.run_script("connect a sum out signal_in").unwrap();
+
+        // Each sample adds 1 to the previous one, which a only sees one sample later
+        rack.process_module_chain();
+        let block: Vec<SampleType> = (0..4).map(|_| consumer.pop().unwrap()).collect();
+        assert_eq!(block, [1.0, 2.0, 3.0, 4.0]);
+        assert!(consumer.pop().is_none());
+    }
+
+    /// Oscillators at different frequencies, summed in pairs, so that each position of the
+    /// chain has several modules for the workers to share
+    const PARALLEL_PATCH: &str = "add control k1
+        add control k2
+        add control k3
+        add osc o1
+        add osc o2
+        add osc o3
+        add osc o4
+        add adder a
+        add adder b
+        add multiplier m
+        set k1 value 220
+        set k2 value 330
+        set k3 value 0.5
+        connect k1 value o1 freq
+        connect k2 value o2 freq
+        connect k3 value o3 amp
+        connect o1 audio_out a a
+        connect o2 audio_out a b
+        connect o3 audio_out b a
+        connect o4 audio_out b b
+        connect a sum m a
+        connect b sum m b";
+
+    /// A Rack built from a script, whose `m` module feeds an `out` module
+    fn output_rack(script: &str) -> (Rack, Consumer) {
+        let mut rack = Rack::new();
+        rack.set_block_size(64);
+        rack.run_script(script).unwrap();
+        let (output, consumer) = Output::new("out".into(), 1);
+        rack.add_module(Arc::new(Mutex::new(output))).unwrap();
+        rack.run_script("connect m result out signal_in").unwrap();
+        (rack, consumer)
+    }
+
+    /// The samples of a number of blocks of a patch, processed with the given number of workers
+    fn render(script: &str, workers: usize, blocks: usize) -> Vec<SampleType> {
+        let (mut rack, consumer) = output_rack(script);
+        rack.set_worker_threads(workers);
+
+        let mut samples = Vec::new();
+        for _ in 0..blocks {
+            rack.process_module_chain();
+            while let Some(sample) = consumer.pop() {
+                samples.push(sample);
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn renders_the_same_samples_with_any_number_of_workers() {
+        let single = render(PARALLEL_PATCH, 0, 8);
+        assert_eq!(single.len(), 8 * 64);
+        assert!(single.iter().any(|sample| *sample != 0.0));
+
+        for workers in [2, 3, 8] {
+            let samples = render(PARALLEL_PATCH, workers, 8);
+            assert_eq!(samples, single, "{} workers", workers);
+        }
+    }
+
+    #[test]
+    fn renders_the_same_feedback_with_any_number_of_workers() {
+        // Feeding a and m back into the oscillators' amplitude makes the patch be processed a
+        // sample at a time, with the pool called for every frame
+        let loops = "connect a sum o4 amp\nconnect m result o2 amp";
+        let script = format!("{}\n{}", PARALLEL_PATCH, loops);
+        let (rack, _) = output_rack(&script);
+        assert!(rack.connections().any(|conn| conn.is_feedback()));
+
+        let single = render(&script, 0, 8);
+        assert_eq!(single.len(), 8 * 64);
+        assert!(single.iter().any(|sample| *sample != 0.0));
+
+        for workers in [2, 3, 8] {
+            assert_eq!(render(&script, workers, 8), single, "{} workers", workers);
+        }
+    }
+
+    #[test]
+    fn changes_the_number_of_workers_between_blocks() {
+        let (mut rack, consumer) = output_rack(PARALLEL_PATCH);
+        let single = render(PARALLEL_PATCH, 0, 6);
+
+        for workers in [4, 0, 2, 8, 1, 3] {
+            rack.set_worker_threads(workers);
+            let expected = if workers < 2 { 0 } else { workers };
+            assert_eq!(rack.get_worker_threads(), expected);
+            rack.process_module_chain();
+        }
+
+        let samples: Vec<SampleType> = std::iter::from_fn(|| consumer.pop()).collect();
+        assert_eq!(samples, single);
+    }
+
+    /// The value of a module's input port, for the first frame of the last block
+    fn in_value(rack: &Rack, module_id: &str, port_id: &str) -> SampleType {
+        let module = rack.modules[module_id]
+            .lock()
+            .expect("Mutex lock is poisoned");
+        let port = module.get_in_port_ref(port_id).unwrap();
+        port.seek(0);
+        port.get_value()
+    }
+
+    fn summed_rack() -> Rack {
+        rack_with(
+            "add control k1
+            add control k2
+            add adder a
+            set k1 value 1
+            set k2 value 2
+            summing a a on",
+        )
+    }
+
+    #[test]
+    fn sums_cables_with_their_gains() {
+        let mut rack = summed_rack();
+        rack.run_script("connect k1 value a a\nconnect k2 value a a gain=0.5")
+            .unwrap();
+
+        rack.process_module_chain();
+        assert_eq!(in_value(&rack, "a", "a"), 2.0);
+
+        let gains: Vec<SampleType> = rack.connections().map(|conn| conn.get_gain()).collect();
+        assert_eq!(gains, [1.0, 0.5]);
+    }
+
+    #[test]
+    fn keeps_the_latest_cable_when_summing_is_turned_off() {
+        let mut rack = summed_rack();
+        rack.run_script("connect k1 value a a\nconnect k2 value a a\nconnect k1 value a a")
+            .unwrap();
+        rack.run_script("summing a a off").unwrap();
+
+        // The reconnected cable is the latest, both in the port and in the Rack
+        rack.process_module_chain();
+        assert_eq!(in_value(&rack, "a", "a"), 1.0);
+
+        let sources: Vec<&str> = rack
+            .connections()
+            .map(|conn| conn.get_out_module_id())
+            .collect();
+        assert_eq!(sources, ["k1"]);
+    }
+
+    #[test]
+    fn replaces_the_cable_of_a_port_which_isnt_summing() {
+        let mut rack = rack_with(
+            "add control k1
+            add control k2
+            add adder a
+            set k1 value 1
+            set k2 value 2
+            connect k1 value a a
+            connect k2 value a a",
+        );
+
+        rack.process_module_chain();
+        assert_eq!(in_value(&rack, "a", "a"), 2.0);
+        assert_eq!(rack.connections().count(), 1);
+    }
+
+    #[test]
+    fn stops_sending_keys_to_a_removed_control() {
+        let mut rack = rack_with("add control k\nset k value 0\nfocus k");
+        let control = rack.controls["k"].clone();
+        let value = || control.lock().unwrap().get_value("value");
+
+        rack.send_control_key('k');
+        assert_eq!(value(), Some(100.0));
+
+        rack.run_script("remove k").unwrap();
+        rack.send_control_key('k');
+        // A knob doesn't take MIDI, and would panic if it was still sent any
+        rack.recv_midi(0, &[0x90, 60, 100]);
+        assert_eq!(value(), Some(100.0));
+
+        // Nor does a new control with the same ID get the focus
+        rack.run_script("add control k\nset k value 0").unwrap();
+        rack.send_control_key('k');
+        let control = rack.controls["k"].clone();
+        assert_eq!(control.lock().unwrap().get_value("value"), Some(0.0));
+    }
+
+    #[test]
+    fn reorders_when_disconnected() {
+        let mut rack = rack_with(
+            "add adder a
+            add adder b
+            connect a sum b a",
+        );
+        assert_eq!(chain(&rack), [["a"], ["b"]]);
+
+        rack.run_script("disconnect b a").unwrap();
+        assert_eq!(chain(&rack), [["a", "b"]]);
+
+        rack.run_script("connect b sum a a\nremove b").unwrap();
+        assert_eq!(chain(&rack), [["a"]]);
+    }
+
+    #[test]
+    fn loads_a_saved_patch_as_it_was() {
+        let rack = rack_with(
+            "add control k1
+            add adder a
+            add adder b
+            set k1 value 3
+            summing a a on
+            connect k1 value a a 0.5
+            connect a sum b b
+            connect k1 value b a",
+        );
+        let patch = rack.to_patch();
+
+        // The patch keeps its values through TOML
+        let text = toml::to_string_pretty(&patch).unwrap();
+        assert_eq!(toml::from_str::<Patch>(&text).unwrap(), patch);
+
+        let mut loaded = rack_with("add osc leftover");
+        loaded.apply_patch(&patch).unwrap();
+        assert_eq!(loaded.to_patch(), patch);
+        assert_eq!(chain(&loaded), chain(&rack));
+        assert!(!loaded.modules.contains_key("leftover"));
+
+        loaded.process_module_chain();
+        assert_eq!(in_value(&loaded, "a", "a"), 1.5);
+    }
+
+    #[test]
+    fn reports_every_problem_of_a_patch() {
+        let mut rack = rack_with("add adder a\nadd control k1");
+        let before = rack.to_patch();
+
+        let mut patch = before.clone();
+        patch.modules.push(ModuleEntry {
+            module_type: String::from("theremin"),
+            id: String::from("t"),
+        });
+        patch.modules.push(ModuleEntry {
+            module_type: String::from("control"),
+            id: String::from("c"),
+        });
+        patch.controls.push(ControlEntry {
+            control_type: String::from("button"),
+            id: String::from("b"),
+            values: [(String::from("gate"), 5.0), (String::from("pitch"), 1.0)].into(),
+        });
+        patch.summing.push(PortEntry {
+            module: String::from("k1"),
+            port: String::from("a"),
+        });
+        patch.connections.push(ConnectionEntry {
+            out_module: String::from("k1"),
+            out_port: String::from("value"),
+            in_module: String::from("a"),
+            in_port: String::from("c"),
+            gain: 1.0,
+        });
+        patch.connections.push(ConnectionEntry {
+            out_module: String::from("nothing"),
+            out_port: String::from("value"),
+            in_module: String::from("a"),
+            in_port: String::from("a"),
+            gain: 1.0,
+        });
+
+        let problems = rack.apply_patch(&patch).unwrap_err().problems;
+        assert_eq!(
+            problems,
+            [
+                "t: unknown type theremin",
+                "c: control is a control, not a module",
+                "b: 5 is out of range for gate, which takes 0 to 1",
+                "b: no output port pitch",
+                "summing: k1 is a control, which has no inputs",
+                "connection 1: a has no input port c",
+                "connection 2: there is no module or control nothing",
+            ]
+        );
+
+        // Nothing was changed
+        assert_eq!(rack.to_patch(), before);
+
+        patch.version = PATCH_VERSION + 1;
+        assert!(rack.apply_patch(&patch).is_err());
+    }
+
+    #[test]
+    fn names_an_unknown_type() {
+        let mut rack = Rack::new();
+        let add = "add theremin t".parse().unwrap();
+
+        assert_eq!(
+            rack.handle_command(&add),
+            Err(CommandError::Failed(String::from(
+                "Unknown type theremin, see \"info types\""
+            )))
+        );
+    }
+
+    #[test]
+    fn answers_with_data_which_is_only_formatted_when_shown() {
+        let mut rack = rack_with("add adder a\nadd control k");
+
+        let set = rack.handle_command(&"set k value 0.5".parse().unwrap());
+        assert_eq!(
+            set,
+            Ok(CommandOutput::Value {
+                ctrl: "k".into(),
+                port: "value".into(),
+                value: 0.5,
+            })
+        );
+
+        let info = rack
+            .handle_command(&"info modules".parse().unwrap())
+            .unwrap();
+        assert_eq!(
+            info,
+            CommandOutput::Info(Info::Modules {
+                modules: vec!["a".into()],
+                controls: vec!["k".into()],
+            })
+        );
+        assert_eq!(info.to_string(), "Modules:\n    a\n\nControls:\n    k\n\n");
+    }
+}
@@ -0,0 +1,244 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use arc_swap::ArcSwapOption;
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::ring_buffer::{self, Producer};
+use crate::types::{SampleType, AUDIO_BUF_SIZE};
+
+/// How often the writer thread moves recorded frames to the file
+const WRITE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A point where a module's frames can be listened to, e.g. the frames sent by an `Output`.
+///
+/// Writing to a tap never blocks, nor takes a lock, so it is safe from an audio thread. If
+/// nothing is listening, or the listener has fallen behind, the frames are dropped.
+#[derive(Clone, Default)]
+pub struct Tap {
+    /// Receives a copy of the frames while a recording is running. It is swapped atomically,
+    /// so the writer never waits for it to be changed
+    listener: Arc<ArcSwapOption<Producer>>,
+
+    /// The number of frames dropped while a listener was set
+    dropped: Arc<AtomicU64>,
+}
+
+impl Tap {
+    /// Pass interleaved frames of the given number of channels on to the listener, if any
+    pub fn write(&self, frames: &[SampleType], channels: usize) {
+        if let Some(listener) = self.listener.load().as_ref() {
+            if listener.space() >= frames.len() {
+                listener.push_slice(frames);
+            } else {
+                self.dropped
+                    .fetch_add((frames.len() / channels.max(1)) as u64, Relaxed);
+            }
+        }
+    }
+
+    fn set_listener(&self, listener: Option<Producer>) {
+        self.listener.store(listener.map(Arc::new));
+        self.dropped.store(0, Relaxed);
+    }
+}
+
+/// Records the frames passing a tap to a WAV file, in real time.
+///
+/// The frames are moved from the tap to the file by a background thread, so the module being
+/// recorded never waits for the disk. Samples are written as 32-bit floats, so that loud
+/// signals aren't clipped.
+pub struct Recorder {
+    tap: Tap,
+
+    path: PathBuf,
+
+    /// Tells the writer thread to write the remaining frames and finish the file
+    stop: Arc<AtomicBool>,
+
+    /// The writer thread, which returns the number of frames written
+    writer: Option<JoinHandle<Result<u64, String>>>,
+}
+
+impl Recorder {
+    /// Start recording the frames of the given number of channels which pass the tap. Only
+    /// one recording of a tap can run at a time
+    pub fn start<P: AsRef<Path>>(
+        tap: &Tap,
+        channels: usize,
+        sample_rate: SampleType,
+        path: P,
+    ) -> Result<Self, Box<dyn Error>> {
+        let channels = channels.max(1);
+        let spec = WavSpec {
+            channels: u16::try_from(channels)?,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut file = WavWriter::create(path.as_ref(), spec)?;
+
+        // Leaves plenty of time for the writer thread to catch up, e.g. on a slow disk
+        let (listener, frames_rx) = ring_buffer::ring_buffer(16 * AUDIO_BUF_SIZE * channels);
+        let stop = Arc::new(AtomicBool::new(false));
+        let w_stop = stop.clone();
+
+        let writer = thread::spawn(move || {
+            let mut frames = 0;
+            loop {
+                // Read the stop flag first, so that no frames are left behind after stopping
+                let stopping = w_stop.load(Acquire);
+
+                while frames_rx.len() >= channels {
+                    for _ in 0..channels {
+                        let sample = frames_rx.pop().unwrap_or(0.0);
+                        file.write_sample(sample as f32)
+                            .map_err(|e| e.to_string())?;
+                    }
+                    frames += 1;
+                }
+
+                if stopping {
+                    break;
+                }
+                thread::sleep(WRITE_INTERVAL);
+            }
+
+            file.finalize().map_err(|e| e.to_string())?;
+            Ok(frames)
+        });
+
+        tap.set_listener(Some(listener));
+
+        Ok(Self {
+            tap: tap.clone(),
+            path: path.as_ref().to_path_buf(),
+            stop,
+            writer: Some(writer),
+        })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop recording and finish the file
+    pub fn stop(mut self) -> Result<String, Box<dyn Error>> {
+        let dropped = self.tap.dropped.load(Relaxed);
+        let frames = self.finish()?;
+
+        let mut msg = format!("Recorded {} frames to {}", frames, self.path.display());
+        if dropped > 0 {
+            msg.push_str(&format!(" ({} frames dropped)", dropped));
+        }
+
+        Ok(msg)
+    }
+
+    /// Detach from the tap and wait for the writer thread to finish the file
+    fn finish(&mut self) -> Result<u64, Box<dyn Error>> {
+        self.tap.set_listener(None);
+        self.stop.store(true, Release);
+
+        match self.writer.take() {
+            Some(writer) => match writer.join() {
+                Ok(result) => Ok(result?),
+                Err(_) => Err("The recording's writer thread panicked".into()),
+            },
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Make sure the file is finished, even if the recording wasn't stopped
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::WavReader;
+
+    fn wav_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("yat-test-{}-{}.wav", std::process::id(), name))
+    }
+
+    fn read_samples(path: &Path) -> Vec<f32> {
+        let mut reader = WavReader::open(path).unwrap();
+        reader.samples::<f32>().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn records_the_frames_passing_the_tap() {
+        let path = wav_path("record");
+        let tap = Tap::default();
+        // Nothing is listening yet
+        tap.write(&[1.0, 1.0], 2);
+
+        let recorder = Recorder::start(&tap, 2, 48000.0, &path).unwrap();
+        let frames: Vec<SampleType> = (0..200).map(|i| i as SampleType / 256.0).collect();
+        for block in frames.chunks(20) {
+            tap.write(block, 2);
+        }
+        let msg = recorder.stop().unwrap();
+        // Nothing is listening anymore
+        tap.write(&[1.0, 1.0], 2);
+
+        assert_eq!(msg, format!("Recorded 100 frames to {}", path.display()));
+        let spec = WavReader::open(&path).unwrap().spec();
+        assert_eq!((spec.channels, spec.sample_rate), (2, 48000));
+        assert_eq!(spec.bits_per_sample, 32);
+        assert_eq!(spec.sample_format, SampleFormat::Float);
+        let expected: Vec<f32> = frames.iter().map(|sample| *sample as f32).collect();
+        assert_eq!(read_samples(&path), expected);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn counts_the_frames_dropped_while_the_writer_is_behind() {
+        let path = wav_path("dropped");
+        let tap = Tap::default();
+        let recorder = Recorder::start(&tap, 1, 48000.0, &path).unwrap();
+
+        // More than the writer thread can take in at once
+        tap.write(&vec![0.5; 17 * AUDIO_BUF_SIZE], 1);
+        tap.write(&[0.25; 10], 1);
+        let msg = recorder.stop().unwrap();
+
+        assert_eq!(
+            msg,
+            format!(
+                "Recorded 10 frames to {} ({} frames dropped)",
+                path.display(),
+                17 * AUDIO_BUF_SIZE
+            )
+        );
+        assert_eq!(read_samples(&path), [0.25; 10]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn finishes_the_file_when_dropped() {
+        let path = wav_path("drop");
+        let tap = Tap::default();
+        let recorder = Recorder::start(&tap, 1, 48000.0, &path).unwrap();
+        tap.write(&[0.5; 64], 1);
+        drop(recorder);
+
+        assert_eq!(read_samples(&path), [0.5; 64]);
+        tap.write(&[0.5; 64], 1);
+        assert_eq!(read_samples(&path).len(), 64);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::clock::Clock;
+use crate::controls::basic_keyboard::BasicKeyboard;
+use crate::controls::button::Button;
+use crate::controls::control::Control;
+use crate::controls::control_knob::ControlKnob;
+use crate::info::Info;
+use crate::modules::adder::Adder;
+use crate::modules::adsr::Adsr;
+use crate::modules::bitwise_and::BitwiseAnd;
+use crate::modules::bitwise_or::BitwiseOr;
+use crate::modules::delay::Delay;
+use crate::modules::divider::Divider;
+use crate::modules::io_module::IoModule;
+use crate::modules::modulo::Modulo;
+use crate::modules::multiplier::Multiplier;
+use crate::modules::oscillator::Oscillator;
+use crate::out_port::PortRef;
+use crate::port_descriptor::PortDescriptor;
+use crate::types::{SampleType, SAMPLE_RATE};
+
+/// What a module's constructor gets to know about the Rack it is created for
+pub struct ModuleContext {
+    pub sample_rate: SampleType,
+    pub clock: Arc<RwLock<Clock>>,
+}
+
+impl Default for ModuleContext {
+    fn default() -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            clock: Arc::new(RwLock::new(Clock::new(SAMPLE_RATE))),
+        }
+    }
+}
+
+pub type ModuleConstructor =
+    Box<dyn Fn(String, &ModuleContext) -> Arc<Mutex<dyn IoModule + Send + Sync>> + Send + Sync>;
+
+pub type ControlConstructor =
+    Box<dyn Fn(String) -> Arc<Mutex<dyn Control + Send + Sync>> + Send + Sync>;
+
+/// A module or control, e.g. one taken out of the Rack, or about to be put into it
+pub(crate) enum RackItem {
+    Module(Arc<Mutex<dyn IoModule + Send + Sync>>),
+    Control(Arc<Mutex<dyn Control + Send + Sync>>),
+}
+
+impl RackItem {
+    /// The IDs of the item's input ports. Controls have none
+    pub(crate) fn get_in_ports(&self) -> Vec<String> {
+        match self {
+            RackItem::Module(module) => module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_in_ports()
+                .clone(),
+            RackItem::Control(_) => Vec::new(),
+        }
+    }
+
+    /// The IDs of the item's output ports
+    pub(crate) fn get_out_ports(&self) -> Vec<String> {
+        match self {
+            RackItem::Module(module) => module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_out_ports()
+                .clone(),
+            RackItem::Control(control) => control
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_out_ports()
+                .iter()
+                .map(|port_id| port_id.to_string())
+                .collect(),
+        }
+    }
+
+    /// Describe the item's ports, inputs first
+    pub(crate) fn get_port_descriptors(&self) -> Vec<PortDescriptor> {
+        match self {
+            RackItem::Module(module) => module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_port_descriptors(),
+            RackItem::Control(control) => control
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_port_descriptors(),
+        }
+    }
+
+    /// Get a reference to one of the item's output ports
+    pub(crate) fn get_port_reference(&self, port_id: &str) -> Option<PortRef> {
+        match self {
+            RackItem::Module(module) => module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_out_port_ref(port_id)
+                .map(|port| port.get_ref()),
+            RackItem::Control(control) => control
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .get_port_reference(port_id),
+        }
+    }
+}
+
+/// Describes a registered module or control type
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeInfo {
+    /// The name which the type is added by, e.g. "osc"
+    pub name: String,
+
+    pub description: String,
+
+    pub is_control: bool,
+
+    pub in_ports: Vec<String>,
+
+    pub out_ports: Vec<String>,
+
+    /// The ranges, defaults and units of the input and output ports, inputs first
+    pub ports: Vec<PortDescriptor>,
+}
+
+enum Constructor {
+    Module(ModuleConstructor),
+    Control(ControlConstructor),
+}
+
+struct RegisteredType {
+    info: TypeInfo,
+    constructor: Constructor,
+}
+
+/// Maps type names to the constructors of modules and controls, so that they can be added to
+/// a Rack by name, e.g. with the `add` command.
+///
+/// The registry of a new Rack holds the built-in types. Other crates can register their own
+/// with `Rack::get_registry_mut`. A type's ports and their descriptors are read from an
+/// instance which is created when it is registered.
+pub struct ModuleRegistry {
+    types: BTreeMap<String, RegisteredType>,
+}
+
+impl ModuleRegistry {
+    /// Create a registry holding the built-in modules and controls
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+
+        let modules: Vec<(&str, &str, ModuleConstructor)> = vec![
+            (
+                "osc",
+                "Sine oscillator",
+                Box::new(|id, ctx| Arc::new(Mutex::new(Oscillator::new(id, ctx.sample_rate)))),
+            ),
+            (
+                "adsr",
+                "ADSR envelope, triggered by a gate",
+                Box::new(|id, ctx| Arc::new(Mutex::new(Adsr::new(id, ctx.clock.clone())))),
+            ),
+            (
+                "delay",
+                "Outputs its input up to two seconds later",
+                Box::new(|id, ctx| Arc::new(Mutex::new(Delay::new(id, ctx.sample_rate)))),
+            ),
+            (
+                "adder",
+                "Outputs the sum of a and b",
+                Box::new(|id, _| Arc::new(Mutex::new(Adder::new(id)))),
+            ),
+            (
+                "multiplier",
+                "Outputs the product of a and b",
+                Box::new(|id, _| Arc::new(Mutex::new(Multiplier::new(id)))),
+            ),
+            (
+                "divider",
+                "Outputs a divided by b",
+                Box::new(|id, _| Arc::new(Mutex::new(Divider::new(id)))),
+            ),
+            (
+                "modulo",
+                "Outputs the remainder of a divided by b",
+                Box::new(|id, _| Arc::new(Mutex::new(Modulo::new(id)))),
+            ),
+            (
+                "bitwise_and",
+                "Outputs the bitwise AND of a and b, as integers",
+                Box::new(|id, _| Arc::new(Mutex::new(BitwiseAnd::new(id)))),
+            ),
+            (
+                "bitwise_or",
+                "Outputs the bitwise OR of a and b, as integers",
+                Box::new(|id, _| Arc::new(Mutex::new(BitwiseOr::new(id)))),
+            ),
+        ];
+        for (name, description, constructor) in modules {
+            registry.insert_module(name, description, constructor);
+        }
+
+        let controls: Vec<(&str, &str, ControlConstructor)> = vec![
+            (
+                "control",
+                "Knob, with a value output",
+                Box::new(|id| Arc::new(Mutex::new(ControlKnob::new(id)))),
+            ),
+            (
+                "button",
+                "Button, with a gate output",
+                Box::new(|id| Arc::new(Mutex::new(Button::new(id)))),
+            ),
+            (
+                "keyboard",
+                "Keyboard, with gate, pitch and velocity outputs",
+                Box::new(|id| Arc::new(Mutex::new(BasicKeyboard::new(id)))),
+            ),
+        ];
+        for (name, description, constructor) in controls {
+            registry.insert_control(name, description, constructor);
+        }
+
+        registry
+    }
+
+    /// Create a registry without any types
+    pub fn empty() -> Self {
+        Self {
+            types: BTreeMap::new(),
+        }
+    }
+
+    /// Register a module type. Fails if the name is already taken
+    pub fn register_module<F>(
+        &mut self,
+        name: &str,
+        description: &str,
+        constructor: F,
+    ) -> Result<String, Box<dyn Error>>
+    where
+        F: Fn(String, &ModuleContext) -> Arc<Mutex<dyn IoModule + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        if self.types.contains_key(name) {
+            return Err(format!("Type {} is already registered", name).into());
+        }
+        self.insert_module(name, description, Box::new(constructor));
+
+        Ok(format!("Registered module type {}", name))
+    }
+
+    /// Register a control type. Fails if the name is already taken
+    pub fn register_control<F>(
+        &mut self,
+        name: &str,
+        description: &str,
+        constructor: F,
+    ) -> Result<String, Box<dyn Error>>
+    where
+        F: Fn(String) -> Arc<Mutex<dyn Control + Send + Sync>> + Send + Sync + 'static,
+    {
+        if self.types.contains_key(name) {
+            return Err(format!("Type {} is already registered", name).into());
+        }
+        self.insert_control(name, description, Box::new(constructor));
+
+        Ok(format!("Registered control type {}", name))
+    }
+
+    /// Remove a type, e.g. one which belongs to a plugin being unloaded
+    pub fn unregister(&mut self, name: &str) -> Option<TypeInfo> {
+        self.types.remove(name).map(|registered| registered.info)
+    }
+
+    pub fn get_info(&self, name: &str) -> Option<&TypeInfo> {
+        self.types.get(name).map(|registered| &registered.info)
+    }
+
+    /// The registered types, ordered by name
+    pub fn types(&self) -> impl Iterator<Item = &TypeInfo> {
+        self.types.values().map(|registered| &registered.info)
+    }
+
+    /// Create a module or control of the given type, if the type is registered
+    pub(crate) fn create(&self, name: &str, id: &str, context: &ModuleContext) -> Option<RackItem> {
+        match &self.types.get(name)?.constructor {
+            Constructor::Module(constructor) => {
+                Some(RackItem::Module(constructor(id.into(), context)))
+            }
+            Constructor::Control(constructor) => Some(RackItem::Control(constructor(id.into()))),
+        }
+    }
+
+    pub fn print_types(&self) -> String {
+        self.get_types_info().to_string()
+    }
+
+    /// Describe the registered types, ordered by name
+    pub fn get_types_info(&self) -> Info {
+        Info::Types(self.types().cloned().collect())
+    }
+
+    fn insert_module(&mut self, name: &str, description: &str, constructor: ModuleConstructor) {
+        let module = constructor(name.into(), &ModuleContext::default());
+        let module = module.lock().expect("Mutex lock is poisoned");
+
+        let info = TypeInfo {
+            name: name.into(),
+            description: description.into(),
+            is_control: false,
+            in_ports: module.get_in_ports().clone(),
+            out_ports: module.get_out_ports().clone(),
+            ports: module.get_port_descriptors(),
+        };
+        drop(module);
+
+        self.types.insert(
+            name.into(),
+            RegisteredType {
+                info,
+                constructor: Constructor::Module(constructor),
+            },
+        );
+    }
+
+    fn insert_control(&mut self, name: &str, description: &str, constructor: ControlConstructor) {
+        let control = RackItem::Control(constructor(name.into()));
+
+        let info = TypeInfo {
+            name: name.into(),
+            description: description.into(),
+            is_control: true,
+            in_ports: Vec::new(),
+            out_ports: control.get_out_ports(),
+            ports: control.get_port_descriptors(),
+        };
+
+        self.types.insert(
+            name.into(),
+            RegisteredType {
+                info,
+                constructor: Constructor::Control(constructor),
+            },
+        );
+    }
+}
+
+impl Default for ModuleRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::InfoData;
+    use crate::rack::Rack;
+
+    /// The IDs of the Rack's modules and controls
+    fn items(rack: &Rack) -> (Vec<String>, Vec<String>) {
+        match rack.get_info(&InfoData::Modules) {
+            Info::Modules { modules, controls } => (modules, controls),
+            info => panic!("Unexpected info: {:?}", info),
+        }
+    }
+
+    #[test]
+    fn adds_every_registered_type() {
+        let mut rack = Rack::new();
+        let types: Vec<TypeInfo> = rack.get_registry().types().cloned().collect();
+        assert!(types.len() >= 12);
+
+        for info in &types {
+            let id = format!("{}_1", info.name);
+            rack.add_module_type(&info.name, &id).unwrap();
+
+            let (modules, controls) = items(&rack);
+            let added = if info.is_control { controls } else { modules };
+            assert!(added.contains(&id), "{} wasn't added", info.name);
+        }
+    }
+
+    #[test]
+    fn adds_a_type_registered_by_another_crate() {
+        let mut rack = Rack::new();
+        let registry = rack.get_registry_mut();
+        registry
+            .register_module("sum", "Adds a and b", |id, _| {
+                Arc::new(Mutex::new(Adder::new(id)))
+            })
+            .unwrap();
+        registry
+            .register_control("fader", "A knob by another name", |id| {
+                Arc::new(Mutex::new(ControlKnob::new(id)))
+            })
+            .unwrap();
+        // Built-in names, and those already registered, are taken
+        assert!(registry
+            .register_module("osc", "", |id, _| Arc::new(Mutex::new(Adder::new(id))))
+            .is_err());
+        assert!(registry
+            .register_control("fader", "", |id| Arc::new(Mutex::new(Button::new(id))))
+            .is_err());
+
+        let info = rack.get_registry().get_info("sum").unwrap();
+        assert_eq!(info.in_ports, ["a", "b"]);
+        assert_eq!(info.out_ports, ["sum"]);
+
+        rack.run_script("add sum s\nadd fader f\nconnect f value s a")
+            .unwrap();
+        assert_eq!(items(&rack), (vec!["s".into()], vec!["f".into()]));
+    }
+
+    #[test]
+    fn refuses_to_add_an_unknown_type() {
+        let mut rack = Rack::new();
+        rack.get_registry_mut().unregister("adder").unwrap();
+
+        for name in ["adder", "theremin"] {
+            let e = rack.add_module_type(name, "x").unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!("Unknown type {}, see \"info types\"", name)
+            );
+        }
+        assert_eq!(items(&rack), (vec![], vec![]));
+    }
+}
@@ -0,0 +1,299 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::rack::Rack;
+use crate::ring_buffer::Consumer;
+use crate::types::SampleType;
+
+/// Settings for rendering a Rack to a WAV file
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    /// The length of the rendered audio, in seconds
+    pub seconds: f64,
+
+    /// The sample rate which the Rack is run at, and which is written to the file
+    pub sample_rate: u32,
+
+    /// The bit depth of the file. Integer samples can have 8, 16, 24 or 32 bits, float
+    /// samples have 32 bits
+    pub bits_per_sample: u16,
+
+    /// Whether to write float, rather than integer, samples
+    pub float: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            seconds: 10.0,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            float: false,
+        }
+    }
+}
+
+/// Render a Rack to a WAV file, without an audio device.
+///
+/// The Rack's chain is processed as fast as possible, and the frames which an `Output` module
+/// writes to its ring buffer are read from `audio_rx` and written to the file. The last block
+/// is processed with a smaller block size, so that the file has the exact duration. The Rack
+/// is switched to the settings' sample rate first, which resets its clock and modules. Returns
+/// an error, and removes the file, if a block doesn't produce exactly its frames, e.g. if the
+/// Rack has no `Output`.
+pub fn render_to_wav<P: AsRef<Path>>(
+    rack: &mut Rack,
+    audio_rx: &Consumer,
+    channels: usize,
+    path: P,
+    settings: &RenderSettings,
+) -> Result<String, Box<dyn Error>> {
+    let channels = channels.max(1);
+    let sample_format = match (settings.float, settings.bits_per_sample) {
+        (true, 32) => SampleFormat::Float,
+        (false, 8 | 16 | 24 | 32) => SampleFormat::Int,
+        (true, bits) => return Err(format!("Float samples can't have {} bits", bits).into()),
+        (false, bits) => return Err(format!("Integer samples can't have {} bits", bits).into()),
+    };
+    if !(settings.seconds.is_finite() && settings.seconds >= 0.0) {
+        return Err(format!("Invalid duration: {} seconds", settings.seconds).into());
+    }
+    if !audio_rx.is_empty() {
+        return Err("The output holds frames from before rendering".into());
+    }
+
+    let spec = WavSpec {
+        channels: u16::try_from(channels)?,
+        sample_rate: settings.sample_rate,
+        bits_per_sample: settings.bits_per_sample,
+        sample_format,
+    };
+    let mut writer = WavWriter::create(path.as_ref(), spec)?;
+
+    let block_size = rack.get_block_size();
+    let result = write_frames(rack, audio_rx, &mut writer, settings, spec);
+    rack.set_block_size(block_size);
+
+    match result {
+        Ok(frames) => {
+            writer.finalize()?;
+            Ok(format!(
+                "Rendered {} frames to {}",
+                frames,
+                path.as_ref().display()
+            ))
+        }
+        Err(e) => {
+            // Don't leave a broken file behind
+            drop(writer);
+            let _ = std::fs::remove_file(path.as_ref());
+            Err(e)
+        }
+    }
+}
+
+/// Process the Rack for the settings' duration, writing every frame of the output to the
+/// file. Returns the number of frames written
+fn write_frames(
+    rack: &mut Rack,
+    audio_rx: &Consumer,
+    writer: &mut WavWriter<BufWriter<File>>,
+    settings: &RenderSettings,
+    spec: WavSpec,
+) -> Result<u64, Box<dyn Error>> {
+    rack.set_sample_rate(settings.sample_rate as SampleType)?;
+
+    let channels = spec.channels as usize;
+    let block_size = rack.get_block_size();
+    let total_frames = (settings.seconds * settings.sample_rate as f64).round() as u64;
+    let mut frames = 0;
+    while frames < total_frames {
+        let block = block_size.min((total_frames - frames) as usize);
+        rack.set_block_size(block);
+        rack.process_module_chain();
+
+        // Without an `Output` writing to `audio_rx`, rendering would never finish
+        let samples = audio_rx.len();
+        if samples == 0 {
+            return Err("Processing the Rack produced no audio, is there an output module?".into());
+        }
+        if samples != block * channels {
+            return Err(format!(
+                "Processing {} frames of {} channels produced {} samples, rather than {}",
+                block,
+                channels,
+                samples,
+                block * channels
+            )
+            .into());
+        }
+
+        for _ in 0..samples {
+            let sample = audio_rx.pop().unwrap_or(0.0);
+            write_sample(writer, sample, spec.sample_format, spec.bits_per_sample)?;
+        }
+        frames += block as u64;
+    }
+
+    Ok(frames)
+}
+
+/// Write a sample, clipping it to the range of the file's sample format
+fn write_sample(
+    writer: &mut WavWriter<BufWriter<File>>,
+    sample: SampleType,
+    sample_format: SampleFormat,
+    bits_per_sample: u16,
+) -> Result<(), hound::Error> {
+    let sample = sample.clamp(-1.0, 1.0);
+
+    match sample_format {
+        SampleFormat::Float => writer.write_sample(sample as f32),
+        SampleFormat::Int => {
+            let max = ((1i64 << (bits_per_sample - 1)) - 1) as SampleType;
+            let value = (sample * max).round() as i32;
+
+            match bits_per_sample {
+                8 => writer.write_sample(value as i8),
+                16 => writer.write_sample(value as i16),
+                _ => writer.write_sample(value),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::output::Output;
+    use crate::ring_buffer::ring_buffer;
+    use hound::WavReader;
+    use std::sync::{Arc, Mutex};
+
+    /// A Rack playing an oscillator on every channel of its output
+    fn oscillator_rack(channels: usize) -> (Rack, Consumer) {
+        let mut rack = Rack::new();
+        let (output, audio_rx) = Output::new("out".into(), channels);
+        rack.add_module(Arc::new(Mutex::new(output))).unwrap();
+        rack.run_script("add osc o\nconnect o audio_out out signal_in")
+            .unwrap();
+        (rack, audio_rx)
+    }
+
+    /// Render an oscillator in the given format, returning the file's spec and samples, scaled
+    /// to -1.0..1.0
+    fn render_oscillator(name: &str, bits: u16, float: bool) -> (WavSpec, Vec<SampleType>) {
+        let (mut rack, audio_rx) = oscillator_rack(2);
+        let path = std::env::temp_dir().join(format!("yat-render-{}.wav", name));
+        let settings = RenderSettings {
+            seconds: 0.05,
+            sample_rate: 48_000,
+            bits_per_sample: bits,
+            float,
+        };
+
+        let msg = render_to_wav(&mut rack, &audio_rx, 2, &path, &settings).unwrap();
+        assert_eq!(msg, format!("Rendered 2400 frames to {}", path.display()));
+        assert!(audio_rx.is_empty());
+
+        let mut reader = WavReader::open(&path).unwrap();
+        let spec = reader.spec();
+        let samples = if float {
+            reader
+                .samples::<f32>()
+                .map(|sample| sample.unwrap() as SampleType)
+                .collect()
+        } else {
+            let max = ((1i64 << (bits - 1)) - 1) as SampleType;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.unwrap() as SampleType / max)
+                .collect()
+        };
+        std::fs::remove_file(&path).unwrap();
+
+        (spec, samples)
+    }
+
+    #[test]
+    fn renders_the_exact_duration() {
+        let (spec, samples) = render_oscillator("duration", 16, false);
+
+        assert_eq!((spec.channels, spec.sample_rate), (2, 48_000));
+        assert_eq!(spec.bits_per_sample, 16);
+        assert_eq!(spec.sample_format, SampleFormat::Int);
+        // 0.05 seconds don't fit in a whole number of blocks
+        assert_eq!(samples.len(), 2 * 2400);
+        assert!(samples.iter().any(|sample| sample.abs() > 0.1));
+        for frame in samples.chunks(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+
+    #[test]
+    fn writes_every_sample_format() {
+        let (spec, reference) = render_oscillator("float", 32, true);
+        assert_eq!(spec.bits_per_sample, 32);
+        assert_eq!(spec.sample_format, SampleFormat::Float);
+        assert!(reference.iter().any(|sample| sample.abs() > 0.1));
+
+        for bits in [8, 16, 24, 32] {
+            let (spec, samples) = render_oscillator(&format!("int{}", bits), bits, false);
+            assert_eq!(spec.bits_per_sample, bits);
+            assert_eq!(spec.sample_format, SampleFormat::Int);
+            assert_eq!(samples.len(), reference.len());
+
+            // Off by no more than a step of the format
+            let step = 1.0 / ((1i64 << (bits - 1)) - 1) as SampleType;
+            for (sample, expected) in samples.iter().zip(&reference) {
+                assert!(
+                    (sample - expected).abs() <= step + 1e-6,
+                    "{} bits: {} rather than {}",
+                    bits,
+                    sample,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_the_racks_block_size() {
+        let (mut rack, audio_rx) = oscillator_rack(1);
+        rack.set_block_size(100);
+        let path = std::env::temp_dir().join("yat-render-block-size.wav");
+        let settings = RenderSettings {
+            seconds: 0.001,
+            ..RenderSettings::default()
+        };
+
+        render_to_wav(&mut rack, &audio_rx, 1, &path, &settings).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rack.get_block_size(), 100);
+    }
+
+    #[test]
+    fn fails_without_an_output() {
+        let mut rack = Rack::new();
+        let (_audio_tx, audio_rx) = ring_buffer(16);
+        let path = std::env::temp_dir().join("yat-render-without-output.wav");
+
+        let result = render_to_wav(&mut rack, &audio_rx, 1, &path, &RenderSettings::default());
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn fails_when_the_output_has_other_channels() {
+        let (mut rack, audio_rx) = oscillator_rack(2);
+        let path = std::env::temp_dir().join("yat-render-other-channels.wav");
+
+        let result = render_to_wav(&mut rack, &audio_rx, 1, &path, &RenderSettings::default());
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::Arc;
+
+use crate::types::SampleType;
+
+/// A lock-free queue of samples, with a single producer and a single consumer.
+///
+/// Samples are stored as the bits of each value in atomics, like a port's buffer, so neither
+/// side ever takes a lock. This allows the consumer to be an audio callback, which must not
+/// block on the thread processing the Rack.
+struct RingBuffer {
+    samples: Box<[AtomicU64]>,
+
+    /// The number of samples written so far. Only the producer changes this
+    write_pos: AtomicUsize,
+
+    /// The number of samples read so far. Only the consumer changes this
+    read_pos: AtomicUsize,
+
+    /// Set once either side has been dropped
+    closed: AtomicBool,
+}
+
+impl RingBuffer {
+    fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+/// The writing side of a ring buffer
+pub struct Producer {
+    ring: Arc<RingBuffer>,
+}
+
+/// The reading side of a ring buffer
+pub struct Consumer {
+    ring: Arc<RingBuffer>,
+}
+
+/// A view of how full a ring buffer is, e.g. for waiting on the consumer without holding the
+/// producer
+#[derive(Clone)]
+pub struct Watcher {
+    ring: Arc<RingBuffer>,
+}
+
+/// Lets an engine wait for an output's ring buffer to have space for the next block, see
+/// `Output::get_pacer`
+pub struct Pacer {
+    watcher: Watcher,
+
+    /// The number of samples per frame
+    channels: usize,
+}
+
+/// Create a ring buffer, which holds up to the given number of samples
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let ring = Arc::new(RingBuffer {
+        samples: (0..capacity.max(1)).map(|_| AtomicU64::new(0)).collect(),
+        write_pos: AtomicUsize::new(0),
+        read_pos: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+    });
+
+    let producer = Producer { ring: ring.clone() };
+    let consumer = Consumer { ring };
+
+    (producer, consumer)
+}
+
+impl Producer {
+    /// Write a single sample. Returns false, dropping the sample, if the buffer is full
+    pub fn push(&self, value: SampleType) -> bool {
+        self.push_slice(&[value]) == 1
+    }
+
+    /// Write as many of the samples as there is space for. Returns the number written
+    pub fn push_slice(&self, block: &[SampleType]) -> usize {
+        let ring = &self.ring;
+        let write_pos = ring.write_pos.load(Relaxed);
+        let read_pos = ring.read_pos.load(Acquire);
+        let free = ring.capacity() - write_pos.wrapping_sub(read_pos);
+
+        let count = block.len().min(free);
+        for (i, value) in block[..count].iter().enumerate() {
+            let index = write_pos.wrapping_add(i) % ring.capacity();
+            ring.samples[index].store(value.to_bits(), Relaxed);
+        }
+        ring.write_pos.store(write_pos.wrapping_add(count), Release);
+
+        count
+    }
+
+    /// Returns the number of samples which can be written without waiting
+    pub fn space(&self) -> usize {
+        let ring = &self.ring;
+        ring.capacity()
+            - ring
+                .write_pos
+                .load(Relaxed)
+                .wrapping_sub(ring.read_pos.load(Acquire))
+    }
+
+    /// Whether the consumer has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Relaxed)
+    }
+
+    /// Get a view of the buffer's fill level, which can be used from another thread
+    pub fn watch(&self) -> Watcher {
+        Watcher {
+            ring: self.ring.clone(),
+        }
+    }
+}
+
+impl Watcher {
+    /// Returns the number of samples which can be written without waiting
+    pub fn space(&self) -> usize {
+        let ring = &self.ring;
+        ring.capacity()
+            - ring
+                .write_pos
+                .load(Acquire)
+                .wrapping_sub(ring.read_pos.load(Acquire))
+    }
+
+    /// Returns the number of samples read so far, e.g. for telling whether the consumer is
+    /// still reading. This wraps around on overflow
+    pub fn read_count(&self) -> usize {
+        self.ring.read_pos.load(Acquire)
+    }
+
+    /// Whether either side has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Relaxed)
+    }
+}
+
+impl Pacer {
+    pub fn new(watcher: Watcher, channels: usize) -> Self {
+        Self { watcher, channels }
+    }
+
+    pub fn get_watcher(&self) -> &Watcher {
+        &self.watcher
+    }
+
+    /// Whether a block of the given number of frames fits into the buffer
+    pub fn has_space(&self, frames: usize) -> bool {
+        self.watcher.space() >= frames * self.channels
+    }
+}
+
+impl Consumer {
+    /// Read the next sample, or None if the buffer is empty
+    pub fn pop(&self) -> Option<SampleType> {
+        let ring = &self.ring;
+        let read_pos = ring.read_pos.load(Relaxed);
+        let write_pos = ring.write_pos.load(Acquire);
+
+        if read_pos == write_pos {
+            return None;
+        }
+
+        let index = read_pos % ring.capacity();
+        let value = SampleType::from_bits(ring.samples[index].load(Relaxed));
+        ring.read_pos.store(read_pos.wrapping_add(1), Release);
+
+        Some(value)
+    }
+
+    /// Discard up to the given number of samples, oldest first. Returns the number discarded
+    pub fn skip(&self, count: usize) -> usize {
+        let ring = &self.ring;
+        let count = count.min(self.len());
+        ring.read_pos
+            .store(ring.read_pos.load(Relaxed).wrapping_add(count), Release);
+
+        count
+    }
+
+    /// Returns the number of samples waiting to be read
+    pub fn len(&self) -> usize {
+        let ring = &self.ring;
+        ring.write_pos
+            .load(Acquire)
+            .wrapping_sub(ring.read_pos.load(Relaxed))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the producer has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Relaxed)
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Relaxed);
+    }
+}
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_nothing_when_empty() {
+        let (producer, consumer) = ring_buffer(4);
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(consumer.skip(2), 0);
+        assert_eq!(producer.space(), 4);
+    }
+
+    #[test]
+    fn drops_samples_when_full() {
+        let (producer, consumer) = ring_buffer(4);
+        assert_eq!(producer.push_slice(&[1.0, 2.0, 3.0, 4.0, 5.0]), 4);
+        assert_eq!(producer.space(), 0);
+        assert!(!producer.push(6.0));
+        assert_eq!(consumer.len(), 4);
+
+        let samples: Vec<_> = std::iter::from_fn(|| consumer.pop()).collect();
+        assert_eq!(samples, vec![1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let (producer, consumer) = ring_buffer(4);
+        let mut expected = 0.0;
+
+        // Write and read in blocks which don't divide the capacity, so they wrap mid-block
+        for round in 0..10 {
+            let block: Vec<_> = (0..3).map(|i| (round * 3 + i) as SampleType).collect();
+            assert_eq!(producer.push_slice(&block), 3);
+            assert_eq!(producer.space(), 1);
+            for _ in 0..3 {
+                assert_eq!(consumer.pop(), Some(expected));
+                expected += 1.0;
+            }
+            assert!(consumer.is_empty());
+        }
+        assert_eq!(producer.watch().read_count(), 30);
+    }
+
+    #[test]
+    fn closes_when_either_side_is_dropped() {
+        let (producer, consumer) = ring_buffer(4);
+        let watcher = producer.watch();
+        assert!(!watcher.is_closed());
+        drop(consumer);
+        assert!(producer.is_closed());
+        assert!(watcher.is_closed());
+    }
+}
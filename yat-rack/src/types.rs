@@ -3,6 +3,7 @@ use std::fmt;
 // use std::sync::{Arc, RwLock};
 
 pub type SampleType = f64;
+/// The sample rate used until the audio device's rate is known
 pub const SAMPLE_RATE: SampleType = 96000f64;
 pub const AUDIO_BUF_SIZE: usize = 1024;
 // pub type IoPort = Arc<RwLock<Option<SampleType>>>;
@@ -0,0 +1,107 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::modules::io_module::IoModule;
+
+/// A module to be processed for a number of frames
+type Job = (Arc<Mutex<dyn IoModule + Send + Sync>>, usize);
+
+/// A pool of worker threads for processing the modules of a chain position in parallel.
+///
+/// Modules in the same position of a Rack's chain don't depend on each other, so processing
+/// them concurrently gives the same, bit-identical, results as processing them one by one.
+pub struct WorkerPool {
+    /// A job queue for each worker
+    job_queues: Vec<Sender<Job>>,
+
+    /// Receives a message for each finished job, which is false if the job panicked
+    done_rx: Receiver<bool>,
+
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Create a pool with the given number of worker threads
+    pub fn new(size: usize) -> Self {
+        let (done_tx, done_rx) = mpsc::channel();
+        let mut job_queues = Vec::with_capacity(size);
+        let mut workers = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let (job_tx, job_rx) = mpsc::channel::<Job>();
+            let done_tx = done_tx.clone();
+
+            workers.push(thread::spawn(move || {
+                for (module, frames) in job_rx {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        module
+                            .lock()
+                            .expect("Mutex lock is poisoned")
+                            .process_block(frames);
+                    }));
+
+                    if done_tx.send(result.is_ok()).is_err() {
+                        break;
+                    }
+                }
+            }));
+            job_queues.push(job_tx);
+        }
+
+        Self {
+            job_queues,
+            done_rx,
+            workers,
+        }
+    }
+
+    /// Returns the number of worker threads
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Process each of the modules for a number of frames, spread over the workers and the
+    /// calling thread. Returns once all of them have been processed.
+    pub fn process(&self, modules: &[Arc<Mutex<dyn IoModule + Send + Sync>>], frames: usize) {
+        // The calling thread would otherwise sit idle, so it takes a share of the modules too
+        let threads = self.job_queues.len() + 1;
+        let mut jobs = 0;
+        for (i, module) in modules.iter().enumerate() {
+            if let Some(job_queue) = self.job_queues.get(i % threads) {
+                job_queue
+                    .send((module.clone(), frames))
+                    .expect("Worker thread has stopped");
+                jobs += 1;
+            }
+        }
+
+        for module in modules.iter().skip(threads - 1).step_by(threads) {
+            module
+                .lock()
+                .expect("Mutex lock is poisoned")
+                .process_block(frames);
+        }
+
+        let mut panicked = false;
+        for _ in 0..jobs {
+            panicked |= !self.done_rx.recv().expect("Worker thread has stopped");
+        }
+
+        if panicked {
+            panic!("A module panicked while being processed by a worker thread");
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Closing the job queues lets the workers finish
+        self.job_queues.clear();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
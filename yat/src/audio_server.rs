@@ -1,72 +1,606 @@
 use std::error::Error;
-use std::sync::mpsc::Receiver;
+use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Sample, SampleFormat};
+use cpal::{Sample, SampleFormat, SupportedBufferSize};
 
-use yat_rack::types::{SampleType, SAMPLE_RATE};
+use yat_rack::ring_buffer::{Consumer, Producer};
+use yat_rack::types::SampleType;
 
 use std::thread;
 
-pub fn setup_audio_thread(audio_rx: Receiver<SampleType>) {
-    let _ = thread::spawn(move || {
-        let host = cpal::default_host();
-        let device = host.default_output_device().expect("no device available");
-
-        let channels = 2u16;
-        let sample_rate = cpal::SampleRate(SAMPLE_RATE as u32);
-        let suported_buffer_size = cpal::SupportedBufferSize::Unknown;
-        let sample_format = cpal::SampleFormat::F32;
-
-        let config = cpal::SupportedStreamConfig::new(
-            channels,
-            sample_rate,
-            suported_buffer_size,
-            sample_format
-        );
-
-        match config.sample_format() {
-            SampleFormat::F32 => run::<f32>(&device, &config.into(), audio_rx).unwrap(),
-            SampleFormat::I16 => run::<i16>(&device, &config.into(), audio_rx).unwrap(),
-            SampleFormat::U16 => run::<u16>(&device, &config.into(), audio_rx).unwrap(),
+/// Which output device to open, and how. Settings which are None use the defaults of the
+/// host or device
+#[derive(Debug, Clone, Default)]
+pub struct AudioConfig {
+    /// The audio API, e.g. "ALSA" or "JACK"
+    pub host: Option<String>,
+
+    pub device: Option<String>,
+
+    /// The number of frames per callback
+    pub buffer_size: Option<u32>,
+
+    pub sample_format: Option<SampleFormat>,
+
+    pub channels: Option<u16>,
+}
+
+impl AudioConfig {
+    /// Change one of the settings, given as text, e.g. from a command line option.
+    /// A value of "default" resets the setting
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let value = match value {
+            "default" => None,
+            value => Some(value),
+        };
+
+        match key {
+            "host" => self.host = value.map(String::from),
+            "device" => self.device = value.map(String::from),
+            "buffer_size" => self.buffer_size = value.map(str::parse::<u32>).transpose()?,
+            "format" => self.sample_format = value.map(parse_sample_format).transpose()?,
+            "channels" => self.channels = value.map(str::parse::<u16>).transpose()?,
+            _ => return Err(format!("Unknown audio setting: {}", key).into()),
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for AudioConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn or_default<T: fmt::Debug>(setting: &Option<T>) -> String {
+            setting
+                .as_ref()
+                .map_or(String::from("default"), |value| format!("{:?}", value))
+        }
+
+        write!(
+            f,
+            "host: {}, device: {}, buffer_size: {}, format: {}, channels: {}",
+            or_default(&self.host),
+            or_default(&self.device),
+            or_default(&self.buffer_size),
+            or_default(&self.sample_format),
+            or_default(&self.channels),
+        )
+    }
+}
+
+fn parse_sample_format(format: &str) -> Result<SampleFormat, Box<dyn Error>> {
+    match format.to_lowercase().as_str() {
+        "f32" => Ok(SampleFormat::F32),
+        "i16" => Ok(SampleFormat::I16),
+        "u16" => Ok(SampleFormat::U16),
+        _ => Err(format!("Unknown sample format: {}", format).into()),
+    }
+}
+
+/// List the output devices of every available host, along with their default configuration
+pub fn list_devices() -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for host_id in cpal::available_hosts() {
+        lines.push(format!("Host: {}", host_id.name()));
+
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(e) => {
+                lines.push(format!("    {}", e));
+                continue;
+            }
+        };
+        let devices = match host.output_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                lines.push(format!("    {}", e));
+                continue;
+            }
+        };
+
+        let default_name = host
+            .default_output_device()
+            .and_then(|device| device.name().ok());
+        for device in devices {
+            let name = device.name().unwrap_or_else(|_| String::from("(unnamed)"));
+            let default = if default_name.as_ref() == Some(&name) {
+                " (default)"
+            } else {
+                ""
+            };
+            lines.push(format!("    {}{}", name, default));
+
+            if let Ok(config) = device.default_output_config() {
+                lines.push(format!(
+                    "        {} channels, {} Hz, {:?}",
+                    config.channels(),
+                    config.sample_rate().0,
+                    config.sample_format()
+                ));
+            }
+        }
+    }
+
+    lines
+}
+
+/// Find a host by name, or the default host
+fn select_host(name: Option<&str>) -> Result<cpal::Host, Box<dyn Error>> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(cpal::default_host()),
+    };
+
+    let host_id = cpal::available_hosts()
+        .into_iter()
+        .find(|host_id| host_id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("No host named {}", name))?;
+
+    Ok(cpal::host_from_id(host_id)?)
+}
+
+/// Find an output device by name, or the host's default output device
+fn select_device(host: &cpal::Host, name: Option<&str>) -> Result<cpal::Device, Box<dyn Error>> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(host.default_output_device().ok_or("no device available")?),
+    };
+
+    let device = host
+        .output_devices()?
+        .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+        .ok_or_else(|| format!("No output device named {}", name))?;
+
+    Ok(device)
+}
+
+/// Pick a stream configuration supported by the device, based on its default configuration
+/// and the given settings. The device's default sample rate is kept if it's supported
+fn select_config(
+    device: &cpal::Device,
+    config: &AudioConfig,
+) -> Result<(cpal::StreamConfig, SampleFormat), Box<dyn Error>> {
+    let default = device.default_output_config()?;
+    let channels = config.channels.unwrap_or_else(|| default.channels());
+    let sample_format = config.sample_format.unwrap_or_else(|| default.sample_format());
+    let sample_rate = default.sample_rate();
+
+    let range = device
+        .supported_output_configs()?
+        .find(|range| range.channels() == channels && range.sample_format() == sample_format)
+        .ok_or_else(|| {
+            format!(
+                "The device doesn't support {} channels of {:?} samples",
+                channels, sample_format
+            )
+        })?;
+
+    let supported = if range.min_sample_rate() <= sample_rate && sample_rate <= range.max_sample_rate() {
+        range.with_sample_rate(sample_rate)
+    } else {
+        range.with_max_sample_rate()
+    };
+
+    let mut stream_config = supported.config();
+    if let Some(buffer_size) = config.buffer_size {
+        if let SupportedBufferSize::Range { min, max } = supported.buffer_size() {
+            if buffer_size < *min || buffer_size > *max {
+                return Err(format!("The buffer size must be between {} and {}", min, max).into());
+            }
+        }
+        stream_config.buffer_size = cpal::BufferSize::Fixed(buffer_size);
+    }
+
+    Ok((stream_config, sample_format))
+}
+
+/// The audio thread, once the output device has been opened. Dropping the server stops the
+/// stream, so that another device can be opened
+pub struct AudioServer {
+    /// The name of the output device
+    pub device_name: String,
+
+    /// The sample rate of the device's configuration, which the rack has to run at
+    pub sample_rate: SampleType,
+
+    /// The number of channels of the device
+    pub channels: usize,
+
+    /// The number of xruns, i.e., the times the audio device ran out of samples
+    pub xruns: Arc<AtomicU64>,
+
+    /// Hands the ring buffer, and the number of channels interleaved in it, to the audio thread.
+    /// The thread keeps the stream running until this is closed
+    play_tx: Option<mpsc::SyncSender<(Arc<Consumer>, usize)>>,
+
+    /// Receives whether the stream could be started
+    result_rx: mpsc::Receiver<Result<(), String>>,
+
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AudioServer {
+    /// Start playing the samples from the ring buffer, which holds frames of the given number
+    /// of channels. Ideally, this matches the device's channels
+    pub fn play(&self, audio_rx: Arc<Consumer>, channels: usize) -> Result<(), Box<dyn Error>> {
+        let stopped = "The audio thread has stopped";
+
+        self.play_tx
+            .as_ref()
+            .ok_or(stopped)?
+            .send((audio_rx, channels))
+            .map_err(|_| stopped)?;
+
+        self.result_rx.recv().map_err(|_| stopped)??;
+
+        Ok(())
+    }
+}
+
+impl Drop for AudioServer {
+    fn drop(&mut self) {
+        // Closing the channel stops the stream
+        self.play_tx.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Open an output device with the given configuration. The stream has to live on the audio
+/// thread, so the configuration is negotiated there and sent back. Errors of the running
+/// stream are written to the message queue, and its playback latency, in frames, to `latency`
+pub fn setup_audio_thread(
+    config: &AudioConfig,
+    latency: Arc<AtomicU64>,
+    msg_tx: Sender<String>,
+) -> Result<AudioServer, Box<dyn Error>> {
+    let xruns = Arc::new(AtomicU64::new(0));
+    let s_xruns = xruns.clone();
+    let config = config.clone();
+
+    let (config_tx, config_rx) = mpsc::sync_channel(1);
+    let (play_tx, play_rx) = mpsc::sync_channel::<(Arc<Consumer>, usize)>(1);
+    let (result_tx, result_rx) = mpsc::sync_channel(1);
+
+    let thread = thread::spawn(move || {
+        let opened = select_host(config.host.as_deref()).and_then(|host| {
+            let device = select_device(&host, config.device.as_deref())?;
+            let (stream_config, sample_format) = select_config(&device, &config)?;
+
+            Ok((device, stream_config, sample_format))
+        });
+
+        let (device, stream_config, sample_format) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                let _ = config_tx.send(Err(e.to_string()));
+                return;
+            }
+        };
+        let _ = config_tx.send(Ok((
+            device.name().unwrap_or_default(),
+            stream_config.sample_rate.0 as SampleType,
+            stream_config.channels as usize,
+        )));
+
+        // Wait for the rack's output
+        let (audio_rx, rack_channels) = match play_rx.recv() {
+            Ok(output) => output,
+            Err(_) => return,
+        };
+
+        let output = StreamOutput {
+            audio_rx,
+            rack_channels,
+            xruns: s_xruns,
+            latency,
+        };
+        let stream = match sample_format {
+            SampleFormat::F32 => run::<f32>(&device, &stream_config, output, msg_tx),
+            SampleFormat::I16 => run::<i16>(&device, &stream_config, output, msg_tx),
+            SampleFormat::U16 => run::<u16>(&device, &stream_config, output, msg_tx),
+        };
+
+        match stream {
+            Ok(_stream) => {
+                let _ = result_tx.send(Ok(()));
+
+                // Keep the stream alive until the server is dropped
+                while play_rx.recv().is_ok() {}
+            }
+            Err(e) => {
+                let _ = result_tx.send(Err(e.to_string()));
+            }
         }
     });
+
+    let (device_name, sample_rate, channels) = config_rx.recv()??;
+
+    Ok(AudioServer {
+        device_name,
+        sample_rate,
+        channels,
+        xruns,
+        play_tx: Some(play_tx),
+        result_rx,
+        thread: Some(thread),
+    })
+}
+
+/// Where an output stream takes its samples from, and what it reports back
+struct StreamOutput {
+    /// The rack's output
+    audio_rx: Arc<Consumer>,
+
+    /// The number of channels interleaved in `audio_rx`
+    rack_channels: usize,
+
+    xruns: Arc<AtomicU64>,
+
+    /// The frames queued in `audio_rx`, plus the time from the callback to playing its samples
+    latency: Arc<AtomicU64>,
 }
 
 // Build output stream and play audio
 fn run<T: Sample>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
-    audio_rx: Receiver<SampleType>,
-) -> Result<(), Box<dyn Error>> {
+    output: StreamOutput,
+    msg_tx: Sender<String>,
+) -> Result<cpal::Stream, Box<dyn Error>> {
+    let StreamOutput {
+        audio_rx,
+        rack_channels,
+        xruns,
+        latency,
+    } = output;
     let channels = config.channels as usize;
+    let rack_channels = rack_channels.max(1);
+    let sample_rate = config.sample_rate.0 as SampleType;
+
+    // One frame of the rack's output
+    let mut rack_frame = vec![0f32; rack_channels];
+
+    // Silence before the first samples arrive isn't an xrun
+    let mut in_xrun = true;
 
-    let err_fn = |err| eprintln!("an error occurred on the stream: {}", err);
+    let err_fn = move |err| {
+        let _ = msg_tx.send(format!("An error occurred on the output stream: {}", err));
+    };
 
     // Build an output stream
     let stream = device.build_output_stream(
         config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(delay) = timestamp.playback.duration_since(&timestamp.callback) {
+                let queued = audio_rx.len() / rack_channels;
+                let frames = (delay.as_secs_f64() * sample_rate) as usize + queued;
+                latency.store(frames as u64, Relaxed);
+            }
+
+            let mut underrun = false;
             for frame in data.chunks_mut(channels) {
-                // NOTE: Converting from the rack's 64-bit floats to 32-bit for samples
-                let next_sample: f32 = match audio_rx.recv() {
-                    Ok(sample) => sample as f32,
-                    Err(_) => break,
-                };
-                let value: T = cpal::Sample::from::<f32>(&next_sample);
-
-                for sample in frame.iter_mut() {
-                    *sample = value;
+                // Only take whole frames, so that the channels stay aligned
+                if audio_rx.len() >= rack_channels {
+                    for sample in rack_frame.iter_mut() {
+                        // NOTE: Converting from the rack's 64-bit floats to 32-bit for samples
+                        *sample = audio_rx.pop().unwrap_or(0.0) as f32;
+                    }
+                } else {
+                    underrun = true;
+                    rack_frame.fill(0.0);
                 }
+
+                // A mono rack plays on every channel. Otherwise each channel plays its own
+                // signal, and device channels which the rack doesn't have stay silent
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let value = match rack_frame.get(channel) {
+                        Some(value) => *value,
+                        None if rack_channels == 1 => rack_frame[0],
+                        None => 0.0,
+                    };
+                    *sample = cpal::Sample::from::<f32>(&value);
+                }
+            }
+
+            // A gap in the stream, e.g. while the rack is stopped, only counts once
+            if underrun && !in_xrun {
+                xruns.fetch_add(1, Relaxed);
             }
+            in_xrun = underrun;
         },
         err_fn,
     )?;
 
     stream.play()?;
 
-    std::thread::park();
+    Ok(stream)
+}
+
+/// The capture thread, once the input device has been opened. Dropping the server stops the
+/// stream, and waits for the thread to end
+pub struct InputServer {
+    /// The sample rate of the device's configuration
+    pub sample_rate: SampleType,
+
+    /// The number of channels of the device
+    pub channels: usize,
+
+    /// The number of overruns, i.e., the times captured samples were dropped because the
+    /// rack didn't read them in time
+    pub overruns: Arc<AtomicU64>,
+
+    /// Hands the ring buffer to the capture thread. The thread keeps the stream running until
+    /// this is closed
+    audio_tx: Option<mpsc::SyncSender<Producer>>,
+
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InputServer {
+    /// Start capturing samples into the ring buffer, which holds frames of the device's
+    /// channels
+    pub fn record(&self, audio_tx: Producer) {
+        if let Some(tx) = &self.audio_tx {
+            // The capture thread only stops if it failed to build the stream
+            let _ = tx.send(audio_tx);
+        }
+    }
+}
+
+impl Drop for InputServer {
+    fn drop(&mut self) {
+        // Closing the channel stops the stream
+        self.audio_tx.take();
 
-    Ok(())
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
 }
 
+/// Open the default input device, using the device's default configuration. Like the output,
+/// the stream lives on its own thread, its errors are written to the message queue, and its
+/// capture latency, in frames, to `latency`
+pub fn setup_input_thread(
+    latency: Arc<AtomicU64>,
+    msg_tx: Sender<String>,
+) -> Result<InputServer, Box<dyn Error>> {
+    let overruns = Arc::new(AtomicU64::new(0));
+    let s_overruns = overruns.clone();
+
+    let (config_tx, config_rx) = mpsc::sync_channel(1);
+    let (audio_tx, audio_rx) = mpsc::sync_channel::<Producer>(1);
+
+    let thread = thread::spawn(move || {
+        let host = cpal::default_host();
+        let device = match host.default_input_device() {
+            Some(device) => device,
+            None => {
+                let _ = config_tx.send(Err(String::from("no device available")));
+                return;
+            }
+        };
+
+        let config = match device.default_input_config() {
+            Ok(config) => config,
+            Err(e) => {
+                let _ = config_tx.send(Err(e.to_string()));
+                return;
+            }
+        };
+        let _ = config_tx.send(Ok((
+            config.sample_rate().0 as SampleType,
+            config.channels() as usize,
+        )));
+
+        // Wait for the rack's input
+        let producer = match audio_rx.recv() {
+            Ok(input) => input,
+            Err(_) => return,
+        };
+
+        let input = StreamInput {
+            audio_tx: producer,
+            overruns: s_overruns,
+            latency,
+        };
+        let e_msg_tx = msg_tx.clone();
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => capture::<f32>(&device, &config.into(), input, msg_tx),
+            SampleFormat::I16 => capture::<i16>(&device, &config.into(), input, msg_tx),
+            SampleFormat::U16 => capture::<u16>(&device, &config.into(), input, msg_tx),
+        };
+
+        match stream {
+            // Keep the stream alive until the server is dropped
+            Ok(_stream) => while audio_rx.recv().is_ok() {},
+            Err(e) => {
+                let _ = e_msg_tx.send(format!("No audio input: {}", e));
+            }
+        }
+    });
+
+    let (sample_rate, channels) = config_rx.recv()??;
+
+    Ok(InputServer {
+        sample_rate,
+        channels,
+        overruns,
+        audio_tx: Some(audio_tx),
+        thread: Some(thread),
+    })
+}
+
+/// Where an input stream writes its samples to, and what it reports back
+struct StreamInput {
+    /// The rack's input
+    audio_tx: Producer,
+
+    overruns: Arc<AtomicU64>,
+
+    /// The time from capturing samples to their callback
+    latency: Arc<AtomicU64>,
+}
+
+// Build input stream and capture audio
+fn capture<T: Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    input: StreamInput,
+    msg_tx: Sender<String>,
+) -> Result<cpal::Stream, Box<dyn Error>> {
+    let StreamInput {
+        audio_tx,
+        overruns,
+        latency,
+    } = input;
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0 as SampleType;
+
+    let err_fn = move |err| {
+        let _ = msg_tx.send(format!("An error occurred on the input stream: {}", err));
+    };
+
+    // Build an input stream
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], info: &cpal::InputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(delay) = timestamp.callback.duration_since(&timestamp.capture) {
+                latency.store((delay.as_secs_f64() * sample_rate) as u64, Relaxed);
+            }
+
+            let mut overrun = false;
+            for frame in data.chunks(channels) {
+                // Only write whole frames, so that the channels stay aligned
+                if audio_tx.space() < frame.len() {
+                    overrun = true;
+                    continue;
+                }
+
+                for sample in frame {
+                    audio_tx.push(sample.to_f32() as SampleType);
+                }
+            }
+
+            if overrun {
+                overruns.fetch_add(1, Relaxed);
+            }
+        },
+        err_fn,
+    )?;
+
+    stream.play()?;
+
+    Ok(stream)
+}
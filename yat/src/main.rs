@@ -1,8 +1,11 @@
 use midir::{MidiInput, Ignore};
 
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
 use crossterm::event::KeyEventKind;
 use crossterm::{
@@ -25,12 +28,75 @@ use tui::{
 
 use unicode_width::UnicodeWidthStr;
 
+use yat_rack::command::Command as RackCommand;
+use yat_rack::engine::Engine;
+use yat_rack::event::Event as RackEvent;
+use yat_rack::event_server::EventServer;
+use yat_rack::modules::input::{DeviceLatency, Input};
 use yat_rack::modules::output::Output;
+use yat_rack::osc::OscListener;
 use yat_rack::rack::Rack;
+use yat_rack::recorder::{Recorder, Tap};
+use yat_rack::ring_buffer::Consumer;
+
+use audio_server::{AudioConfig, AudioServer, InputServer};
 
 mod audio_server;
+mod render;
 
 fn main() -> Result<(), io::Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // Render a patch to a file, rather than running the TUI
+    if args.first().is_some_and(|arg| arg == "render") {
+        match render::render(&args[1..]) {
+            Ok(msg) => println!("{}", msg),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    // Audio settings are given as options, e.g. "--device NAME" or "--buffer-size 256". The
+    // event server is started with "--listen PATH" or "--listen-tcp 127.0.0.1:PORT", and the
+    // OSC listener with "--osc ADDRESS:PORT". Plugins are loaded from "--plugins DIR"
+    let mut audio_config = AudioConfig::default();
+    let mut listen_socket = None;
+    let mut listen_tcp = None;
+    let mut listen_osc = None;
+    let mut plugin_dir = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let key = arg.trim_start_matches("--").replace('-', "_");
+        let result = match (key.as_str(), args.next()) {
+            ("listen", Some(value)) => {
+                listen_socket = Some(value);
+                Ok(())
+            }
+            ("listen_tcp", Some(value)) => {
+                listen_tcp = Some(value);
+                Ok(())
+            }
+            ("osc", Some(value)) => {
+                listen_osc = Some(value);
+                Ok(())
+            }
+            ("plugins", Some(value)) => {
+                plugin_dir = Some(value);
+                Ok(())
+            }
+            (_, Some(value)) => audio_config.set(&key, &value),
+            (_, None) => Err(format!("No value given for {}", arg).into()),
+        };
+
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            return Ok(());
+        }
+    }
+
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -51,7 +117,14 @@ fn main() -> Result<(), io::Error> {
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
-    let app = App::default();
+    let app = App {
+        audio_config,
+        listen_socket,
+        listen_tcp,
+        listen_osc,
+        plugin_dir,
+        ..App::default()
+    };
     let res = app.run_app(&mut terminal);
 
     // restore terminal
@@ -90,16 +163,68 @@ pub struct App {
     commands: Vec<String>,
     /// History of recorded messages
     messages: Vec<String>,
+    /// Queue of messages from other threads, e.g. audio stream errors
+    msg_tx: mpsc::Sender<String>,
+    msg_rx: mpsc::Receiver<String>,
+    /// Settings for opening the audio device
+    audio_config: AudioConfig,
+    /// The running audio output, if a device could be opened
+    audio_server: Option<AudioServer>,
+    /// The reading side of the audio_out module's ring buffer
+    audio_rx: Option<Arc<Consumer>>,
+    /// The number of channels of the audio_out module
+    out_channels: usize,
+    /// A copy of the frames sent by the audio_out module
+    out_tap: Tap,
+    /// The running recording of the audio_out module, if any
+    recorder: Option<Recorder>,
+    /// The number of times the audio device ran out of samples
+    xruns: Arc<AtomicU64>,
+    /// The number of times captured audio was dropped
+    overruns: Arc<AtomicU64>,
+    /// The running audio input, if a capture device could be opened at the rack's rate
+    input_server: Option<InputServer>,
+    /// The audio_in module, once a capture device was opened
+    audio_in: Option<Arc<Mutex<Input>>>,
+    /// The latencies of the capture and playback devices, as measured by their streams
+    device_latency: DeviceLatency,
+    /// The path of the event server's Unix socket, if it should listen on one
+    listen_socket: Option<String>,
+    /// The localhost address of the event server's TCP port, if it should listen on one
+    listen_tcp: Option<String>,
+    /// The UDP address to listen for OSC messages on, if any
+    listen_osc: Option<String>,
+    /// The directory to load plugin libraries from at startup, if any
+    plugin_dir: Option<String>,
 }
 
 impl Default for App {
     fn default() -> App {
+        let (msg_tx, msg_rx) = mpsc::channel();
+
         App {
             rack: Arc::new(Mutex::new(Rack::new())),
             input: String::new(),
             input_mode: InputMode::Normal,
             commands: Vec::new(),
             messages: Vec::new(),
+            msg_tx,
+            msg_rx,
+            audio_config: AudioConfig::default(),
+            audio_server: None,
+            audio_rx: None,
+            out_channels: 2,
+            out_tap: Tap::default(),
+            recorder: None,
+            xruns: Arc::new(AtomicU64::new(0)),
+            overruns: Arc::new(AtomicU64::new(0)),
+            input_server: None,
+            audio_in: None,
+            device_latency: DeviceLatency::default(),
+            listen_socket: None,
+            listen_tcp: None,
+            listen_osc: None,
+            plugin_dir: None,
         }
     }
 }
@@ -107,8 +232,17 @@ impl Default for App {
 impl App {
     pub fn run_app<B: Backend>(mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
         // setup audio and interface
-        let (audio_out, audio_rx) = Output::new(String::from("audio_out"));
         let rack = self.rack.clone();
+        self.load_plugins();
+        self.start_audio();
+
+        // Give the output a channel for each of the device's, or stereo without a device
+        if let Some(audio_server) = &self.audio_server {
+            self.out_channels = audio_server.channels;
+        }
+        let (audio_out, audio_rx) = Output::new(String::from("audio_out"), self.out_channels);
+        self.out_tap = audio_out.get_tap();
+        let out_pacer = audio_out.get_pacer();
 
         // Add the audio_out module by defualt
         // TODO: Make sure there is only one of these for now
@@ -116,31 +250,36 @@ impl App {
             .unwrap()
             .add_module(Arc::new(Mutex::new(audio_out))).unwrap();
 
-        audio_server::setup_audio_thread(audio_rx);
+        self.audio_rx = Some(Arc::new(audio_rx));
+        self.play_audio();
+
         // TODO handle errors and allow selection of interface (config screen??)
         self.setup_midi_thread().unwrap();
 
         let c_rack_ref = Arc::clone(&rack);
-        let s_rack_ref = Arc::clone(&rack);
 
-        let (quit_tx, quit_rx) = mpsc::sync_channel(1);
+        // Responses to commands are shown in the messages pane
+        let (response_tx, response_rx) = mpsc::channel();
+        rack.lock().unwrap().set_msg_queue(response_tx);
+
+        let (mut engine, event_tx) = Engine::new(Arc::clone(&rack));
+        engine.pace_by(out_pacer);
+        let _event_servers = self.start_event_servers(&event_tx);
+        let _osc_listener = self.start_osc_listener(&event_tx);
         thread::scope(|c_scope| {
-            c_scope.spawn(move || {
-                // TODO: Use std::sync::Convar to actually block CPU
-                loop {
-                    while *s_rack_ref.lock().unwrap().running.get_mut() {
-                            s_rack_ref.lock().unwrap().process_module_chain();
-                    }
-                    match quit_rx.try_recv() {
-                        Ok(_) => break,
-                        Err(_) => continue,
-                    }
-                }
-            });
+            c_scope.spawn(move || engine.run());
 
             loop {
+                self.messages.extend(self.msg_rx.try_iter());
+                self.messages
+                    .extend(response_rx.try_iter().map(|response| response.to_string()));
                 terminal.draw(|f| self.ui(f))?;
 
+                // Wake up regularly, so that new messages are drawn
+                if !event::poll(Duration::from_millis(100))? {
+                    continue;
+                }
+
                 if let Event::Key(key) = event::read()? {
                     match self.input_mode {
                         InputMode::Normal => match key.code {
@@ -152,8 +291,7 @@ impl App {
                             }
                             KeyCode::Char('q') => {
                                 self.messages.push("Quiting...\n".into());
-                                c_scope.spawn(|| c_rack_ref.lock().unwrap().stop());
-                                quit_tx.send(true).unwrap();
+                                let _ = event_tx.send(RackEvent::Command(RackCommand::Quit));
                                 return Ok::<(), io::Error>(());
                             }
                             _ => {}
@@ -194,13 +332,32 @@ impl App {
                                     KeyCode::Enter => {
                                         self.commands.push(self.input.drain(..).collect());
 
-                                        if self.commands.last().unwrap() == "clear messages" {
+                                        let command = self.commands.last().unwrap().clone();
+                                        if command == "clear messages" {
                                             self.messages.clear();
-                                        } else if self.commands.last().unwrap() == "quit" {
+                                        } else if command == "audio"
+                                            || command.starts_with("audio ")
+                                        {
+                                            self.audio_command(&command["audio".len()..]);
+                                        } else if command == "record"
+                                            || command.starts_with("record ")
+                                        {
+                                            self.record_command(&command["record".len()..]);
+                                        } else if command == "quit" {
                                             self.messages.push("Quiting...\n".into());
-                                            c_scope.spawn(|| c_rack_ref.lock().unwrap().stop());
-                                            quit_tx.send(true).unwrap();
+                                            let _ = event_tx
+                                                .send(RackEvent::Command(RackCommand::Quit));
                                             return Ok(());
+                                        } else if !command.trim().is_empty() {
+                                            // Rack commands are applied by the engine, which
+                                            // writes their responses to the messages
+                                            match command.parse::<RackCommand>() {
+                                                Ok(command) => {
+                                                    let _ = event_tx
+                                                        .send(RackEvent::Command(command));
+                                                }
+                                                Err(e) => self.messages.push(e.to_string()),
+                                            }
                                         }
                                     }
                                     KeyCode::Char(c) => {
@@ -262,7 +419,7 @@ impl App {
                     Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
                     Span::raw(" to stop editing, "),
                     Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" to record the message"),
+                    Span::raw(" to run the command"),
                 ],
                 Style::default(),
             ),
@@ -356,10 +513,287 @@ impl App {
         // Bottom right block with styled left and right border
         let module_list = Paragraph::new(modules)
             .style(Style::default())
-            .block(Block::default().borders(Borders::ALL).title("Modules"));
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(
+                        "Modules (xruns: {}, input overruns: {})",
+                        self.xruns.load(Relaxed),
+                        self.overruns.load(Relaxed)
+                    )),
+            );
         f.render_widget(module_list, bottom_chunks[2]);
     }
 
+    /// Handle an audio command:
+    /// * "audio" shows the current settings
+    /// * "audio devices" lists the available devices
+    /// * "audio restart" reopens the device
+    /// * "audio <setting> <value>" changes a setting and reopens the device. The settings are
+    ///   host, device, buffer_size, format and channels, and a value of "default" resets them
+    fn audio_command(&mut self, args: &str) {
+        let args = args.trim();
+        let (key, value) = match args.split_once(' ') {
+            Some((key, value)) => (key, value.trim()),
+            None => (args, ""),
+        };
+
+        match key {
+            "" => {
+                self.messages.push(format!("Audio settings: {}", self.audio_config));
+                match &self.audio_server {
+                    Some(audio_server) => self.messages.push(format!(
+                        "Playing on {}: {} Hz, {} channels",
+                        audio_server.device_name, audio_server.sample_rate, audio_server.channels
+                    )),
+                    None => self.messages.push(String::from("No audio output")),
+                }
+                if let (Some(audio_in), Some(_)) = (&self.audio_in, &self.input_server) {
+                    let (capture, playback) = self.device_latency.get_frames();
+                    let audio_in = audio_in.lock().unwrap();
+                    self.messages.push(format!(
+                        "Input latency: {} frames ({} capturing, {} buffered, {} playing)",
+                        audio_in.get_latency(),
+                        capture,
+                        audio_in.get_buffered_frames(),
+                        playback
+                    ));
+                }
+            }
+            "devices" => self.messages.extend(audio_server::list_devices()),
+            "restart" => self.start_audio(),
+            _ => match self.audio_config.set(key, value) {
+                Ok(()) => self.start_audio(),
+                Err(e) => self.messages.push(e.to_string()),
+            },
+        }
+    }
+
+    /// Handle a recording command:
+    /// * "record start <file>" records the audio_out module's signal to a WAV file
+    /// * "record stop" stops the recording and finishes the file
+    /// * "record" shows whether a recording is running
+    fn record_command(&mut self, args: &str) {
+        let args = args.trim();
+        let (action, file) = match args.split_once(' ') {
+            Some((action, file)) => (action, file.trim()),
+            None => (args, ""),
+        };
+
+        match action {
+            "start" if file.is_empty() => self.messages.push(String::from("No file given")),
+            "start" => {
+                // Finish any running recording first, as only one can run at a time
+                if let Some(recorder) = self.recorder.take() {
+                    self.stop_recording(recorder);
+                }
+
+                let sample_rate = self.rack.lock().unwrap().get_sample_rate();
+                match Recorder::start(&self.out_tap, self.out_channels, sample_rate, file) {
+                    Ok(recorder) => {
+                        self.messages.push(format!("Recording to {}", file));
+                        self.recorder = Some(recorder);
+                    }
+                    Err(e) => self.messages.push(format!("Can't record to {}: {}", file, e)),
+                }
+            }
+            "stop" => match self.recorder.take() {
+                Some(recorder) => self.stop_recording(recorder),
+                None => self.messages.push(String::from("Not recording")),
+            },
+            "" => match &self.recorder {
+                Some(recorder) => self
+                    .messages
+                    .push(format!("Recording to {}", recorder.get_path().display())),
+                None => self.messages.push(String::from("Not recording")),
+            },
+            _ => self.messages.push(format!("Unknown record command: {}", action)),
+        }
+    }
+
+    fn stop_recording(&mut self, recorder: Recorder) {
+        match recorder.stop() {
+            Ok(msg) => self.messages.push(msg),
+            Err(e) => self.messages.push(format!("Recording failed: {}", e)),
+        }
+    }
+
+    /// (Re)open the audio device with the current settings. The rack keeps running, and its
+    /// sample rate follows the device's
+    fn start_audio(&mut self) {
+        // Only one stream may read from the output at a time, so the current one is stopped first
+        self.audio_server = None;
+
+        let latency = self.device_latency.playback.clone();
+        match audio_server::setup_audio_thread(&self.audio_config, latency, self.msg_tx.clone()) {
+            Ok(audio_server) => {
+                self.xruns = audio_server.xruns.clone();
+
+                let mut rack = self.rack.lock().unwrap();
+                if rack.get_sample_rate() != audio_server.sample_rate {
+                    match rack.set_sample_rate(audio_server.sample_rate) {
+                        Ok(msg) => self.messages.push(msg),
+                        Err(e) => self.messages.push(e.to_string()),
+                    }
+                }
+                drop(rack);
+
+                self.audio_server = Some(audio_server);
+                self.play_audio();
+            }
+            Err(e) => self.messages.push(format!("No audio output: {}", e)),
+        }
+
+        // The rate may have changed, so the input has to be checked again
+        self.start_input();
+    }
+
+    /// (Re)open the capture device for the audio_in module, which is added the first time.
+    /// Input isn't resampled, so the device has to run at the rack's rate, otherwise the
+    /// module stays silent
+    fn start_input(&mut self) {
+        // Only one stream may write to the input at a time, so the current one is stopped first
+        self.input_server = None;
+
+        let latency = self.device_latency.capture.clone();
+        let input_server = match audio_server::setup_input_thread(latency, self.msg_tx.clone()) {
+            Ok(input_server) => input_server,
+            Err(e) => {
+                self.messages.push(format!("No audio input: {}", e));
+                return;
+            }
+        };
+
+        let sample_rate = self.rack.lock().unwrap().get_sample_rate();
+        if input_server.sample_rate != sample_rate {
+            self.messages.push(format!(
+                "No audio input: the device runs at {}, but the rack at {}",
+                input_server.sample_rate, sample_rate
+            ));
+            return;
+        }
+
+        let audio_tx = match &self.audio_in {
+            Some(audio_in) => {
+                let mut audio_in = audio_in.lock().unwrap();
+                if audio_in.get_channels() != input_server.channels {
+                    let channels = audio_in.get_channels();
+                    drop(audio_in);
+                    self.messages.push(format!(
+                        "No audio input: the device has {} channels, but audio_in {}",
+                        input_server.channels, channels
+                    ));
+                    return;
+                }
+                audio_in.reopen()
+            }
+            None => {
+                let (mut audio_in, audio_tx) =
+                    Input::new(String::from("audio_in"), input_server.channels);
+                audio_in.set_device_latency(self.device_latency.clone());
+
+                let audio_in = Arc::new(Mutex::new(audio_in));
+                if let Err(e) = self.rack.lock().unwrap().add_module(audio_in.clone()) {
+                    self.messages.push(format!("No audio input: {}", e));
+                    return;
+                }
+                self.audio_in = Some(audio_in);
+                audio_tx
+            }
+        };
+
+        self.overruns = input_server.overruns.clone();
+        input_server.record(audio_tx);
+        self.input_server = Some(input_server);
+    }
+
+    /// Play the audio_out module's signal on the audio device, once both exist
+    fn play_audio(&mut self) {
+        let (audio_server, audio_rx) = match (&self.audio_server, &self.audio_rx) {
+            (Some(audio_server), Some(audio_rx)) => (audio_server, audio_rx.clone()),
+            _ => return,
+        };
+
+        match audio_server.play(audio_rx, self.out_channels) {
+            Ok(()) => self
+                .messages
+                .push(format!("Playing on {}", audio_server.device_name)),
+            Err(e) => {
+                self.messages.push(format!("No audio output: {}", e));
+                self.audio_server = None;
+            }
+        }
+    }
+
+    /// Start the event servers given on the command line, so that other programs can drive
+    /// the rack. Dropping them stops them from accepting new clients
+    fn start_event_servers(&mut self, event_tx: &mpsc::Sender<RackEvent>) -> Vec<EventServer> {
+        let mut servers = Vec::new();
+
+        if let Some(path) = self.listen_socket.clone() {
+            #[cfg(unix)]
+            let result = EventServer::bind_unix(&path, self.rack.clone(), event_tx.clone());
+            #[cfg(not(unix))]
+            let result = Err("Unix sockets aren't supported on this platform".into());
+
+            match result {
+                Ok(server) => servers.push(server),
+                Err(e) => self.messages.push(format!("Can't listen on {}: {}", path, e)),
+            }
+        }
+
+        if let Some(address) = self.listen_tcp.clone() {
+            match EventServer::bind_tcp(&address, self.rack.clone(), event_tx.clone()) {
+                Ok(server) => servers.push(server),
+                Err(e) => self.messages.push(format!("Can't listen on {}: {}", address, e)),
+            }
+        }
+
+        for server in &servers {
+            self.messages
+                .push(format!("Listening for commands on {}", server.get_address()));
+        }
+
+        servers
+    }
+
+    /// Load the plugin libraries in the directory given on the command line, if any
+    fn load_plugins(&mut self) {
+        let Some(dir) = self.plugin_dir.clone() else {
+            return;
+        };
+
+        match self.rack.lock().unwrap().load_plugin_dir(&dir) {
+            Ok(msg) => self.messages.push(msg),
+            Err(e) => self.messages.push(format!("Can't load plugins: {}", e)),
+        }
+    }
+
+    /// Start listening for OSC messages, if an address was given on the command line. It stops
+    /// once dropped
+    fn start_osc_listener(&mut self, event_tx: &mpsc::Sender<RackEvent>) -> Option<OscListener> {
+        let address = self.listen_osc.clone()?;
+        let result = OscListener::bind(
+            &address,
+            self.rack.clone(),
+            event_tx.clone(),
+            self.msg_tx.clone(),
+        );
+
+        match result {
+            Ok(listener) => {
+                self.messages
+                    .push(format!("Listening for OSC on {}", listener.get_address()));
+                Some(listener)
+            }
+            Err(e) => {
+                self.messages
+                    .push(format!("Can't listen for OSC on {}: {}", address, e));
+                None
+            }
+        }
+    }
+
     fn setup_midi_thread(&self) -> Result<(), Box<dyn Error>> {
         let midi_rack = self.rack.clone();
         thread::spawn(move || {
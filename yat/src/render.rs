@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use yat_rack::modules::output::Output;
+use yat_rack::patch::Patch;
+use yat_rack::rack::Rack;
+use yat_rack::render::{self, RenderSettings};
+
+/// Render a patch to a WAV file, without an audio device:
+///
+/// `yat render <patch> -o <file> [--seconds N] [--sample-rate R] [--bits B] [--float] [--channels C]`
+///
+/// A patch ending in `.toml` is a patch file, as saved by the TUI's `save` command, and is
+/// loaded like with `load`, see `Rack::apply_patch`. Any other patch is a script of rack
+/// commands, one per line, as applied by `Rack::run_script`. Either way, like in the TUI, an
+/// Output module with the ID "audio_out" is added first, so the patch can connect to it.
+pub fn render(args: &[String]) -> Result<String, Box<dyn Error>> {
+    let mut patch = None;
+    let mut out = None;
+    let mut settings = RenderSettings::default();
+    let mut bits = None;
+    let mut channels = 2;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("No value given for {}", arg));
+
+        match arg.as_str() {
+            "-o" | "--output" => out = Some(value()?.clone()),
+            "--seconds" => settings.seconds = value()?.parse()?,
+            "--sample-rate" => settings.sample_rate = value()?.parse()?,
+            "--bits" => bits = Some(value()?.parse()?),
+            "--float" => settings.float = true,
+            "--channels" => channels = value()?.parse()?,
+            _ if patch.is_none() && !arg.starts_with('-') => patch = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg).into()),
+        }
+    }
+
+    let patch = patch.ok_or("No patch given")?;
+    let out = out.ok_or("No output file given, use -o <file>")?;
+
+    // Float samples only come in 32 bits
+    settings.bits_per_sample = bits.unwrap_or(if settings.float { 32 } else { 16 });
+
+    let mut rack = Rack::new();
+    let (audio_out, audio_rx) = Output::new(String::from("audio_out"), channels);
+    rack.add_module(Arc::new(Mutex::new(audio_out)))?;
+
+    let is_patch_file = Path::new(&patch)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+    if is_patch_file {
+        rack.apply_patch(&Patch::read(&patch)?)?;
+    } else {
+        let script = fs::read_to_string(&patch).map_err(|e| format!("{}: {}", patch, e))?;
+        rack.run_script(&script)?;
+    }
+
+    render::render_to_wav(&mut rack, &audio_rx, channels, out, &settings)
+}